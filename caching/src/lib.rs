@@ -1,8 +1,14 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+pub mod service;
+
+use normalization::{Deserializable, Serializable};
+use once_cell::sync::Lazy;
+use rpc::reflection::{Schema, ServiceDescription};
+use rpc::{schema, ProcedureId, Procedures};
 
 pub const SYSTEM_NAME: &str = "caching";
-pub const SYSTEM_ADDRESS: &str = "127.0.0.1:10700";
+pub static SYSTEM_ADDRESS: Lazy<String> = Lazy::new(|| {
+    std::env::var("CACHING_ADDR").unwrap_or_else(|_| "127.0.0.1:10700".to_string())
+});
 
 pub const GET_PROCEDURE: ProcedureId = 1;
 pub const SET_PROCEDURE: ProcedureId = 2;
@@ -12,6 +18,17 @@ pub const REPLICATE_SET_PROCEDURE: ProcedureId = 5;
 pub const REPLICATE_DELETE_PROCEDURE: ProcedureId = 6;
 pub const MODE_PROCEDURE: ProcedureId = 7;
 
+// Replication carries versions, so a repeated one is a no-op, and setting a
+// mode twice leaves it set. A set or delete retried late could undo a newer
+// write, so those are sent once.
+pub const PROCEDURES: Procedures = Procedures::new(&[
+    GET_PROCEDURE,
+    STATS_PROCEDURE,
+    REPLICATE_SET_PROCEDURE,
+    REPLICATE_DELETE_PROCEDURE,
+    MODE_PROCEDURE,
+]);
+
 #[derive(Debug, Serializable, Deserializable)]
 pub struct GetArgs {
     pub key: String,
@@ -74,49 +91,63 @@ pub struct ModeResult {
 // Client helpers
 use rpc::{client, Request};
 
+pub async fn get(addr: &str, key: String) -> GetResult {
+    let args = GetArgs { key };
+    let request = Request::new(GET_PROCEDURE, args.serialize());
+    match client::send_request(addr, request).await {
+        Ok(response) => GetResult::deserialize(&response.payload).unwrap_or(GetResult {
+            value: String::new(),
+            hit: 0,
+        }),
+        Err(_) => GetResult {
+            value: String::new(),
+            hit: 0,
+        },
+    }
+}
+
+pub async fn set(addr: &str, key: String, value: String, ttl_secs: i32) -> Result<(), rpc::Error> {
+    let args = SetArgs {
+        key,
+        value,
+        ttl_secs,
+    };
+    let request = Request::new(SET_PROCEDURE, args.serialize());
+    client::send_request(addr, request).await.map(|_| ())
+}
+
 pub async fn replicate_set(
     addr: &str,
     key: String,
     value: String,
     ttl_secs: i32,
     version: i32,
-) -> String {
+) -> Result<(), rpc::Error> {
     let args = ReplicateSetArgs {
         key,
         value,
         ttl_secs,
         version,
     };
-    let request = Request {
-        procedure_id: REPLICATE_SET_PROCEDURE,
-        payload: args.serialize(),
-    };
-    match client::send_request(addr, request).await {
-        Ok(response) => response.payload,
-        Err(e) => format!("ERROR: {}", e),
-    }
+    let request = Request::new(REPLICATE_SET_PROCEDURE, args.serialize());
+    client::send_request(addr, request).await.map(|_| ())
 }
 
-pub async fn replicate_delete_remote(addr: &str, key: String, version: i32) -> String {
+pub async fn replicate_delete_remote(
+    addr: &str,
+    key: String,
+    version: i32,
+) -> Result<(), rpc::Error> {
     let args = ReplicateDeleteArgs { key, version };
-    let request = Request {
-        procedure_id: REPLICATE_DELETE_PROCEDURE,
-        payload: args.serialize(),
-    };
-    match client::send_request(addr, request).await {
-        Ok(response) => response.payload,
-        Err(e) => format!("ERROR: {}", e),
-    }
+    let request = Request::new(REPLICATE_DELETE_PROCEDURE, args.serialize());
+    client::send_request(addr, request).await.map(|_| ())
 }
 
 pub async fn get_mode(addr: &str) -> ModeResult {
     let args = ModeArgs {
         mode: String::new(),
     };
-    let request = Request {
-        procedure_id: MODE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(MODE_PROCEDURE, args.serialize());
     match client::send_request(addr, request).await {
         Ok(response) => ModeResult::deserialize(&response.payload).unwrap_or(ModeResult {
             mode: "unknown".to_string(),
@@ -129,16 +160,37 @@ pub async fn get_mode(addr: &str) -> ModeResult {
 
 pub async fn set_mode(addr: &str, mode: String) -> ModeResult {
     let args = ModeArgs { mode };
-    let request = Request {
-        procedure_id: MODE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(MODE_PROCEDURE, args.serialize());
     match client::send_request(addr, request).await {
         Ok(response) => ModeResult::deserialize(&response.payload).unwrap_or(ModeResult {
             mode: "unknown".to_string(),
         }),
+        Err(rpc::Error::Status(_, message)) => ModeResult {
+            mode: format!("ERROR: {}", message),
+        },
         Err(_) => ModeResult {
             mode: "unknown".to_string(),
         },
     }
 }
+
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION"))
+        .procedure(GET_PROCEDURE, "get", schema!(GetArgs), schema!(GetResult))
+        .procedure(SET_PROCEDURE, "set", schema!(SetArgs), Schema::none())
+        .procedure(DELETE_PROCEDURE, "delete", schema!(DeleteArgs), Schema::none())
+        .procedure(STATS_PROCEDURE, "stats", schema!(StatsArgs), schema!(StatsResult))
+        .procedure(
+            REPLICATE_SET_PROCEDURE,
+            "replicate_set",
+            schema!(ReplicateSetArgs),
+            Schema::none(),
+        )
+        .procedure(
+            REPLICATE_DELETE_PROCEDURE,
+            "replicate_delete",
+            schema!(ReplicateDeleteArgs),
+            Schema::none(),
+        )
+        .procedure(MODE_PROCEDURE, "mode", schema!(ModeArgs), schema!(ModeResult))
+}
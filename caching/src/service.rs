@@ -0,0 +1,474 @@
+use crate::{
+    DeleteArgs, GetArgs, GetResult, ModeArgs, ModeResult, ReplicateDeleteArgs, ReplicateSetArgs,
+    SetArgs, StatsArgs, StatsResult, DELETE_PROCEDURE, GET_PROCEDURE, MODE_PROCEDURE,
+    REPLICATE_DELETE_PROCEDURE, REPLICATE_SET_PROCEDURE, SET_PROCEDURE, STATS_PROCEDURE,
+    SYSTEM_NAME,
+};
+use rpc::reflection::Reflection;
+use rpc::{server, transport, Handlers, Request, Response, Status, Shutdown};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::time::{sleep, Duration, Instant};
+
+const MAX_CAPACITY: usize = 10000;
+const SHARD_COUNT: usize = 16;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
+
+struct CacheEntry {
+    value: String,
+    expires_at: Instant,
+    version: u64,
+}
+
+struct Cache {
+    entries: HashMap<String, CacheEntry>,
+    lru_order: VecDeque<String>,
+    hits: i32,
+    misses: i32,
+    max_capacity: usize,
+    next_version: u64,
+}
+
+impl Cache {
+    fn new(max_capacity: usize) -> Self {
+        Cache {
+            entries: HashMap::new(),
+            lru_order: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+            max_capacity,
+            next_version: 1,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<(String, u64)> {
+        if let Some(entry) = self.entries.get(key) {
+            if entry.expires_at < Instant::now() {
+                self.entries.remove(key);
+                self.lru_order.retain(|k| k != key);
+                self.misses += 1;
+                return None;
+            }
+            let value = entry.value.clone();
+            let version = entry.version;
+            self.lru_order.retain(|k| k != key);
+            self.lru_order.push_front(key.to_string());
+            self.hits += 1;
+            Some((value, version))
+        } else {
+            self.misses += 1;
+            None
+        }
+    }
+
+    fn set(&mut self, key: String, value: String, ttl_secs: i32) -> u64 {
+        let version = self.next_version;
+        self.next_version += 1;
+        self.set_with_version(key, value, ttl_secs, version);
+        version
+    }
+
+    fn set_with_version(&mut self, key: String, value: String, ttl_secs: i32, version: u64) {
+        let ttl = if ttl_secs > 0 {
+            Duration::from_secs(ttl_secs as u64)
+        } else {
+            Duration::from_secs(3600)
+        };
+
+        // Only apply if version >= current
+        if let Some(existing) = self.entries.get(&key) {
+            if version < existing.version {
+                return;
+            }
+        }
+
+        let entry = CacheEntry {
+            value,
+            expires_at: Instant::now() + ttl,
+            version,
+        };
+
+        self.lru_order.retain(|k| k != &key);
+
+        while self.entries.len() >= self.max_capacity {
+            if let Some(evicted_key) = self.lru_order.pop_back() {
+                self.entries.remove(&evicted_key);
+                println!("Evicted key: {}", evicted_key);
+            } else {
+                break;
+            }
+        }
+
+        if version >= self.next_version {
+            self.next_version = version + 1;
+        }
+
+        self.entries.insert(key.clone(), entry);
+        self.lru_order.push_front(key);
+    }
+
+    fn delete(&mut self, key: &str) -> u64 {
+        let version = self.next_version;
+        self.next_version += 1;
+        self.entries.remove(key);
+        self.lru_order.retain(|k| k != key);
+        version
+    }
+
+    fn delete_with_version(&mut self, key: &str, version: u64) {
+        if let Some(existing) = self.entries.get(key) {
+            if version < existing.version {
+                return;
+            }
+        }
+        self.entries.remove(key);
+        self.lru_order.retain(|k| k != key);
+        if version >= self.next_version {
+            self.next_version = version + 1;
+        }
+    }
+
+    fn cleanup_expired(&mut self) {
+        let now = Instant::now();
+        let expired_keys: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at < now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &expired_keys {
+            self.entries.remove(key);
+            self.lru_order.retain(|k| k != key);
+        }
+
+        if !expired_keys.is_empty() {
+            println!("Cleaned up {} expired entries", expired_keys.len());
+        }
+    }
+}
+
+// Keys are spread over independently locked shards, since even a cache read
+// updates the LRU order and hit counters. Every key always lands in the same
+// shard, so per-shard versions still order the writes to a key. Shard locks
+// are never held across an await, so replication runs outside them.
+struct CachingState {
+    shards: Vec<Mutex<Cache>>,
+    consistency_mode: RwLock<String>,
+    own_addr: String,
+}
+
+impl CachingState {
+    fn new(max_capacity: usize, consistency_mode: String, own_addr: String) -> Self {
+        CachingState {
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new(Cache::new(max_capacity / SHARD_COUNT)))
+                .collect(),
+            consistency_mode: RwLock::new(consistency_mode),
+            own_addr,
+        }
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<Cache> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    fn consistency_mode(&self) -> String {
+        self.consistency_mode.read().unwrap().clone()
+    }
+}
+
+async fn get_peers(own_addr: &str) -> Vec<String> {
+    let own_socket = transport::unix_address(own_addr);
+    let result = match discovery::list(SYSTEM_NAME.to_string()).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Failed to list peers: {}", e);
+            return Vec::new();
+        }
+    };
+    let listed = result
+        .addresses
+        .split(';')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    transport::one_per_server(listed)
+        .into_iter()
+        .filter(|s| s != own_addr && Some(s) != own_socket.as_ref())
+        .collect()
+}
+
+mod handlers {
+    use super::*;
+
+    pub async fn get(payload: &str, state: &CachingState) -> Response {
+        let args = GetArgs::deserialize(payload).expect("Failed to deserialize payload");
+
+        let cached = state.shard(&args.key).lock().unwrap().get(&args.key);
+        match cached {
+            Some((value, _version)) => {
+                let result = GetResult { value, hit: 1 };
+                Response::ok(result.serialize())
+            }
+            None => {
+                let result = GetResult {
+                    value: String::new(),
+                    hit: 0,
+                };
+                Response::ok(result.serialize())
+            }
+        }
+    }
+
+    pub async fn set(payload: &str, state: &CachingState) -> Response {
+        let args = SetArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let version = state.shard(&args.key).lock().unwrap().set(
+            args.key.clone(),
+            args.value.clone(),
+            args.ttl_secs,
+        );
+        let mode = state.consistency_mode();
+        let own_addr = state.own_addr.clone();
+
+        // Replicate based on consistency mode
+        match mode.as_str() {
+            "eventual" => {
+                // Fire-and-forget async replication
+                let key = args.key.clone();
+                let value = args.value.clone();
+                let ttl = args.ttl_secs;
+                let ver = version as i32;
+                let own = own_addr.clone();
+                tokio::spawn(async move {
+                    let peers = get_peers(&own).await;
+                    for peer in &peers {
+                        let _ =
+                            crate::replicate_set(peer, key.clone(), value.clone(), ttl, ver)
+                                .await;
+                    }
+                });
+            }
+            "quorum" => {
+                // Wait for 1 peer ack (W=2 of N=3)
+                let peers = get_peers(&own_addr).await;
+                let mut acks = 0;
+                for peer in &peers {
+                    if acks >= 1 {
+                        break;
+                    }
+                    let result = crate::replicate_set(
+                        peer,
+                        args.key.clone(),
+                        args.value.clone(),
+                        args.ttl_secs,
+                        version as i32,
+                    )
+                    .await;
+                    if result.is_ok() {
+                        acks += 1;
+                    }
+                }
+            }
+            "strong" => {
+                // Wait for ALL peer acks (W=N)
+                let peers = get_peers(&own_addr).await;
+                for peer in &peers {
+                    let _ = crate::replicate_set(
+                        peer,
+                        args.key.clone(),
+                        args.value.clone(),
+                        args.ttl_secs,
+                        version as i32,
+                    )
+                    .await;
+                }
+            }
+            _ => {}
+        }
+
+        Response::ok("OK")
+    }
+
+    pub async fn delete(payload: &str, state: &CachingState) -> Response {
+        let args = DeleteArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let version = state.shard(&args.key).lock().unwrap().delete(&args.key);
+        let mode = state.consistency_mode();
+        let own_addr = state.own_addr.clone();
+
+        // Replicate delete based on consistency mode
+        match mode.as_str() {
+            "eventual" => {
+                let key = args.key.clone();
+                let ver = version as i32;
+                let own = own_addr.clone();
+                tokio::spawn(async move {
+                    let peers = get_peers(&own).await;
+                    for peer in &peers {
+                        let _ =
+                            crate::replicate_delete_remote(peer, key.clone(), ver).await;
+                    }
+                });
+            }
+            "quorum" => {
+                let peers = get_peers(&own_addr).await;
+                let mut acks = 0;
+                for peer in &peers {
+                    if acks >= 1 {
+                        break;
+                    }
+                    let result = crate::replicate_delete_remote(
+                        peer,
+                        args.key.clone(),
+                        version as i32,
+                    )
+                    .await;
+                    if result.is_ok() {
+                        acks += 1;
+                    }
+                }
+            }
+            "strong" => {
+                let peers = get_peers(&own_addr).await;
+                for peer in &peers {
+                    let _ = crate::replicate_delete_remote(
+                        peer,
+                        args.key.clone(),
+                        version as i32,
+                    )
+                    .await;
+                }
+            }
+            _ => {}
+        }
+
+        Response::ok("OK")
+    }
+
+    pub async fn stats(payload: &str, state: &CachingState) -> Response {
+        let _args = StatsArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let mut result = StatsResult {
+            hits: 0,
+            misses: 0,
+            size: 0,
+        };
+        for shard in &state.shards {
+            let cache = shard.lock().unwrap();
+            result.hits += cache.hits;
+            result.misses += cache.misses;
+            result.size += cache.entries.len() as i32;
+        }
+        Response::ok(result.serialize())
+    }
+
+    pub async fn replicate_set(payload: &str, state: &CachingState) -> Response {
+        let args =
+            ReplicateSetArgs::deserialize(payload).expect("Failed to deserialize payload");
+        state.shard(&args.key).lock().unwrap().set_with_version(
+            args.key,
+            args.value,
+            args.ttl_secs,
+            args.version as u64,
+        );
+        Response::ok("OK")
+    }
+
+    pub async fn replicate_delete(payload: &str, state: &CachingState) -> Response {
+        let args =
+            ReplicateDeleteArgs::deserialize(payload).expect("Failed to deserialize payload");
+        state
+            .shard(&args.key)
+            .lock()
+            .unwrap()
+            .delete_with_version(&args.key, args.version as u64);
+        Response::ok("OK")
+    }
+
+    pub async fn mode(payload: &str, state: &CachingState) -> Response {
+        let args = ModeArgs::deserialize(payload).expect("Failed to deserialize payload");
+        if !args.mode.is_empty() {
+            // Set mode
+            match args.mode.as_str() {
+                "eventual" | "quorum" | "strong" => {
+                    *state.consistency_mode.write().unwrap() = args.mode.clone();
+                    println!("Consistency mode changed to: {}", args.mode);
+                }
+                _ => {
+                    return Response::error(
+                        Status::InvalidArgument,
+                        format!("unknown mode '{}'", args.mode),
+                    );
+                }
+            }
+        }
+        let result = ModeResult {
+            mode: state.consistency_mode(),
+        };
+        Response::ok(result.serialize())
+    }
+}
+
+async fn request_handler(request: Request, state: Arc<CachingState>) -> Response {
+    match request.procedure_id {
+        GET_PROCEDURE => handlers::get(&request.payload, &state).await,
+        SET_PROCEDURE => handlers::set(&request.payload, &state).await,
+        DELETE_PROCEDURE => handlers::delete(&request.payload, &state).await,
+        STATS_PROCEDURE => handlers::stats(&request.payload, &state).await,
+        REPLICATE_SET_PROCEDURE => handlers::replicate_set(&request.payload, &state).await,
+        REPLICATE_DELETE_PROCEDURE => {
+            handlers::replicate_delete(&request.payload, &state).await
+        }
+        MODE_PROCEDURE => handlers::mode(&request.payload, &state).await,
+        _ => Response::unknown_procedure(request.procedure_id),
+    }
+}
+
+// Serves the cache on `addr` until `shutdown` is triggered, registered with
+// discovery for as long as it does.
+pub async fn serve(addr: &str, shutdown: Shutdown) {
+    let consistency_mode =
+        std::env::var("CONSISTENCY_MODE").unwrap_or_else(|_| "eventual".to_string());
+
+    let state = Arc::new(CachingState::new(
+        MAX_CAPACITY,
+        consistency_mode.clone(),
+        addr.to_string(),
+    ));
+
+    // Background cleanup task for expired entries
+    let cleanup_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        loop {
+            sleep(CLEANUP_INTERVAL).await;
+            for shard in &cleanup_state.shards {
+                shard.lock().unwrap().cleanup_expired();
+            }
+        }
+    });
+
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.to_string(), &shutdown);
+
+    println!(
+        "Caching service starting on {} (mode={})",
+        addr, consistency_mode
+    );
+
+    server::start_server_with_shutdown(
+        addr,
+        Handlers::standard().with(Reflection::new(crate::describe())),
+        |request, state| {
+            Box::pin(request_handler(request, state))
+                as Pin<Box<dyn Future<Output = Response> + Send>>
+        },
+        state,
+        shutdown,
+    )
+    .await
+    .expect("Server crashed");
+}
@@ -1,14 +1,25 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+use normalization::{Deserializable, Serializable};
 
 pub const SYSTEM_NAME: &str = "configuration";
 pub const SYSTEM_ADDRESS: &str = "127.0.0.1:10500";
 
-pub const GET_PROCEDURE: ProcedureId = 1;
-pub const SET_PROCEDURE: ProcedureId = 2;
-pub const DELETE_PROCEDURE: ProcedureId = 3;
-pub const LIST_PROCEDURE: ProcedureId = 4;
-pub const WATCH_PROCEDURE: ProcedureId = 5;
+// Generates GET_PROCEDURE and friends, Configuration::dispatch and ConfigurationClient.
+#[rpc::service]
+pub trait Configuration {
+    async fn get(&self, args: GetArgs) -> GetResult;
+    async fn set(&self, args: SetArgs);
+    async fn delete(&self, args: DeleteArgs);
+    async fn list(&self, args: ListArgs) -> ListResult;
+    // Returns the current value; clients poll it to detect changes.
+    async fn watch(&self, args: WatchArgs) -> WatchEvent;
+}
+
+// rpc::faults reads its rules with these procedures without depending on
+// this crate, so reordering the trait must not move them.
+const _: () = assert!(
+    GET_PROCEDURE == rpc::faults::CONFIG_GET_PROCEDURE
+        && LIST_PROCEDURE == rpc::faults::CONFIG_LIST_PROCEDURE
+);
 
 #[derive(Debug, Serializable, Deserializable)]
 pub struct GetArgs {
@@ -1,9 +1,9 @@
 use configuration::{
-    DeleteArgs, GetArgs, GetResult, ListArgs, ListResult, SetArgs, WatchArgs, WatchEvent,
-    DELETE_PROCEDURE, GET_PROCEDURE, LIST_PROCEDURE, SET_PROCEDURE, SYSTEM_ADDRESS, SYSTEM_NAME,
-    WATCH_PROCEDURE,
+    Configuration, DeleteArgs, GetArgs, GetResult, ListArgs, ListResult, SetArgs, WatchArgs,
+    WatchEvent, SYSTEM_ADDRESS, SYSTEM_NAME,
 };
-use rpc::{server, Request, Response};
+use rpc::reflection::Reflection;
+use rpc::{server, Handlers, Response, Shutdown};
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
@@ -25,100 +25,78 @@ impl ConfigStore {
     }
 }
 
-mod handlers {
-    use super::*;
+struct ConfigService {
+    store: Mutex<ConfigStore>,
+}
 
-    pub async fn get(payload: &str, store: &mut ConfigStore) -> Response {
-        let args = GetArgs::deserialize(payload).expect("Failed to deserialize payload");
+impl Configuration for ConfigService {
+    async fn get(&self, args: GetArgs) -> GetResult {
+        let store = self.store.lock().await;
         let value = store.data.get(&args.key).cloned().unwrap_or_default();
-        let result = GetResult { value };
-        Response {
-            payload: result.serialize(),
-        }
+        GetResult { value }
     }
 
-    pub async fn set(payload: &str, store: &mut ConfigStore) -> Response {
-        let args = SetArgs::deserialize(payload).expect("Failed to deserialize payload");
+    async fn set(&self, args: SetArgs) {
+        let mut store = self.store.lock().await;
         store.data.insert(args.key.clone(), args.value.clone());
         let _ = store.watchers.send((args.key, args.value));
-        Response {
-            payload: "OK".to_string(),
-        }
     }
 
-    pub async fn delete(payload: &str, store: &mut ConfigStore) -> Response {
-        let args = DeleteArgs::deserialize(payload).expect("Failed to deserialize payload");
+    async fn delete(&self, args: DeleteArgs) {
+        let mut store = self.store.lock().await;
         store.data.remove(&args.key);
         let _ = store.watchers.send((args.key, String::new()));
-        Response {
-            payload: "OK".to_string(),
-        }
     }
 
-    pub async fn list(payload: &str, store: &mut ConfigStore) -> Response {
-        let args = ListArgs::deserialize(payload).expect("Failed to deserialize payload");
+    async fn list(&self, args: ListArgs) -> ListResult {
+        let store = self.store.lock().await;
         let keys: Vec<String> = store
             .data
             .keys()
             .filter(|k| k.starts_with(&args.prefix))
             .cloned()
             .collect();
-        let result = ListResult {
+        ListResult {
             keys: keys.join(","),
-        };
-        Response {
-            payload: result.serialize(),
         }
     }
 
-    pub async fn watch(payload: &str, store: &mut ConfigStore) -> Response {
-        let args = WatchArgs::deserialize(payload).expect("Failed to deserialize payload");
-
-        // Return current value for the watched key
-        // Clients poll this endpoint to detect changes
+    async fn watch(&self, args: WatchArgs) -> WatchEvent {
+        let store = self.store.lock().await;
         let value = store.data.get(&args.key).cloned().unwrap_or_default();
-        let event = WatchEvent {
+        WatchEvent {
             key: args.key,
             value,
-        };
-        Response {
-            payload: event.serialize(),
         }
     }
 }
 
-async fn request_handler(request: Request, shared_state: Arc<Mutex<ConfigStore>>) -> Response {
-    let mut store = shared_state.lock().await;
-    match request.procedure_id {
-        GET_PROCEDURE => handlers::get(&request.payload, &mut store).await,
-        SET_PROCEDURE => handlers::set(&request.payload, &mut store).await,
-        DELETE_PROCEDURE => handlers::delete(&request.payload, &mut store).await,
-        LIST_PROCEDURE => handlers::list(&request.payload, &mut store).await,
-        WATCH_PROCEDURE => handlers::watch(&request.payload, &mut store).await,
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
-    }
-}
-
 #[tokio::main]
 async fn main() {
-    let store = Arc::new(Mutex::new(ConfigStore::new()));
+    let service = Arc::new(ConfigService {
+        store: Mutex::new(ConfigStore::new()),
+    });
 
     let addr = std::env::var("PORT")
         .map(|p| format!("127.0.0.1:{}", p))
         .unwrap_or_else(|_| SYSTEM_ADDRESS.to_string());
-    discovery::register(SYSTEM_NAME.to_string(), addr.clone());
+    let shutdown = Shutdown::on_signal();
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.clone(), &shutdown);
 
     println!("Configuration service starting on {}", addr);
 
-    server::start_server_with_state(
+    // Dispatch answers reflection too; this names the procedures for the
+    // handlers in front of it, such as fault injection.
+    let handlers = Handlers::standard().with(Reflection::new(service.describe()));
+    server::start_server_with_shutdown(
         &addr,
-        |request, state| {
-            Box::pin(request_handler(request, state))
+        handlers,
+        |request, service: Arc<ConfigService>| {
+            Box::pin(async move { service.dispatch(request).await })
                 as Pin<Box<dyn Future<Output = Response> + Send>>
         },
-        store,
+        service,
+        shutdown,
     )
     .await
     .expect("Server crashed");
@@ -1,5 +1,8 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+pub mod service;
+
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::{Schema, ServiceDescription};
+use rpc::{schema, ProcedureId, Procedures};
 
 pub const REGISTER_PROCEDURE: ProcedureId = 1;
 // pub const PING_PROCEDURE: ProcedureId = 2;
@@ -7,6 +10,17 @@ pub const QUERY_PROCEDURE: ProcedureId = 2;
 pub const LIST_PROCEDURE: ProcedureId = 3;
 pub const FEDERATED_REGISTER_PROCEDURE: ProcedureId = 4;
 pub const LIST_LOCAL_PROCEDURE: ProcedureId = 5;
+pub const DEREGISTER_PROCEDURE: ProcedureId = 6;
+
+// Registering an address that is already registered doesn't add it twice.
+pub const PROCEDURES: Procedures = Procedures::new(&[
+    REGISTER_PROCEDURE,
+    QUERY_PROCEDURE,
+    LIST_PROCEDURE,
+    FEDERATED_REGISTER_PROCEDURE,
+    LIST_LOCAL_PROCEDURE,
+    DEREGISTER_PROCEDURE,
+]);
 
 #[derive(Debug, Serializable, Deserializable)]
 pub struct RegisterArgs {
@@ -14,6 +28,12 @@ pub struct RegisterArgs {
     pub address: String,
 }
 
+#[derive(Debug, Serializable, Deserializable)]
+pub struct DeregisterArgs {
+    pub name: String,
+    pub address: String,
+}
+
 // #[derive(Debug, Serializable, Deserializable)]
 // pub struct PingArgs {
 //     pub address: String,
@@ -46,17 +66,55 @@ pub struct ListResult {
     pub addresses: String,
 }
 
-use rpc::{client, Request};
+use once_cell::sync::Lazy;
+use rpc::{client, hedge, transport, Request, Response, Shutdown};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use tokio::time::{interval, sleep, Duration};
+use tokio::time::{interval, sleep, Duration, Instant};
 
-const SYSTEM_ADDRESS: &str = "127.0.0.1:10200";
+pub static SYSTEM_ADDRESS: Lazy<String> =
+    Lazy::new(|| std::env::var("DISCOVERY_ADDR").unwrap_or_else(|_| "127.0.0.1:10200".to_string()));
 const PING_INTERVAL: Duration = Duration::from_secs(5);
 const BASE_DELAY: Duration = Duration::from_secs(1);
 const MAX_DELAY: Duration = Duration::from_secs(60);
 const MAX_RETRIES: u32 = 10;
 
+// Addresses from `list` are reused for this long before asking again.
+const LIST_CACHE_TTL: Duration = PING_INTERVAL;
+
+type Listed = HashMap<String, (Instant, Vec<String>)>;
+
+static LISTED: Lazy<Mutex<Listed>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 pub fn register(name: String, address: String) {
+    start_registration(name, address, Arc::new(AtomicBool::new(false)));
+}
+
+// Registers like `register`, but stops pinging and removes the address from
+// discovery as soon as `shutdown` is triggered, so no new traffic is sent to
+// a server that is draining.
+pub fn register_until(name: String, address: String, shutdown: &Shutdown) {
+    // The socket a server also listens on is registered next to its TCP
+    // address, see transport::one_per_server.
+    if let Some(socket) = transport::unix_address(&address) {
+        register_one_until(name.clone(), socket, shutdown);
+    }
+    register_one_until(name, address, shutdown);
+}
+
+fn register_one_until(name: String, address: String, shutdown: &Shutdown) {
+    let stopped = Arc::new(AtomicBool::new(false));
+    start_registration(name.clone(), address.clone(), stopped.clone());
+
+    shutdown.on_shutdown(async move {
+        stopped.store(true, Ordering::SeqCst);
+        deregister(name, address).await;
+    });
+}
+
+fn start_registration(name: String, address: String, stopped: Arc<AtomicBool>) {
     let args = RegisterArgs {
         name: name,
         address: address,
@@ -67,23 +125,20 @@ pub fn register(name: String, address: String) {
             .enable_all()
             .build()
             .unwrap();
-        runtime.block_on(register_with_ping(args));
+        runtime.block_on(register_with_ping(args, stopped));
     });
 }
 
-async fn register_with_ping(args: RegisterArgs) {
+async fn register_with_ping(args: RegisterArgs, stopped: Arc<AtomicBool>) {
     let mut interval = interval(PING_INTERVAL);
     let mut retries = 0;
     let mut delay = BASE_DELAY;
 
-    let request = Request {
-        procedure_id: REGISTER_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(REGISTER_PROCEDURE, args.serialize());
     println!("{:?}", request.clone());
 
-    loop {
-        match client::send_request(SYSTEM_ADDRESS, request.clone()).await {
+    while !stopped.load(Ordering::SeqCst) {
+        match client::send_request(&SYSTEM_ADDRESS, request.clone()).await {
             Ok(response) => {
                 println!("Response: {}", response.payload);
                 interval.tick().await;
@@ -106,20 +161,30 @@ async fn register_with_ping(args: RegisterArgs) {
     }
 }
 
+pub async fn deregister(name: String, address: String) {
+    let args = DeregisterArgs { name, address };
+    let request = Request::new(DEREGISTER_PROCEDURE, args.serialize());
+
+    match client::send_request(&SYSTEM_ADDRESS, request).await {
+        Ok(_) => println!("Deregistered {} at {}", args.name, args.address),
+        Err(e) => eprintln!("Failed to deregister {}: {}", args.address, e),
+    }
+}
+
 pub async fn query(name: String) -> QueryResult {
     let args = QueryArgs { name: name.clone() };
 
-    let request = Request {
-        procedure_id: QUERY_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(QUERY_PROCEDURE, args.serialize());
 
-    match client::send_request(SYSTEM_ADDRESS, request.clone()).await {
+    // The local registry answers; a federated peer is only asked too when it
+    // is slow, since peers answer with their own region's view.
+    let result = hedge::send_request_preferring(&SYSTEM_ADDRESS, &discovery_peers(), request).await;
+    match result {
         Ok(response) => {
             let result =
                 QueryResult::deserialize(&response.payload).expect("Failed to deserialize payload");
             println!("Address for {} is {}", name, result.address);
-            return result;
+            result
         }
         Err(e) => {
             panic!("Failed to send request: {}.", e);
@@ -127,44 +192,88 @@ pub async fn query(name: String) -> QueryResult {
     }
 }
 
-pub async fn list(name: String) -> ListResult {
+pub async fn list(name: String) -> Result<ListResult, rpc::Error> {
     let args = ListArgs { name: name.clone() };
 
-    let request = Request {
-        procedure_id: LIST_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(LIST_PROCEDURE, args.serialize());
 
-    match client::send_request(SYSTEM_ADDRESS, request.clone()).await {
-        Ok(response) => {
-            let result =
-                ListResult::deserialize(&response.payload).expect("Failed to deserialize payload");
-            println!("Addresses for {}: {}", name, result.addresses);
-            result
-        }
-        Err(e) => {
-            panic!("Failed to send list request: {}.", e);
-        }
-    }
+    let response = client::send_request(&SYSTEM_ADDRESS, request).await?;
+    let result = deserialize_list(&response.payload)?;
+    println!("Addresses for {}: {}", name, result.addresses);
+    Ok(result)
 }
 
-pub async fn list_local(name: String) -> ListResult {
+pub async fn list_local(name: String) -> Result<ListResult, rpc::Error> {
     let args = ListArgs { name: name.clone() };
 
-    let request = Request {
-        procedure_id: LIST_LOCAL_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(LIST_LOCAL_PROCEDURE, args.serialize());
 
-    match client::send_request(SYSTEM_ADDRESS, request.clone()).await {
-        Ok(response) => {
-            let result =
-                ListResult::deserialize(&response.payload).expect("Failed to deserialize payload");
-            println!("Local addresses for {}: {}", name, result.addresses);
-            result
-        }
-        Err(e) => {
-            panic!("Failed to send list_local request: {}.", e);
+    let response = client::send_request(&SYSTEM_ADDRESS, request).await?;
+    let result = deserialize_list(&response.payload)?;
+    println!("Local addresses for {}: {}", name, result.addresses);
+    Ok(result)
+}
+
+fn deserialize_list(payload: &str) -> Result<ListResult, rpc::Error> {
+    ListResult::deserialize(payload).map_err(|e| {
+        rpc::Error::Status(
+            rpc::Status::Internal,
+            format!("Failed to deserialize ListResult: {}", e),
+        )
+    })
+}
+
+fn discovery_peers() -> Vec<String> {
+    std::env::var("DISCOVERY_PEERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|peer| peer.trim().to_string())
+        .filter(|peer| !peer.is_empty())
+        .collect()
+}
+
+// Like `list`, split into addresses, and cached for a few seconds so that
+// per-request callers don't add a discovery round trip to every call.
+pub async fn addresses(name: &str) -> Result<Vec<String>, rpc::Error> {
+    if let Some((listed_at, addresses)) = LISTED.lock().unwrap().get(name) {
+        if listed_at.elapsed() < LIST_CACHE_TTL {
+            return Ok(addresses.clone());
         }
     }
+
+    let listed = list(name.to_string())
+        .await?
+        .addresses
+        .split(';')
+        .filter(|address| !address.is_empty())
+        .map(|address| address.to_string())
+        .collect();
+    let addresses = transport::one_per_server(listed);
+    LISTED
+        .lock()
+        .unwrap()
+        .insert(name.to_string(), (Instant::now(), addresses.clone()));
+    Ok(addresses)
+}
+
+// Sends a read to one instance of `name`, hedging it to a second instance if
+// the first is slow. Only for idempotent procedures any instance can answer.
+pub async fn send_hedged(name: &str, request: Request) -> Result<Response, rpc::Error> {
+    let addresses = addresses(name).await?;
+    hedge::send_request(&addresses, request).await
+}
+
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new("discovery", env!("CARGO_PKG_VERSION"))
+        .procedure(REGISTER_PROCEDURE, "register", schema!(RegisterArgs), Schema::none())
+        .procedure(QUERY_PROCEDURE, "query", schema!(QueryArgs), schema!(QueryResult))
+        .procedure(LIST_PROCEDURE, "list", schema!(ListArgs), schema!(ListResult))
+        .procedure(
+            FEDERATED_REGISTER_PROCEDURE,
+            "federated_register",
+            schema!(FederatedRegisterArgs),
+            Schema::none(),
+        )
+        .procedure(LIST_LOCAL_PROCEDURE, "list_local", schema!(ListArgs), schema!(ListResult))
+        .procedure(DEREGISTER_PROCEDURE, "deregister", schema!(DeregisterArgs), Schema::none())
 }
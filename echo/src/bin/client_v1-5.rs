@@ -8,10 +8,7 @@ async fn main() {
     };
     let serialized_args = args.serialize();
 
-    let request = Request {
-        procedure_id: ECHO_PROCEDURE,
-        payload: serialized_args,
-    };
+    let request = Request::new(ECHO_PROCEDURE, serialized_args);
 
     let address = discovery::query(SYSTEM_NAME.to_string()).await.address;
 
@@ -1,4 +1,4 @@
-use echo::{EchoArgs, ECHO_PROCEDURE, SYSTEM_ADDRESS};
+use echo::{EchoArgs, ECHO_PROCEDURE, PROCEDURES, SYSTEM_ADDRESS};
 use rpc::{client_v1, Request};
 
 #[tokio::main]
@@ -8,12 +8,9 @@ async fn main() {
     };
     let serialized_args = args.serialize();
 
-    let request = Request {
-        procedure_id: ECHO_PROCEDURE,
-        payload: serialized_args,
-    };
+    let request = Request::new(ECHO_PROCEDURE, serialized_args);
 
-    let response = client_v1::send_request(SYSTEM_ADDRESS, request)
+    let response = client_v1::send_request_with_procedures(SYSTEM_ADDRESS, request, &PROCEDURES)
         .await
         .expect("Failed to get response");
     println!("Response: {}", response.payload);
@@ -10,13 +10,9 @@ fn handler(request: Request) -> Response {
         ECHO_PROCEDURE => {
             let args: EchoArgs =
                 EchoArgs::deserialize(&request.payload).expect("Failed to deserialize");
-            Response {
-                payload: handle_echo(args),
-            }
+            Response::ok(handle_echo(args))
         }
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
+        _ => Response::unknown_procedure(request.procedure_id),
     }
 }
 
@@ -1,5 +1,5 @@
 use echo::{EchoArgs, ECHO_PROCEDURE, SYSTEM_ADDRESS, SYSTEM_NAME};
-use rpc::{server_v1, Request, Response};
+use rpc::{server, Request, Response};
 use std::{thread, time::Duration};
 
 fn handle_echo(args: EchoArgs) -> String {
@@ -12,20 +12,16 @@ fn handler(request: Request) -> Response {
         ECHO_PROCEDURE => {
             let args: EchoArgs =
                 EchoArgs::deserialize(&request.payload).expect("Failed to deserialize");
-            Response {
-                payload: handle_echo(args),
-            }
+            Response::ok(handle_echo(args))
         }
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
+        _ => Response::unknown_procedure(request.procedure_id),
     }
 }
 
 #[tokio::main]
 async fn main() {
     discovery::register(SYSTEM_NAME.to_string(), SYSTEM_ADDRESS.to_string());
-    server_v1::start_server(SYSTEM_ADDRESS, handler)
+    server::start_server(SYSTEM_ADDRESS, handler)
         .await
         .expect("Server crashed");
 }
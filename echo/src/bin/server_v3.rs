@@ -1,5 +1,5 @@
 use echo::{EchoArgs, ECHO_PROCEDURE, SYSTEM_ADDRESS, SYSTEM_NAME};
-use rpc::{server_v2, Request, Response};
+use rpc::{server, Request, Response, Status};
 use tokio::time::{timeout, sleep, Duration};
 use std::pin::Pin;
 use std::future::Future;
@@ -16,31 +16,26 @@ async fn handle_with_timeout(request: Request) -> Response {
         match request.procedure_id {
             ECHO_PROCEDURE => {
                 let args: EchoArgs = EchoArgs::deserialize(&request.payload).expect("Failed to deserialize");
-                Response {
-                    payload: handle_echo(args).await,
-                }
+                Response::ok(handle_echo(args).await)
             }
-            _ => Response {
-                payload: "Unknown procedure".to_string(),
-            },
+            _ => Response::unknown_procedure(request.procedure_id),
         }
     }).await {
         Ok(response) => response,
-        Err(_) => Response {
-            payload: "Operation timed out".to_string(),
-        },
+        Err(_) => Response::error(Status::DeadlineExceeded, "Operation timed out"),
     }
 }
 
 #[tokio::main]
 async fn main() {
     discovery::register(SYSTEM_NAME.to_string(), SYSTEM_ADDRESS.to_string());
-    server_v2::start_server(
+    server::start_server_with_state(
         SYSTEM_ADDRESS,
-        |request| {
+        |request, _state: ()| {
             Box::pin(handle_with_timeout(request))
                 as Pin<Box<dyn Future<Output = Response> + Send>>
         },
+        (),
     )
     .await
     .expect("Server crashed");
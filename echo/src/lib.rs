@@ -1,11 +1,13 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+use normalization::{Deserializable, Serializable};
+use rpc::{ProcedureId, Procedures};
 
 pub const SYSTEM_NAME: &str = "echo";
 pub const SYSTEM_ADDRESS: &str = "127.0.0.1:10100";
 
 pub const ECHO_PROCEDURE: ProcedureId = 1;
 
+pub const PROCEDURES: Procedures = Procedures::new(&[ECHO_PROCEDURE]);
+
 #[derive(Serializable, Deserializable)]
 pub struct EchoArgs {
     pub message: String,
@@ -1,9 +1,11 @@
 mod content;
 mod content_ja;
 
-use rpc::{client, Request};
+use once_cell::sync::Lazy;
+use rpc::{client, deadline, trace, transport, Request};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
 
@@ -22,6 +24,12 @@ const LOADBALANCER_ADDR: &str = "127.0.0.1:8080";
 
 static USER_COUNTER: AtomicU64 = AtomicU64::new(1);
 
+static REQUEST_TIMEOUT_MS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("REQUEST_TIMEOUT_MS")
+        .map(|val| val.parse().expect("REQUEST_TIMEOUT_MS must be a number"))
+        .unwrap_or(10000)
+});
+
 // ── Base64 encode/decode (inline, no external crate) ────────────────────────
 
 const B64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
@@ -79,10 +87,7 @@ fn base64_decode(input: &str) -> Vec<u8> {
 // ── RPC helpers ─────────────────────────────────────────────────────────────
 
 async fn send(addr: &str, procedure_id: i32, payload: String) -> String {
-    let request = Request {
-        procedure_id,
-        payload,
-    };
+    let request = Request::new(procedure_id, payload);
     match client::send_request(addr, request).await {
         Ok(response) => response.payload,
         Err(e) => format!("ERROR: {}", e),
@@ -1402,6 +1407,7 @@ fn wrap_dashboard(title: &str, nav_active: &str, body: &str) -> String {
         ("/dashboard/loadbalancer", "Load Balancer"),
         ("/dashboard/consistency", "Consistency"),
         ("/dashboard/regions", "Regions"),
+        ("/dashboard/traces", "Traces"),
     ];
 
     let nav_html: String = nav_items
@@ -1719,6 +1725,11 @@ async fn page_config(body: &str) -> String {
         <button type="submit" class="btn-primary">Set</button>
     </form>
 </div>
+<div class="card">
+    <h2>Fault Injection</h2>
+    <p>Keys like <code>faults/storage/get</code> inject faults into a service's procedure, named or by id, or <code>*</code> for all of them. Services pick rule changes up within a few seconds; delete the key to stop.</p>
+    <p>Values combine <code>latency_ms=200</code>, <code>error=unavailable</code>, <code>drop</code> and <code>corrupt</code>, with <code>percent=25</code> to affect only some requests.</p>
+</div>
 <div class="card">
     <h2>Configuration Entries</h2>
     {}
@@ -2916,10 +2927,7 @@ async fn page_regions() -> String {
     let tailer_addr = "127.0.0.1:10400";
     let tailer_stats = match rpc::client::send_request(
         tailer_addr,
-        rpc::Request {
-            procedure_id: 1,
-            payload: "0".to_string(),
-        },
+        rpc::Request::new(1, "0".to_string()),
     )
     .await
     {
@@ -2927,10 +2935,23 @@ async fn page_regions() -> String {
         Err(_) => "unavailable".to_string(),
     };
 
-    let storage_all = discovery::list("storage".to_string()).await;
-    let storage_local = discovery::list_local("storage".to_string()).await;
-    let all_count = storage_all.addresses.split(';').filter(|s| !s.is_empty()).count();
-    let local_count = storage_local.addresses.split(';').filter(|s| !s.is_empty()).count();
+    let storage_all = discovery::list("storage".to_string())
+        .await
+        .map(|result| result.addresses)
+        .unwrap_or_default();
+    let storage_local = discovery::list_local("storage".to_string())
+        .await
+        .map(|result| result.addresses)
+        .unwrap_or_default();
+    // Instances with a socket are listed under it too
+    let count = |addresses: &str| {
+        addresses
+            .split(';')
+            .filter(|s| !s.is_empty() && !transport::is_unix(s))
+            .count()
+    };
+    let all_count = count(&storage_all);
+    let local_count = count(&storage_local);
     let remote_count = if all_count > local_count { all_count - local_count } else { 0 };
 
     let cache_mode = caching::get_mode(CACHING_ADDR).await;
@@ -2983,6 +3004,151 @@ async fn page_regions() -> String {
     wrap_dashboard("Regions", "Regions", &body)
 }
 
+// ── Traces dashboard ────────────────────────────────────────────────────────
+
+fn format_micros(micros: u64) -> String {
+    format!("{:.1} ms", micros as f64 / 1000.0)
+}
+
+async fn page_traces() -> String {
+    let args = monitoring::ListTracesArgs { limit: 50 };
+    let resp = send(
+        MONITORING_ADDR,
+        monitoring::LIST_TRACES_PROCEDURE,
+        args.serialize(),
+    )
+    .await;
+
+    let mut rows = String::new();
+    if let Ok(result) = monitoring::ListTracesResult::deserialize(&resp) {
+        for summary in result.traces.split(';').filter_map(monitoring::TraceSummary::decode) {
+            let trace_id = trace::format_id(summary.trace_id);
+            rows.push_str(&format!(
+                "<tr><td><a href=\"/dashboard/traces/{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                trace_id,
+                trace_id,
+                html_escape(&summary.service),
+                html_escape(&summary.name),
+                summary.span_count,
+                format_micros(summary.duration),
+            ));
+        }
+    }
+
+    let table = if rows.is_empty() {
+        "<div class=\"empty\">No traces recorded yet.</div>".to_string()
+    } else {
+        format!(
+            "<table><tr><th>Trace</th><th>Service</th><th>Root</th><th>Spans</th><th>Duration</th></tr>{}</table>",
+            rows
+        )
+    };
+
+    let body = format!(
+        r#"<div class="card">
+    <h2>Recent Traces</h2>
+    {}
+</div>"#,
+        table
+    );
+
+    wrap_dashboard("Traces", "Traces", &body)
+}
+
+// Orders spans depth first so every span sits under its parent, paired with
+// its depth. Spans whose parent never arrived are shown at the top level.
+fn span_tree(spans: &[trace::Span]) -> Vec<(usize, &trace::Span)> {
+    let ids: Vec<u64> = spans.iter().map(|span| span.span_id).collect();
+    let mut children: HashMap<Option<u64>, Vec<&trace::Span>> = HashMap::new();
+    for span in spans {
+        let parent = span.parent_id.filter(|parent| ids.contains(parent));
+        children.entry(parent).or_default().push(span);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by_key(|span| span.start);
+    }
+
+    let mut ordered = Vec::new();
+    let mut stack: Vec<(usize, &trace::Span)> = children
+        .get(&None)
+        .map(|roots| roots.iter().rev().map(|span| (0, *span)).collect())
+        .unwrap_or_default();
+    while let Some((depth, span)) = stack.pop() {
+        ordered.push((depth, span));
+        if let Some(spans) = children.get(&Some(span.span_id)) {
+            stack.extend(spans.iter().rev().map(|child| (depth + 1, *child)));
+        }
+    }
+    ordered
+}
+
+async fn page_trace(trace_id: &str) -> String {
+    let args = monitoring::GetTraceArgs {
+        trace_id: trace_id.to_string(),
+    };
+    let resp = send(
+        MONITORING_ADDR,
+        monitoring::GET_TRACE_PROCEDURE,
+        args.serialize(),
+    )
+    .await;
+    let spans = match monitoring::GetTraceResult::deserialize(&resp) {
+        Ok(result) => trace::decode_spans(&result.spans),
+        Err(_) => Vec::new(),
+    };
+
+    let start = spans.iter().map(|span| span.start).min().unwrap_or(0);
+    let end = spans.iter().map(trace::Span::end).max().unwrap_or(0);
+    let total = (end - start).max(1) as f64;
+
+    let mut rows = String::new();
+    for (depth, span) in span_tree(&spans) {
+        let left = (span.start - start) as f64 / total * 100.0;
+        let width = (span.duration as f64 / total * 100.0).max(0.5);
+        let bar_class = if span.status == "OK" {
+            "btn-primary"
+        } else {
+            "btn-danger"
+        };
+        rows.push_str(&format!(
+            "<tr><td style=\"padding-left: {}px\">{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td style=\"width: 45%\"><div style=\"position: relative; height: 14px\">\
+             <div class=\"{}\" style=\"position: absolute; left: {:.2}%; width: {:.2}%; height: 100%; border-radius: 2px\"></div>\
+             </div></td></tr>\n",
+            12 + depth * 16,
+            html_escape(&span.service),
+            html_escape(&span.name),
+            html_escape(&span.status),
+            format_micros(span.duration),
+            bar_class,
+            left,
+            width,
+        ));
+    }
+
+    let table = if rows.is_empty() {
+        "<div class=\"empty\">No spans recorded for this trace.</div>".to_string()
+    } else {
+        format!(
+            "<table><tr><th>Service</th><th>Span</th><th>Status</th><th>Duration</th><th>{}</th></tr>{}</table>",
+            format_micros(end - start),
+            rows
+        )
+    };
+
+    let body = format!(
+        r#"<div class="card">
+    <h2>Trace {}</h2>
+    {}
+</div>
+<p><a href="/dashboard/traces">&larr; All traces</a></p>"#,
+        html_escape(trace_id),
+        table
+    );
+
+    wrap_dashboard("Trace", "Traces", &body)
+}
+
 // ── SEO helpers ─────────────────────────────────────────────────────────────
 
 fn generate_sitemap() -> String {
@@ -3203,6 +3369,10 @@ async fn handle_request(
             }
         }
         ("GET", "/dashboard/regions") => (200, page_regions().await),
+        ("GET", "/dashboard/traces") => (200, page_traces().await),
+        ("GET", path) if path.starts_with("/dashboard/traces/") => {
+            (200, page_trace(&path["/dashboard/traces/".len()..]).await)
+        }
 
         // ── SEO: robots.txt and sitemap.xml ──
         ("GET", "/robots.txt") => (200, "User-agent: *\nAllow: /\nDisallow: /dashboard\nDisallow: /api/\nSitemap: https://p.jjm.net/sitemap.xml\n".to_string()),
@@ -3308,8 +3478,32 @@ async fn main() {
 
             println!("{} {} {} from {}", method, path, body.len(), addr);
 
-            let (status, html, cookies) =
-                handle_request(method, path, &request_str, &body).await;
+            // Every page is the root of a trace, so the rpc calls made while
+            // serving it show up in its waterfall. Trace pages aren't traced,
+            // or looking at traces would keep adding new ones.
+            let (base, _) = parse_query_string(path);
+            let span = if base.starts_with("/dashboard/traces") {
+                None
+            } else {
+                Some(trace::ActiveSpan::root(format!("{} {}", method, base)))
+            };
+
+            // Every rpc call made while serving the page shares one budget.
+            let (status, html, cookies) = trace::scope(
+                span.as_ref().map(|span| span.context()),
+                deadline::scope(
+                    Some(deadline::after(Duration::from_millis(*REQUEST_TIMEOUT_MS))),
+                    handle_request(method, path, &request_str, &body),
+                ),
+            )
+            .await;
+            if let Some(span) = span {
+                span.finish(if status < 400 {
+                    "OK".to_string()
+                } else {
+                    status.to_string()
+                });
+            }
 
             // Handle redirects for legacy paths
             if status == 301 {
@@ -1,9 +1,10 @@
 use rand::Rng;
+use rpc::transport;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration, Instant};
 
@@ -294,12 +295,13 @@ impl LoadBalancer {
     }
 }
 
+// A socket is on this host, like a loopback address.
 fn is_local(addr: &str) -> bool {
-    addr.starts_with("127.0.0.1")
+    addr.starts_with("127.0.0.1") || transport::is_unix(addr)
 }
 
 fn region_for_address(addr: &str, own_region: &str) -> String {
-    if addr.starts_with("127.0.0.1") {
+    if is_local(addr) {
         return own_region.to_string();
     }
     for &(region, ip) in REGIONS {
@@ -310,20 +312,9 @@ fn region_for_address(addr: &str, own_region: &str) -> String {
     "unknown".to_string()
 }
 
+// Sent to monitoring with the next batch of rpc metrics.
 fn report_metric(metric: &str, value: i32) {
-    let args = monitoring::ReportArgs {
-        service: "loadbalancer".to_string(),
-        metric: metric.to_string(),
-        value,
-    };
-    let payload = args.serialize();
-    tokio::spawn(async move {
-        let request = rpc::Request {
-            procedure_id: monitoring::REPORT_PROCEDURE,
-            payload,
-        };
-        let _ = rpc::client::send_request(monitoring::SYSTEM_ADDRESS, request).await;
-    });
+    rpc::metrics::report(metric, value);
 }
 
 #[tokio::main]
@@ -341,13 +332,20 @@ async fn main() {
     tokio::spawn(async move {
         loop {
             sleep(BACKEND_REFRESH_INTERVAL).await;
-            let result = discovery::list("frontend".to_string()).await;
-            let addresses: Vec<String> = result
+            let result = match discovery::list("frontend".to_string()).await {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("Failed to refresh backends: {}", e);
+                    continue;
+                }
+            };
+            let listed = result
                 .addresses
                 .split(';')
                 .filter(|s| !s.is_empty())
                 .map(|s| s.to_string())
                 .collect();
+            let addresses = transport::one_per_server(listed);
 
             if !addresses.is_empty() {
                 refresh_lb.lock().await.refresh_backends(&addresses);
@@ -363,7 +361,7 @@ async fn main() {
             let mut lb = health_lb.lock().await;
             for backend in lb.backends.iter_mut() {
                 let was_healthy = backend.healthy;
-                backend.healthy = TcpStream::connect(&backend.address).await.is_ok();
+                backend.healthy = transport::connect(&backend.address).await.is_ok();
                 if was_healthy != backend.healthy {
                     println!(
                         "Backend {} is now {}",
@@ -618,7 +616,7 @@ async fn main() {
 
             // Connect to the backend
             let result = async {
-                let mut backend = match TcpStream::connect(&backend_addr).await {
+                let mut backend = match transport::connect(&backend_addr).await {
                     Ok(s) => s,
                     Err(e) => {
                         eprintln!("Failed to connect to backend {}: {}", backend_addr, e);
@@ -1,5 +1,10 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::{Schema, ServiceDescription};
+use rpc::trace::{self, Span, TraceId};
+use rpc::{schema, ProcedureId};
+
+pub use rpc::metrics::ReportBatchArgs;
+pub use rpc::trace::RecordSpansArgs;
 
 pub const SYSTEM_NAME: &str = "monitoring";
 pub const SYSTEM_ADDRESS: &str = "127.0.0.1:10800";
@@ -8,6 +13,19 @@ pub const REPORT_PROCEDURE: ProcedureId = 1;
 pub const HEARTBEAT_PROCEDURE: ProcedureId = 2;
 pub const QUERY_PROCEDURE: ProcedureId = 3;
 pub const HEALTH_PROCEDURE: ProcedureId = 4;
+// Monitoring hosts the trace collector that every process ships spans to.
+pub const RECORD_SPANS_PROCEDURE: ProcedureId = 5;
+pub const GET_TRACE_PROCEDURE: ProcedureId = 6;
+pub const LIST_TRACES_PROCEDURE: ProcedureId = 7;
+// Every rpc server reports its per-procedure metrics here in batches.
+pub const REPORT_BATCH_PROCEDURE: ProcedureId = 8;
+
+// rpc sends spans and metrics here without depending on this crate, so its
+// ids must stay the same as these.
+const _: () = assert!(
+    RECORD_SPANS_PROCEDURE == trace::RECORD_SPANS_PROCEDURE
+        && REPORT_BATCH_PROCEDURE == rpc::metrics::REPORT_BATCH_PROCEDURE
+);
 
 #[derive(Debug, Serializable, Deserializable)]
 pub struct ReportArgs {
@@ -42,3 +60,102 @@ pub struct HealthArgs {
 pub struct HealthResult {
     pub services: String,
 }
+
+// `trace_id` is the hex id spans are shown with.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct GetTraceArgs {
+    pub trace_id: String,
+}
+
+// `spans` is encoded with rpc::trace::encode_spans.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct GetTraceResult {
+    pub spans: String,
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct ListTracesArgs {
+    pub limit: i32,
+}
+
+// `traces` holds one encoded TraceSummary per entry, newest first, joined by ';'.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct ListTracesResult {
+    pub traces: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceSummary {
+    pub trace_id: TraceId,
+    // The service and name of the root span, or of the earliest span if the
+    // root hasn't arrived yet.
+    pub service: String,
+    pub name: String,
+    pub start: u64,
+    pub duration: u64,
+    pub span_count: usize,
+}
+
+impl TraceSummary {
+    pub fn from_spans(spans: &[Span]) -> Option<TraceSummary> {
+        let root = spans
+            .iter()
+            .find(|span| span.parent_id.is_none())
+            .or_else(|| spans.iter().min_by_key(|span| span.start))?;
+        let start = spans.iter().map(|span| span.start).min()?;
+        let end = spans.iter().map(Span::end).max()?;
+        Some(TraceSummary {
+            trace_id: root.trace_id,
+            service: root.service.clone(),
+            name: root.name.clone(),
+            start,
+            duration: end - start,
+            span_count: spans.len(),
+        })
+    }
+
+    // `trace|service|name|start|duration|span_count`
+    pub fn encode(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}|{}",
+            trace::format_id(self.trace_id),
+            self.service.replace(['|', ';'], " "),
+            self.name.replace(['|', ';'], " "),
+            self.start,
+            self.duration,
+            self.span_count
+        )
+    }
+
+    pub fn decode(s: &str) -> Option<TraceSummary> {
+        let fields: Vec<&str> = s.trim().split('|').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        Some(TraceSummary {
+            trace_id: trace::parse_id(fields[0])?,
+            service: fields[1].to_string(),
+            name: fields[2].to_string(),
+            start: fields[3].parse().ok()?,
+            duration: fields[4].parse().ok()?,
+            span_count: fields[5].parse().ok()?,
+        })
+    }
+}
+
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION"))
+        .procedure(REPORT_PROCEDURE, "report", schema!(ReportArgs), Schema::none())
+        .procedure(HEARTBEAT_PROCEDURE, "heartbeat", schema!(HeartbeatArgs), Schema::none())
+        .procedure(QUERY_PROCEDURE, "query", schema!(QueryArgs), schema!(QueryResult))
+        .procedure(HEALTH_PROCEDURE, "health", schema!(HealthArgs), schema!(HealthResult))
+        .procedure(RECORD_SPANS_PROCEDURE, "record_spans", schema!(RecordSpansArgs), Schema::none())
+        .procedure(GET_TRACE_PROCEDURE, "get_trace", schema!(GetTraceArgs), schema!(GetTraceResult))
+        .procedure(
+            LIST_TRACES_PROCEDURE,
+            "list_traces",
+            schema!(ListTracesArgs),
+            schema!(ListTracesResult),
+        )
+        .procedure(REPORT_BATCH_PROCEDURE, "report_batch", schema!(ReportBatchArgs), Schema::none())
+}
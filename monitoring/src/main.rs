@@ -1,10 +1,15 @@
 use monitoring::{
-    HeartbeatArgs, HealthArgs, HealthResult, QueryArgs, QueryResult, ReportArgs,
-    HEARTBEAT_PROCEDURE, HEALTH_PROCEDURE, QUERY_PROCEDURE, REPORT_PROCEDURE, SYSTEM_ADDRESS,
-    SYSTEM_NAME,
+    GetTraceArgs, GetTraceResult, HealthArgs, HealthResult, HeartbeatArgs, ListTracesArgs,
+    ListTracesResult, QueryArgs, QueryResult, RecordSpansArgs, ReportArgs, ReportBatchArgs,
+    TraceSummary, GET_TRACE_PROCEDURE, HEALTH_PROCEDURE, HEARTBEAT_PROCEDURE,
+    LIST_TRACES_PROCEDURE, QUERY_PROCEDURE, RECORD_SPANS_PROCEDURE, REPORT_BATCH_PROCEDURE,
+    REPORT_PROCEDURE, SYSTEM_ADDRESS, SYSTEM_NAME,
 };
-use rpc::{server, Request, Response};
-use std::collections::HashMap;
+use rpc::metrics;
+use rpc::reflection::Reflection;
+use rpc::trace::{self, Span, TraceId};
+use rpc::{server, Handlers, Request, Response, Shutdown};
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -14,6 +19,8 @@ use tokio::time::{sleep, Duration, Instant};
 const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
 const CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
 const MAX_METRIC_WINDOW: usize = 100;
+// The collector keeps the most recently started traces and forgets the rest.
+const MAX_TRACES: usize = 200;
 
 struct ServiceHealth {
     status: String,
@@ -23,6 +30,9 @@ struct ServiceHealth {
 struct MonitoringState {
     health: HashMap<String, ServiceHealth>,
     metrics: HashMap<String, Vec<i32>>,
+    traces: HashMap<TraceId, Vec<Span>>,
+    // Trace ids in the order their first span arrived, oldest first.
+    trace_order: VecDeque<TraceId>,
 }
 
 impl MonitoringState {
@@ -30,9 +40,33 @@ impl MonitoringState {
         MonitoringState {
             health: HashMap::new(),
             metrics: HashMap::new(),
+            traces: HashMap::new(),
+            trace_order: VecDeque::new(),
         }
     }
 
+    fn record_metric(&mut self, service: &str, metric: &str, value: i32) {
+        let key = metric_key(service, metric);
+        let values = self.metrics.entry(key).or_default();
+        values.push(value);
+        // Keep only the last N values (rolling window)
+        if values.len() > MAX_METRIC_WINDOW {
+            values.remove(0);
+        }
+    }
+
+    fn record_span(&mut self, span: Span) {
+        if !self.traces.contains_key(&span.trace_id) {
+            if self.trace_order.len() >= MAX_TRACES {
+                if let Some(oldest) = self.trace_order.pop_front() {
+                    self.traces.remove(&oldest);
+                }
+            }
+            self.trace_order.push_back(span.trace_id);
+        }
+        self.traces.entry(span.trace_id).or_default().push(span);
+    }
+
     fn check_stale_services(&mut self) {
         let now = Instant::now();
         for (service, health) in self.health.iter_mut() {
@@ -55,16 +89,16 @@ mod handlers {
 
     pub async fn report(payload: &str, state: &mut MonitoringState) -> Response {
         let args = ReportArgs::deserialize(payload).expect("Failed to deserialize payload");
-        let key = metric_key(&args.service, &args.metric);
-        let values = state.metrics.entry(key).or_insert_with(Vec::new);
-        values.push(args.value);
-        // Keep only the last N values (rolling window)
-        if values.len() > MAX_METRIC_WINDOW {
-            values.remove(0);
-        }
-        Response {
-            payload: "OK".to_string(),
+        state.record_metric(&args.service, &args.metric, args.value);
+        Response::ok("OK")
+    }
+
+    pub async fn report_batch(payload: &str, state: &mut MonitoringState) -> Response {
+        let args = ReportBatchArgs::deserialize(payload).expect("Failed to deserialize payload");
+        for (metric, value) in metrics::decode_batch(&args.metrics) {
+            state.record_metric(&args.service, &metric, value);
         }
+        Response::ok("OK")
     }
 
     pub async fn heartbeat(payload: &str, state: &mut MonitoringState) -> Response {
@@ -76,9 +110,7 @@ mod handlers {
         health.status = args.status;
         health.last_heartbeat = Instant::now();
         println!("Heartbeat from {}", args.service);
-        Response {
-            payload: "OK".to_string(),
-        }
+        Response::ok("OK")
     }
 
     pub async fn query(payload: &str, state: &mut MonitoringState) -> Response {
@@ -96,9 +128,7 @@ mod handlers {
                 values: String::new(),
             },
         };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn health(payload: &str, state: &mut MonitoringState) -> Response {
@@ -111,9 +141,40 @@ mod handlers {
         let result = HealthResult {
             services: services.join(";"),
         };
-        Response {
-            payload: result.serialize(),
+        Response::ok(result.serialize())
+    }
+
+    pub async fn record_spans(payload: &str, state: &mut MonitoringState) -> Response {
+        let args = RecordSpansArgs::deserialize(payload).expect("Failed to deserialize payload");
+        for span in trace::decode_spans(&args.spans) {
+            state.record_span(span);
         }
+        Response::ok("OK")
+    }
+
+    pub async fn get_trace(payload: &str, state: &mut MonitoringState) -> Response {
+        let args = GetTraceArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let spans = trace::parse_id(&args.trace_id)
+            .and_then(|trace_id| state.traces.get(&trace_id))
+            .map(|spans| trace::encode_spans(spans))
+            .unwrap_or_default();
+        Response::ok(GetTraceResult { spans }.serialize())
+    }
+
+    pub async fn list_traces(payload: &str, state: &mut MonitoringState) -> Response {
+        let args = ListTracesArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let traces: Vec<String> = state
+            .trace_order
+            .iter()
+            .rev()
+            .take(args.limit.max(0) as usize)
+            .filter_map(|trace_id| TraceSummary::from_spans(state.traces.get(trace_id)?))
+            .map(|summary| summary.encode())
+            .collect();
+        let result = ListTracesResult {
+            traces: traces.join(";"),
+        };
+        Response::ok(result.serialize())
     }
 }
 
@@ -121,12 +182,14 @@ async fn request_handler(request: Request, shared_state: Arc<Mutex<MonitoringSta
     let mut state = shared_state.lock().await;
     match request.procedure_id {
         REPORT_PROCEDURE => handlers::report(&request.payload, &mut state).await,
+        REPORT_BATCH_PROCEDURE => handlers::report_batch(&request.payload, &mut state).await,
         HEARTBEAT_PROCEDURE => handlers::heartbeat(&request.payload, &mut state).await,
         QUERY_PROCEDURE => handlers::query(&request.payload, &mut state).await,
         HEALTH_PROCEDURE => handlers::health(&request.payload, &mut state).await,
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
+        RECORD_SPANS_PROCEDURE => handlers::record_spans(&request.payload, &mut state).await,
+        GET_TRACE_PROCEDURE => handlers::get_trace(&request.payload, &mut state).await,
+        LIST_TRACES_PROCEDURE => handlers::list_traces(&request.payload, &mut state).await,
+        _ => Response::unknown_procedure(request.procedure_id),
     }
 }
 
@@ -147,17 +210,20 @@ async fn main() {
     let addr = std::env::var("PORT")
         .map(|p| format!("127.0.0.1:{}", p))
         .unwrap_or_else(|_| SYSTEM_ADDRESS.to_string());
-    discovery::register(SYSTEM_NAME.to_string(), addr.clone());
+    let shutdown = Shutdown::on_signal();
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.clone(), &shutdown);
 
     println!("Monitoring service starting on {}", addr);
 
-    server::start_server_with_state(
+    server::start_server_with_shutdown(
         &addr,
+        Handlers::standard().with(Reflection::new(monitoring::describe())),
         |request, state| {
             Box::pin(request_handler(request, state))
                 as Pin<Box<dyn Future<Output = Response> + Send>>
         },
         state,
+        shutdown,
     )
     .await
     .expect("Server crashed");
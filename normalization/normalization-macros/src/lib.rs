@@ -2,7 +2,10 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{Data, DataStruct, DeriveInput, Fields, FieldsNamed, Ident, Type::Path, TypePath, PathArguments, GenericArgument, Type};
+use syn::{
+    Attribute, Data, DataEnum, DataStruct, DeriveInput, Field, Fields, FieldsNamed, Generics,
+    Ident, Lit, LitStr, Meta, NestedMeta, TypeParamBound, Variant,
+};
 
 // fn generate_serialization_for_type(
 //     field_name: &Option<syn::Ident>,
@@ -83,124 +86,319 @@ use syn::{Data, DataStruct, DeriveInput, Fields, FieldsNamed, Ident, Type::Path,
 //     }
 // }
 
-fn generate_serialization_for_type(
-    field_name: &Option<Ident>,
-    field_type: &TypePath,
-) -> proc_macro2::TokenStream {
-    let field_name_str = field_name.as_ref().unwrap().to_string();
-    let field_str = quote::format_ident!("{}", field_name_str);
-
-    // if is_basic_type(&Type::Path(field_type.clone())) {
-    //     // Basic type or reference to a basic type: use standard formatting
-    //     quote! {
-    //         format!("{}: {}", stringify!(#field_str), &self.#field_str)
-    //     }
-    if field_type.path.is_ident("i32") || field_type.path.is_ident("u32") || field_type.path.is_ident("u64") || field_type.path.is_ident("usize") || field_type.path.is_ident("bool") {
-        quote! { format!("{}: {}", stringify!(#field_name), self.#field_name) }
-    } else if field_type.path.is_ident("String") {
-        quote! {
-            format!(
-                "{}: \"{}\"",
-                stringify!(#field_name),
-                self.#field_name.replace("\\", "\\\\").replace(":", "\\:")
-                    .replace("\"", "\\\"").replace(",", "\\,")
-            )
-        }
-    } else if let Some(segment) = field_type.path.segments.last() {
-        if segment.ident == "Vec" {
-            // Vector of types
-            if let PathArguments::AngleBracketed(inner_args) = &segment.arguments {
-                if let Some(syn::GenericArgument::Type(syn::Type::Path(inner_path))) = inner_args.args.first() {
-                    // if is_basic_type(&Type::Path(inner_path.clone())) {
-                    //     // Serialize vector of basic types
-                    //     quote! {
-                    //         format!(
-                    //             "{}: [{}]",
-                    //             stringify!(#field_str),
-                    //             self.#field_str.iter().map(|item| format!("{}", item)).collect::<Vec<_>>().join(", ")
-                    //         )
-                    //     }
-                    if inner_path.path.is_ident("i32") || inner_path.path.is_ident("u32") || inner_path.path.is_ident("u64") || inner_path.path.is_ident("usize") || inner_path.path.is_ident("bool") {
-                        quote! {
-                            format!(
-                                "{}: [{}]",
-                                stringify!(#field_str),
-                                self.#field_str.iter().map(|item| format!("{}", item)).collect::<Vec<_>>().join(",")
-                            )
-                        }
-                    } else if inner_path.path.is_ident("String") {
-                        quote! {
-                            format!(
-                                // "{}: \"{}\"",
-                                "{}: [{}]",
-                                stringify!(#field_str),
-                                self.#field_str.iter().map(|item| format!("\"{}\"", item
-                                        .replace("\\", "\\\\").replace(":", "\\:")
-                                        .replace("\"", "\\\"").replace(",", "\\,"))).collect::<Vec<_>>().join(",")
-                            )
+// Adds `bound` to every type parameter, so a generic type is Serializable
+// when what it holds is.
+fn add_bounds(generics: &Generics, bound: TypeParamBound) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(bound.clone());
+    }
+    generics
+}
+
+// What `#[normalization(...)]` attributes ask of a field or variant, so
+// structs can change without breaking peers that have another version:
+//   default        a missing field is Default::default()
+//   since = N      the field was added in version N of the type: missing is
+//                  Default::default() from peers writing an older version,
+//                  and an error from peers at N or later
+//   rename = "x"   written and read as `x` rather than the Rust name
+//   skip           never written, and read as Default::default()
+// On the struct or enum itself, `version = N` is written as a `.version`
+// entry of every map of fields; payloads without one are version 1.
+#[derive(Default)]
+struct Options {
+    default: bool,
+    since: Option<u32>,
+    rename: Option<LitStr>,
+    skip: bool,
+}
+
+impl Options {
+    fn parse(attrs: &[Attribute]) -> Options {
+        let mut options = Options::default();
+        for attr in attrs
+            .iter()
+            .filter(|attr| attr.path.is_ident("normalization"))
+        {
+            let list = match attr.parse_meta() {
+                Ok(Meta::List(list)) => list,
+                _ => panic!("Expected #[normalization(...)]"),
+            };
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                        options.default = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                        options.skip = true
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("rename") => {
+                        match pair.lit {
+                            Lit::Str(name) => options.rename = Some(name),
+                            _ => panic!("rename takes a string"),
                         }
-                    } else {
-                        // Serialize vector of custom types
-                        quote! {
-                            format!(
-                                "{}: [{}]",
-                                stringify!(#field_str),
-                                self.#field_str.iter().map(|item| item.serialize()).collect::<Vec<_>>().join(",")
-                            )
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("since") => {
+                        match pair.lit {
+                            Lit::Int(version) => {
+                                options.since = Some(
+                                    version
+                                        .base10_parse()
+                                        .expect("since takes a version number"),
+                                )
+                            }
+                            _ => panic!("since takes a version number"),
                         }
                     }
-                } else {
-                    quote! {
-                        format!("{}: {}", stringify!(#field_str), "Unsupported vector type")
+                    _ => panic!("Unknown normalization attribute!"),
+                }
+            }
+        }
+        options
+    }
+
+    fn of_field(field: &Field) -> Options {
+        Options::parse(&field.attrs)
+    }
+
+    fn of_variant(variant: &Variant) -> Options {
+        let options = Options::parse(&variant.attrs);
+        if options.default || options.skip || options.since.is_some() {
+            panic!("Only rename applies to variants!");
+        }
+        options
+    }
+}
+
+// The key the version is written under, which no Rust field name can clash
+// with.
+const VERSION_KEY: &str = ".version";
+
+fn type_version(attrs: &[Attribute]) -> Option<u32> {
+    let mut version = None;
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("normalization"))
+    {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list,
+            _ => panic!("Expected #[normalization(...)]"),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("version") => {
+                    match pair.lit {
+                        Lit::Int(number) => {
+                            version = Some(number.base10_parse().expect("version takes a number"))
+                        }
+                        _ => panic!("version takes a number"),
                     }
                 }
+                _ => panic!("Only version applies to structs and enums!"),
+            }
+        }
+    }
+    version
+}
+
+// The name a field or variant goes by in the text form.
+fn wire_name(ident: &Ident, options: &Options) -> String {
+    options
+        .rename
+        .as_ref()
+        .map(LitStr::value)
+        .unwrap_or_else(|| ident.to_string())
+}
+
+// Renamed fields and variants still have to read like a Rust name, since
+// separators or spaces in one would break the text form.
+fn check_renames(data: &Data) -> syn::Result<()> {
+    let attrs: Vec<&[Attribute]> = match data {
+        Data::Struct(DataStruct { fields, .. }) => fields.iter().map(|f| &f.attrs[..]).collect(),
+        Data::Enum(DataEnum { variants, .. }) => variants.iter().map(|v| &v.attrs[..]).collect(),
+        Data::Union(_) => Vec::new(),
+    };
+    for rename in attrs
+        .into_iter()
+        .filter_map(|attrs| Options::parse(attrs).rename)
+    {
+        let name = rename.value();
+        let mut chars = name.chars();
+        let identifier_like = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !identifier_like {
+            return Err(syn::Error::new(
+                rename.span(),
+                format!(
+                    "rename must be a name like a Rust identifier, not {:?}",
+                    name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The fields that are written, with their names in the text form.
+fn written_fields(fields: &FieldsNamed) -> Vec<(&Ident, String)> {
+    fields
+        .named
+        .iter()
+        .map(|f| (f, Options::of_field(f)))
+        .filter(|(_, options)| !options.skip)
+        .map(|(f, options)| {
+            let ident = f.ident.as_ref().unwrap();
+            let wire_name = wire_name(ident, &options);
+            if wire_name == VERSION_KEY {
+                panic!("{} is where the version is written", VERSION_KEY);
+            }
+            (ident, wire_name)
+        })
+        .collect()
+}
+
+// `{field: value,...}` for the named fields, read from bindings of the same
+// names, after the version if the type has one.
+fn serialize_named_fields(fields: &FieldsNamed, version: Option<u32>) -> proc_macro2::TokenStream {
+    let version = version
+        .into_iter()
+        .map(|version| quote! { format!("{}: {}", #VERSION_KEY, #version) });
+    let parts = written_fields(fields).into_iter().map(|(field_name, wire_name)| {
+        quote! { format!("{}: {}", #wire_name, ::normalization::Serializable::serialize(#field_name)) }
+    });
+    quote! {{
+        let parts: Vec<String> = vec![#(#version,)* #(#parts),*];
+        format!("{{{}}}", parts.join(","))
+    }}
+}
+
+fn type_name(ty: &impl quote::ToTokens) -> String {
+    quote!(#ty).to_string().replace(' ', "")
+}
+
+// Unit variants are their bare name. Other variants are a one-entry map from
+// the name to the value: the field itself, a list of fields, or a struct.
+fn serialize_variant(variant: &Variant, version: Option<u32>) -> proc_macro2::TokenStream {
+    let variant_name = &variant.ident;
+    let wire_name = wire_name(variant_name, &Options::of_variant(variant));
+    match &variant.fields {
+        Fields::Unit => quote! {
+            Self::#variant_name => #wire_name.to_string(),
+        },
+        Fields::Unnamed(fields) => {
+            let bindings: Vec<Ident> = (0..fields.unnamed.len())
+                .map(|i| quote::format_ident!("field{}", i))
+                .collect();
+            let value = if bindings.len() == 1 {
+                quote! { ::normalization::Serializable::serialize(field0) }
             } else {
-                quote! {
-                    format!("{}: {}", stringify!(#field_str), "Unsupported vector type")
+                quote! { ::normalization::Serializable::serialize(&(#(#bindings,)*)) }
+            };
+            quote! {
+                Self::#variant_name(#(#bindings),*) => {
+                    format!("{{{}: {}}}", #wire_name, #value)
                 }
             }
-        } else {
-            // Custom type: use the serialize method
+        }
+        Fields::Named(fields) => {
+            let bindings = written_fields(fields)
+                .into_iter()
+                .map(|(field_name, _)| field_name);
+            let value = serialize_named_fields(fields, version);
             quote! {
-                format!("{}: {}", stringify!(#field_str), self.#field_str.serialize())
+                Self::#variant_name { #(#bindings,)* .. } => {
+                    format!("{{{}: {}}}", #wire_name, #value)
+                }
             }
         }
-    } else {
-        // Fallback for unsupported types
-        quote! {
-            format!("{}: {}", stringify!(#field_str), "Unsupported type")
-        }
     }
 }
 
-#[proc_macro_derive(Serializable)]
+#[proc_macro_derive(Serializable, attributes(normalization))]
 pub fn derive_serializable(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as DeriveInput);
+    if let Err(e) = check_renames(&input.data) {
+        return e.to_compile_error().into();
+    }
     let name = &input.ident;
-
-    let gen = match &input.data {
+    let version = type_version(&input.attrs);
+    let generics = add_bounds(
+        &input.generics,
+        syn::parse_quote!(::normalization::Serializable),
+    );
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+
+    let (serialization_logic, schema_names, schema_types) = match &input.data {
         Data::Struct(DataStruct {
-            fields: Fields::Named(FieldsNamed { named, .. }),
+            fields: Fields::Named(fields),
             ..
         }) => {
-            let serialization_logic = named.iter().map(|f| {
-                let field_name = &f.ident;
-                match &f.ty {
-                    Path(type_path) => generate_serialization_for_type(field_name, type_path),
-                    _ => panic!("Unsupported type!"),
+            let written = written_fields(fields);
+            let field_names = written.iter().map(|(field_name, _)| field_name);
+            let serialize_fields = serialize_named_fields(fields, version);
+            let logic = quote! {
+                let Self { #(#field_names,)* .. } = self;
+                #serialize_fields
+            };
+            let names: Vec<String> = written
+                .iter()
+                .map(|(_, wire_name)| wire_name.clone())
+                .collect();
+            let types: Vec<String> = fields
+                .named
+                .iter()
+                .filter(|f| !Options::of_field(f).skip)
+                .map(|f| type_name(&f.ty))
+                .collect();
+            (logic, names, types)
+        }
+        Data::Enum(DataEnum { variants, .. }) => {
+            let arms = variants
+                .iter()
+                .map(|variant| serialize_variant(variant, version));
+            let logic = quote! {
+                match self {
+                    #(#arms)*
                 }
-            });
+            };
+            let names: Vec<String> = variants
+                .iter()
+                .map(|variant| wire_name(&variant.ident, &Options::of_variant(variant)))
+                .collect();
+            let types: Vec<String> = variants
+                .iter()
+                .map(|variant| match &variant.fields {
+                    Fields::Unit => String::new(),
+                    fields => type_name(fields),
+                })
+                .collect();
+            (logic, names, types)
+        }
+        _ => panic!("Only named structs and enums are supported!"),
+    };
 
-            quote! {
-                impl #name {
-                    pub fn serialize(&self) -> String {
-                        let parts = vec![#(#serialization_logic),*];
-                        format!("{{{}}}", parts.join(","))
-                    }
-                }
+    let gen = quote! {
+        impl #impl_generics ::normalization::Serializable for #name #type_generics #where_clause {
+            fn serialize(&self) -> String {
+                #serialization_logic
+            }
+        }
+
+        impl #impl_generics #name #type_generics #where_clause {
+            // Inherent too, so callers don't need the trait in scope.
+            pub fn serialize(&self) -> String {
+                ::normalization::Serializable::serialize(self)
+            }
+
+            // Field names and types in declaration order, or variant names
+            // and what they carry, for service reflection.
+            pub fn schema() -> Vec<(&'static str, &'static str)> {
+                vec![#((#schema_names, #schema_types)),*]
             }
         }
-        _ => panic!("Only named structs are supported!"),
     };
 
     gen.into()
@@ -254,417 +452,177 @@ pub fn derive_serializable(input: TokenStream) -> TokenStream {
 //     }
 // }
 
-// Function to check if a type is a basic type like i32, bool, or String
-fn is_basic_type(ty: &syn::Type) -> bool {
-    match ty {
-        syn::Type::Path(type_path) => {
-            if let Some(ident) = type_path.path.get_ident() {
-                match ident.to_string().as_str() {
-                    "i32" | "u32" | "u64" | "usize" | "bool" | "String" => true,
-                    _ => false,
-                }
-            } else {
-                false
+// Statements binding each named field, by name, from `input`, a &str holding
+// `{field: value,...}`. Fields that aren't ours are ignored.
+fn deserialize_named_fields(
+    fields: &FieldsNamed,
+    input: proc_macro2::TokenStream,
+    version: Option<u32>,
+) -> proc_macro2::TokenStream {
+    let mut versioned = false;
+    let bindings: Vec<_> = fields.named.iter().map(|f| {
+        let options = Options::of_field(f);
+        let field_name = f.ident.as_ref().unwrap();
+        let field_type = &f.ty;
+        if options.skip {
+            return quote! { let #field_name: #field_type = Default::default(); };
+        }
+        let wire_name = wire_name(field_name, &options);
+        let field_type_str = type_name(field_type);
+        let missing = quote! { return Err(::normalization::NormalizationError::missing_field(#wire_name, #field_type_str, #input)) };
+        let missing = match options.since {
+            _ if options.default => quote! { Default::default() },
+            Some(since) if since > version.unwrap_or(1) => {
+                panic!("{} is since version {}, past the type's version", field_name, since)
             }
+            Some(since) => {
+                versioned = true;
+                quote! { if peer_version < #since { Default::default() } else { #missing } }
+            }
+            None => missing,
+        };
+        quote! {
+            let #field_name: #field_type = match map.get(#wire_name) {
+                Some(field_value) => <#field_type as ::normalization::Deserializable>::deserialize(field_value)
+                    .map_err(|e| e.within(concat!(".", #wire_name), #input, field_value))?,
+                None => #missing,
+            };
+        }
+    }).collect();
+    let peer_version = if versioned {
+        quote! {
+            let peer_version: u32 = match map.get(#VERSION_KEY) {
+                Some(field_value) => <u32 as ::normalization::Deserializable>::deserialize(field_value)
+                    .map_err(|e| e.within(#VERSION_KEY, #input, field_value))?,
+                None => 1,
+            };
         }
-        _ => false,
+    } else {
+        quote! {}
+    };
+    quote! {
+        let map: std::collections::HashMap<&str, &str> =
+            ::normalization::text::split_entries(#input)?.into_iter().collect();
+        #peer_version
+        #(#bindings)*
     }
 }
 
-// Main function to generate field deserialization logic
-fn generate_field_deserialization(
-    field_name: &Option<Ident>,
-    field_type: &TypePath,
-) -> proc_macro2::TokenStream {
-    let field_name_str = match field_name {
-        Some(ident) => ident.to_string(),
-        None => panic!("Field has no name"),
-    };
-    let field_str = quote::format_ident!("{}", field_name_str);
-
-    if is_basic_type(&Type::Path(field_type.clone())) {
-        // Basic type handling
-        match field_type.path.segments.last().unwrap().ident.to_string().as_str() {
-            "i32" | "u32" | "u64" | "usize" | "bool" => {
-                quote! {
-                    println!("1: {:?}", map);
-                    println!("2: {:?}", #field_name_str);
-                    let #field_str: #field_type = map.get(#field_name_str)
-                        .ok_or(NormalizationError::MissingField)?
-                        .parse()
-                        .map_err(|_| NormalizationError::ParseFailure)?;
-                }
-            }
-            "String" => {
-                // TODO:  The string (second element) is not showing up in the map for vectors of structs.
-                quote! {
-                    println!("3: {:?}", map);
-                    println!("4: {:?}", #field_name_str);
-                    let raw_string = map.get(#field_name_str)
-                        .ok_or(NormalizationError::MissingField)?;
-                    println!("5: {:?}", raw_string);
-                    let #field_str: String = raw_string.trim_matches('\"').replace("\\:", ":")
-                        .replace("\\\"", "\"").replace("\\\\", "\\").replace("\\,", ",");
-                }
-                // quote! {
-                //     let #field_str = map.get(#field_name_str)
-                //         .ok_or(NormalizationError::MissingField)?
-                //         .to_string();
-                // }
+// A match arm reading the variant's data from `value`.
+fn deserialize_variant(variant: &Variant, version: Option<u32>) -> proc_macro2::TokenStream {
+    let variant_name = &variant.ident;
+    let variant_name_str = wire_name(variant_name, &Options::of_variant(variant));
+    let read = match &variant.fields {
+        Fields::Unit => return quote! {},
+        Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+            let field_type = &fields.unnamed[0].ty;
+            quote! {
+                Ok(Self::#variant_name(<#field_type as ::normalization::Deserializable>::deserialize(value)?))
             }
-            _ => unreachable!("Unsupported type!"),
         }
-    } else if let Some(segment) = field_type.path.segments.last() {
-        if segment.ident == "Vec" {
-            if let PathArguments::AngleBracketed(inner_args) = &segment.arguments {
-                if let Some(GenericArgument::Type(inner_type)) = inner_args.args.first() {
-                    let inner_type_path = if let Type::Path(type_path) = inner_type {
-                        type_path
-                    } else {
-                        panic!("Unsupported inner type in Vec");
-                    };
-
-                    let deserialize_vector = if is_basic_type(inner_type) {
-                        match inner_type_path.path.segments.last().unwrap().ident.to_string().as_str() {
-                            "i32" | "u32" | "u64" | "usize" | "bool" => {
-                                // Direct parsing for i32, u32, usize, and bool
-                                quote! {
-                                    println!("6: Serialized vec: {:?}", serialized_vec);
-                                    let elements = serialized_vec
-                                        .split(',')
-                                        .map(|s| s.trim().parse::<#inner_type_path>()
-                                            .map_err(|_| NormalizationError::ParseFailure))
-                                        .collect::<Result<Vec<_>, _>>()?;
-                                    }
-                            }
-                            "String" => {
-                                // Special handling for String to correctly unescape characters
-                                quote! {
-                                    println!("7: Serialized vec: {:?}", serialized_vec);
-                                    let serialized_vec = map.get(#field_name_str)
-                                        .ok_or(NormalizationError::MissingField)?
-                                        .trim_start_matches('[')
-                                        .trim_end_matches(']');
-                                    println!("8: {:?}", serialized_vec);
-
-                                    let mut in_quotes = false;
-                                    let mut escape_next_char = false;
-                                    let mut element_start = 0;
-                                    let mut chars = serialized_vec.chars().enumerate();
-                                    println!("9: {:?}", chars);
-
-                                    println!("10: {:?}", serialized_vec.split(','));
-                                    let elements = serialized_vec
-                                        .split(',')
-                                        .map(|s| s.trim_matches('\"').replace("\\:", ":")
-                                            .replace("\\\"", "\"").replace("\\\\", "\\").replace("\\,", ","))
-                                        .collect::<Vec<String>>();
-                                    println!("11: Elements: {:?}", elements);
-
-                                    // let #field_str: Vec<String> = chars.by_ref()
-                                    //     .filter_map(|(i, c)| {
-                                    //         if escape_next_char {
-                                    //             escape_next_char = false;
-                                    //             None
-                                    //         } else {
-                                    //             match c {
-                                    //                 '\\' => {
-                                    //                     escape_next_char = true;
-                                    //                     None
-                                    //                 }
-                                    //                 '"' => {
-                                    //                     in_quotes = !in_quotes;
-                                    //                     None
-                                    //                 }
-                                    //                 ',' if !in_quotes => {
-                                    //                     let element = serialized_vec[element_start..i].trim().to_string();
-                                    //                     element_start = i + 1;
-                                    //                     Some(element)
-                                    //                 }
-                                    //                 _ => None,
-                                    //             }
-                                    //         }
-                                    //     })
-                                    // .chain(std::iter::once(serialized_vec[element_start..].trim().to_string()))
-                                    //     .collect();
-                                    }
-                                // quote! {
-                                //     let elements = serialized_vec
-                                //         .split(',')
-                                //         .map(|s| s.trim_matches('\"').replace("\\:", ":")
-                                //             .replace("\\\"", "\"").replace("\\\\", "\\").replace("\\,", ","))
-                                //         .collect::<Vec<String>>();
-                                //     }
-                            }
-                            _ => unreachable!("Unsupported type!"),
-                        }
-
-                        // // Deserialize vector of basic types
-                        // quote! {
-                        //     println!("{:?}", map);
-                        //     println!("{:?}", #field_name_str);
-                        //     let elements = serialized_vec
-                        //         .split(',')
-                        //         .map(|s| s.parse::<#inner_type_path>()
-                        //             .map_err(|_| NormalizationError::ParseFailure))
-                        //         .collect::<Result<Vec<_>, _>>()?;
-                        // }
-                    } else {
-                        // Deserialize vector of custom structs
-                        quote! {
-                            println!("12: Serialized vec: {:?}", serialized_vec);
-                            let mut start = 0;
-                            let mut nesting_level = 0;
-                            let mut is_struct_start = false;
-                            let mut struct_strs = Vec::new();
-                            let chars = serialized_vec.chars().enumerate();
-
-                            for (i, c) in chars {
-                                match c {
-                                    '{' => {
-                                        if nesting_level == 0 {
-                                            start = i; // Start of a new struct
-                                            is_struct_start = true;
-                                        }
-                                        nesting_level += 1;
-                                    }
-                                    '}' => {
-                                        nesting_level -= 1;
-                                        if nesting_level == 0 && is_struct_start {
-                                            // End of a struct
-                                            is_struct_start = false;
-                                            struct_strs.push(&serialized_vec[start..=i]);
-                                        }
-                                    }
-                                    _ => (),
-                                }
-                            }
-
-                            println!("Struct strs: {:?}", struct_strs);
-                            // let #field_str: Vec<#inner_type_path> = struct_strs.into_iter()
-                            //     .filter_map(|struct_str| {
-                            //         match #inner_type_path::deserialize(struct_str) {
-                            //             Ok(struct_data) => Some(struct_data),
-                            //             Err(_) => None,
-                            //         }
-                            //     })
-                            // .collect();
-                            println!("Struct data: {:?}", struct_strs.clone().into_iter()
-                                .filter_map(|struct_str| {
-                                    match #inner_type_path::deserialize(&struct_str) {
-                                        Ok(struct_data) => Some(struct_data),
-                                        Err(_) => None,
-                                    }
-                                }));
-                            let elements = struct_strs.clone()
-                                .into_iter()
-                                .map(|s| #inner_type_path::deserialize(s))
-                                .collect::<Result<Vec<_>, _>>()?;
-                            // TODO:  Need to get from struct_strs to calling deserialize on each
-                            // of them and returning a vector of the correct struct type.
-                            // let elements = serialized_vec
-                            //     .split(',')
-                            //     .map(|s| #inner_type_path::deserialize(s))
-                            //     .collect::<Result<Vec<_>, _>>()?;
-                        // quote! {
-                        //     println!("12: Serialized vec: {:?}", serialized_vec);
-                        //     // TODO:  There is a problem deserializing here.
-                        //     println!("12.2:  Split elements: {:?}", serialized_vec.trim_start_matches('{').trim_end_matches('}').split(','));
-                        //     let elements = serialized_vec
-                        //         .trim_start_matches('{')
-                        //         .trim_end_matches('}')
-                        //         .split(',')
-                        //         .map(|s| #inner_type_path::deserialize(s))
-                        //         .collect::<Result<Vec<_>, _>>()?;
-                        //     println!("12.5:  Elements: {:?}", elements);
-                        }
-                    };
-                    // println!("Deserialize vector: {:?}", deserialize_vector);
-                    quote! {
-                        let serialized_vec = map.get(#field_name_str)
-                            .ok_or(NormalizationError::MissingField)?
-                            .trim_start_matches('[')
-                            .trim_end_matches(']');
-                        println!("13: Serialized vec: {:?}", serialized_vec);
-                        #deserialize_vector
-                        let #field_str: Vec<#inner_type_path> = elements;
-                    }
-                } else {
-                    panic!("Vec type without inner type");
-                }
-            } else {
-                panic!("Unsupported field type!");
+        Fields::Unnamed(fields) => {
+            let field_types = fields.unnamed.iter().map(|f| &f.ty);
+            let bindings: Vec<Ident> = (0..fields.unnamed.len())
+                .map(|i| quote::format_ident!("field{}", i))
+                .collect();
+            quote! {
+                let (#(#bindings,)*) = <(#(#field_types,)*) as ::normalization::Deserializable>::deserialize(value)?;
+                Ok(Self::#variant_name(#(#bindings),*))
             }
-        } else {
-            // Handling for non-Vec custom types
-            // TODO:  Parse structs with multiple members correctly so they can be handled here.
+        }
+        Fields::Named(fields) => {
+            let field_names = fields.named.iter().map(|f| &f.ident);
+            let bindings = deserialize_named_fields(fields, quote!(value), version);
             quote! {
-                let serialized_struct = map.get(#field_name_str)
-                    .ok_or(NormalizationError::MissingField)?;
-                println!("14: Serialized struct: {:?}", serialized_struct);
-                let #field_str = #field_type::deserialize(serialized_struct)?;
+                #bindings
+                Ok(Self::#variant_name { #(#field_names),* })
             }
         }
-    } else {
-        panic!("Unsupported field type!");
+    };
+    quote! {
+        #variant_name_str => (|| -> Result<Self, ::normalization::NormalizationError> { #read })()
+            .map_err(|e| e.within(concat!(".", #variant_name_str), input, value)),
     }
 }
 
-#[proc_macro_derive(Deserializable)]
+#[proc_macro_derive(Deserializable, attributes(normalization))]
 pub fn derive_deserializable(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as DeriveInput);
+    if let Err(e) = check_renames(&input.data) {
+        return e.to_compile_error().into();
+    }
     let name = &input.ident;
-
-    match &input.data {
+    let name_str = name.to_string();
+    let version = type_version(&input.attrs);
+    let generics = add_bounds(
+        &input.generics,
+        syn::parse_quote!(::normalization::Deserializable),
+    );
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+
+    let deserialization_logic = match &input.data {
         Data::Struct(DataStruct {
             fields: Fields::Named(fields),
             ..
         }) => {
-            let deserialize_fields = fields.named.iter().map(|f| {
-                let field_name = &f.ident;
-                match &f.ty {
-                    Path(type_path) => generate_field_deserialization(field_name, type_path),
-                    _ => panic!("Unsupported type!"),
-                }
-            });
-
+            let bindings = deserialize_named_fields(fields, quote!(input), version);
             let field_names = fields.named.iter().map(|f| &f.ident);
-
-            let expanded = quote! {
-                impl #name {
-                    pub fn deserialize(input: &str) -> Result<Self, NormalizationError> {
-                        let trimmed = input.trim_matches(|c| c == '{' || c == '}');
-                        let mut parts: Vec<&str> = Vec::new();
-                        let mut start = 0;
-                        let mut escaped = false;
-                        let mut in_vector = false;
-                        let mut nesting_level = 0;  // Track nesting level for structs
-
-                        for (i, char) in trimmed.chars().enumerate() {
-                            if escaped {
-                                escaped = false;
-                                continue;
-                            }
-
-                            match char {
-                                '\\' => escaped = true,
-                                '[' | '{' => nesting_level += 1,
-                                ']' | '}' => nesting_level -= 1,
-                                ',' if nesting_level == 0 => {
-                                    // Split on comma only at the top level (outside of any nested structures)
-                                    parts.push(&trimmed[start..i]);
-                                    start = i + 1;
-                                }
-                                _ => (),
-                            }
-                        }
-                        parts.push(&trimmed[start..]);
-
-                        let mut map = std::collections::HashMap::new();
-                        for part in parts.iter() {
-                            let kv: Vec<&str> = part.splitn(2, ':').map(str::trim).collect();
-                            if kv.len() == 2 {
-                                map.insert(kv[0], kv[1]);
-                            }
-                        }
-
-                        #(#deserialize_fields)*
-                        Ok(Self { #(#field_names),* })
-                    }
+            quote! {
+                #bindings
+                Ok(Self { #(#field_names),* })
+            }
+        }
+        Data::Enum(DataEnum { variants, .. }) => {
+            let unit_names: Vec<&Ident> = variants
+                .iter()
+                .filter(|variant| matches!(variant.fields, Fields::Unit))
+                .map(|variant| &variant.ident)
+                .collect();
+            let unit_names_str = variants
+                .iter()
+                .filter(|variant| matches!(variant.fields, Fields::Unit))
+                .map(|variant| wire_name(&variant.ident, &Options::of_variant(variant)));
+            let arms = variants
+                .iter()
+                .map(|variant| deserialize_variant(variant, version));
+            let expected = format!("a variant of {}", name_str);
+            quote! {
+                match input.trim() {
+                    #(#unit_names_str => return Ok(Self::#unit_names),)*
+                    _ => (),
                 }
-            };
+                let entries = ::normalization::text::split_entries(input)
+                    .map_err(|e| ::normalization::NormalizationError { expected: #expected.to_string(), ..e })?;
+                let (variant, value) = match entries[..] {
+                    [entry] => entry,
+                    _ => return Err(::normalization::NormalizationError::invalid_format(#expected, input, 0)),
+                };
+                match variant {
+                    #(#arms)*
+                    _ => Err(::normalization::NormalizationError::invalid_format(#expected, variant, 0).within("", input, variant)),
+                }
+            }
+        }
+        _ => panic!("Only named structs and enums are supported!"),
+    };
 
-            // let expanded = quote! {
-            //     impl #name {
-            //         pub fn deserialize(input: &str) -> Result<Self, NormalizationError> {
-            //             let trimmed = input.trim_matches(|c| c == '{' || c == '}');
-            //             let mut parts: Vec<&str> = Vec::new();
-            //             let mut start = 0;
-            //             let mut escaped = false;
-            //             let mut in_vector = false;
-            //             let mut nesting_level = 0;  // Added for tracking nesting level
-
-            //             for (i, char) in trimmed.chars().enumerate() {
-            //                 if escaped {
-            //                     escaped = false;
-            //                     continue;
-            //                 }
-
-            //                 match char {
-            //                     '\\' => escaped = true,
-            //                     '[' => in_vector = true,
-            //                     ']' => in_vector = false,
-            //                     '{' => nesting_level += 1,  // Increment nesting level
-            //                     '}' => nesting_level -= 1,  // Decrement nesting level
-            //                     ',' if !in_vector && nesting_level == 0 => {
-            //                         // Split on comma only if not in a vector and not in a nested struct
-            //                         parts.push(&trimmed[start..i]);
-            //                         start = i + 1;
-            //                     }
-            //                     _ => (),
-            //                 }
-            //             }
-            //             parts.push(&trimmed[start..]);
-
-            //             let mut map = std::collections::HashMap::new();
-            //             for part in parts.iter() {
-            //                 let kv: Vec<&str> = part.splitn(2, ':').map(str::trim).collect();
-            //                 if kv.len() == 2 {
-            //                     map.insert(kv[0], kv[1]);
-            //                 }
-            //             }
-
-            //             #(#deserialize_fields)*
-            //             Ok(Self { #(#field_names),* })
-            //         }
-            //     }
-            // };
-            // let expanded = quote! {
-            //     impl #name {
-            //         pub fn deserialize(input: &str) -> Result<Self, NormalizationError> {
-            //             let trimmed = input.trim_matches(|c| c == '{' || c == '}');
-            //             let mut parts: Vec<&str> = Vec::new();
-            //             let mut start = 0;
-            //             let mut escaped = false;
-            //             let mut in_vector = false;
-
-            //             for (i, char) in trimmed.chars().enumerate() {
-            //                 if escaped {
-            //                     escaped = false;
-            //                     continue;
-            //                 }
-
-            //                 match char {
-            //                     '\\' => escaped = true,
-            //                     '[' => in_vector = true,
-            //                     ']' => in_vector = false,
-            //                     ',' if !in_vector => {
-            //                         parts.push(&trimmed[start..i]);
-            //                         start = i + 1;
-            //                     }
-            //                     _ => (),
-            //                 }
-            //             }
-            //             parts.push(&trimmed[start..]);
-
-            //             let mut map = std::collections::HashMap::new();
-            //             for part in parts.iter() {
-            //                 let kv: Vec<&str> = part.splitn(2, ':').map(str::trim).collect();
-            //                 if kv.len() == 2 {
-            //                     map.insert(kv[0], kv[1]);
-            //                 }
-            //             }
-
-            //             #(#deserialize_fields)*
-            //             Ok(Self { #(#field_names),* })
-            //         }
-            //     }
-            // };
-
-            expanded.into()
+    let expanded = quote! {
+        impl #impl_generics ::normalization::Deserializable for #name #type_generics #where_clause {
+            fn deserialize(input: &str) -> Result<Self, ::normalization::NormalizationError> {
+                (|| -> Result<Self, ::normalization::NormalizationError> { #deserialization_logic })()
+                    .map_err(|e| e.in_type(#name_str))
+            }
         }
-        _ => panic!("Only named structs are supported!"),
-    }
+
+        impl #impl_generics #name #type_generics #where_clause {
+            // Inherent too, so callers don't need the trait in scope.
+            pub fn deserialize(input: &str) -> Result<Self, ::normalization::NormalizationError> {
+                <Self as ::normalization::Deserializable>::deserialize(input)
+            }
+        }
+    };
+
+    expanded.into()
 }
 
 // #[proc_macro_derive(Deserializable)]
@@ -0,0 +1,120 @@
+use std::fmt;
+
+// How many characters of the input an error quotes.
+const SNIPPET_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    MissingField,
+    InvalidFormat,
+    ParseFailure,
+}
+
+// Where in the input deserialization failed and what it expected there, e.g.
+// `Sample.nested.number: expected i32 at byte 35, found "x}}"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizationError {
+    pub kind: ErrorKind,
+    // The outermost struct or enum being read, if any, and the path to the
+    // value from there: `.samples[1].nested.number`.
+    pub type_name: String,
+    pub path: String,
+    pub expected: String,
+    // Bytes from the start of the input.
+    pub offset: usize,
+    pub snippet: String,
+}
+
+impl NormalizationError {
+    // Found something other than `expected` at byte `at` of `input`.
+    pub fn new(kind: ErrorKind, expected: impl Into<String>, input: &str, at: usize) -> Self {
+        let at = at.min(input.len());
+        let start = (0..=at)
+            .rev()
+            .find(|i| input.is_char_boundary(*i))
+            .unwrap_or(0);
+        NormalizationError {
+            kind,
+            type_name: String::new(),
+            path: String::new(),
+            expected: expected.into(),
+            offset: start,
+            snippet: input[start..].chars().take(SNIPPET_LEN).collect(),
+        }
+    }
+
+    pub fn invalid_format(expected: impl Into<String>, input: &str, at: usize) -> Self {
+        NormalizationError::new(ErrorKind::InvalidFormat, expected, input, at)
+    }
+
+    pub fn parse_failure(expected: impl Into<String>, input: &str) -> Self {
+        let at = input.len() - input.trim_start().len();
+        NormalizationError::new(ErrorKind::ParseFailure, expected, input, at)
+    }
+
+    // `input` is the struct the field is missing from.
+    pub fn missing_field(field: &str, expected: impl Into<String>, input: &str) -> Self {
+        let mut err = NormalizationError::new(ErrorKind::MissingField, expected, input, 0);
+        err.path = format!(".{}", field);
+        err
+    }
+
+    // For an error reading `value`, a part of `input` found at `segment`
+    // (`.field`, `[2]`): makes the path and offset relative to `input`.
+    pub fn within(mut self, segment: &str, input: &str, value: &str) -> Self {
+        self.path = format!("{}{}", segment, self.path);
+        self.offset += offset_of(input, value);
+        self
+    }
+
+    // Names the struct or enum the path starts from.
+    pub fn in_type(mut self, type_name: &str) -> Self {
+        self.type_name = type_name.to_string();
+        self
+    }
+
+    // `Sample.nested.number`
+    pub fn location(&self) -> String {
+        format!("{}{}", self.type_name, self.path)
+    }
+}
+
+// Where `part`, a slice of `input`, starts in it.
+pub(crate) fn offset_of(input: &str, part: &str) -> usize {
+    let start = input.as_ptr() as usize;
+    let at = part.as_ptr() as usize;
+    if at >= start && at <= start + input.len() {
+        at - start
+    } else {
+        0
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::MissingField => write!(f, "missing field"),
+            ErrorKind::InvalidFormat => write!(f, "invalid format"),
+            ErrorKind::ParseFailure => write!(f, "parse failure"),
+        }
+    }
+}
+
+impl fmt::Display for NormalizationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = self.location();
+        if !location.is_empty() {
+            write!(f, "{}: ", location)?;
+        }
+        match self.kind {
+            ErrorKind::MissingField => write!(f, "missing field of type {}", self.expected),
+            _ => write!(
+                f,
+                "{}: expected {} at byte {}, found {:?}",
+                self.kind, self.expected, self.offset, self.snippet
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NormalizationError {}
@@ -0,0 +1,206 @@
+use crate::text::{quote, split_entries, split_list, unquote};
+use crate::{Deserializable, NormalizationError, Serializable};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::iter::FromIterator;
+
+// Numbers and bools are written as Display writes them.
+macro_rules! impl_primitive {
+    ($($ty:ty),*) => {
+        $(
+            impl Serializable for $ty {
+                fn serialize(&self) -> String {
+                    self.to_string()
+                }
+            }
+
+            impl Deserializable for $ty {
+                fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+                    input
+                        .trim()
+                        .parse()
+                        .map_err(|_| NormalizationError::parse_failure(stringify!($ty), input))
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64, bool);
+
+impl Serializable for str {
+    fn serialize(&self) -> String {
+        quote(self)
+    }
+}
+
+impl Serializable for String {
+    fn serialize(&self) -> String {
+        quote(self)
+    }
+}
+
+impl Deserializable for String {
+    fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+        unquote(input)
+    }
+}
+
+impl<T: Serializable + ?Sized> Serializable for &T {
+    fn serialize(&self) -> String {
+        (**self).serialize()
+    }
+}
+
+impl<T: Serializable + ?Sized> Serializable for Box<T> {
+    fn serialize(&self) -> String {
+        (**self).serialize()
+    }
+}
+
+impl<T: Deserializable> Deserializable for Box<T> {
+    fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+        T::deserialize(input).map(Box::new)
+    }
+}
+
+// `null`, or the value.
+impl<T: Serializable> Serializable for Option<T> {
+    fn serialize(&self) -> String {
+        match self {
+            Some(value) => value.serialize(),
+            None => "null".to_string(),
+        }
+    }
+}
+
+impl<T: Deserializable> Deserializable for Option<T> {
+    fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+        match input.trim() {
+            "null" => Ok(None),
+            value => T::deserialize(value).map(Some),
+        }
+    }
+}
+
+fn serialize_list<'a, T: Serializable + 'a>(items: impl Iterator<Item = &'a T>) -> String {
+    let items: Vec<String> = items.map(Serializable::serialize).collect();
+    format!("[{}]", items.join(","))
+}
+
+// `[a,b,c]`
+impl<T: Serializable> Serializable for [T] {
+    fn serialize(&self) -> String {
+        serialize_list(self.iter())
+    }
+}
+
+impl<T: Serializable> Serializable for Vec<T> {
+    fn serialize(&self) -> String {
+        serialize_list(self.iter())
+    }
+}
+
+impl<T: Deserializable> Deserializable for Vec<T> {
+    fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+        split_list(input)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| {
+                T::deserialize(item).map_err(|e| e.within(&format!("[{}]", i), input, item))
+            })
+            .collect()
+    }
+}
+
+// `{"key": value,...}`, sorted by key so equal maps are written the same.
+fn serialize_map<'a, K, V>(entries: impl Iterator<Item = (&'a K, &'a V)>) -> String
+where
+    K: Serializable + 'a,
+    V: Serializable + 'a,
+{
+    let mut entries: Vec<(String, String)> = entries
+        .map(|(key, value)| (key.serialize(), value.serialize()))
+        .collect();
+    entries.sort();
+    let entries: Vec<String> = entries
+        .into_iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect();
+    format!("{{{}}}", entries.join(","))
+}
+
+fn deserialize_map<K, V, M>(input: &str) -> Result<M, NormalizationError>
+where
+    K: Deserializable,
+    V: Deserializable,
+    M: FromIterator<(K, V)>,
+{
+    split_entries(input)?
+        .into_iter()
+        .map(|(key, value)| {
+            let segment = format!("[{}]", key);
+            Ok((
+                K::deserialize(key).map_err(|e| e.within(&segment, input, key))?,
+                V::deserialize(value).map_err(|e| e.within(&segment, input, value))?,
+            ))
+        })
+        .collect()
+}
+
+impl<K: Serializable, V: Serializable> Serializable for HashMap<K, V> {
+    fn serialize(&self) -> String {
+        serialize_map(self.iter())
+    }
+}
+
+impl<K: Deserializable + Eq + Hash, V: Deserializable> Deserializable for HashMap<K, V> {
+    fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+        deserialize_map(input)
+    }
+}
+
+impl<K: Serializable, V: Serializable> Serializable for BTreeMap<K, V> {
+    fn serialize(&self) -> String {
+        serialize_map(self.iter())
+    }
+}
+
+impl<K: Deserializable + Ord, V: Deserializable> Deserializable for BTreeMap<K, V> {
+    fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+        deserialize_map(input)
+    }
+}
+
+// Tuples are written as lists of their elements.
+macro_rules! impl_tuple {
+    ($len:expr, $($name:ident $index:tt),+) => {
+        impl<$($name: Serializable),+> Serializable for ($($name,)+) {
+            fn serialize(&self) -> String {
+                let elements = [$(self.$index.serialize()),+];
+                format!("[{}]", elements.join(","))
+            }
+        }
+
+        impl<$($name: Deserializable),+> Deserializable for ($($name,)+) {
+            fn deserialize(input: &str) -> Result<Self, NormalizationError> {
+                let items = split_list(input)?;
+                if items.len() != $len {
+                    let expected = format!("a list of {} items", $len);
+                    return Err(NormalizationError::invalid_format(expected, input, 0));
+                }
+                Ok(($(
+                    $name::deserialize(items[$index])
+                        .map_err(|e| e.within(concat!(".", $index), input, items[$index]))?,
+                )+))
+            }
+        }
+    };
+}
+
+impl_tuple!(1, A 0);
+impl_tuple!(2, A 0, B 1);
+impl_tuple!(3, A 0, B 1, C 2);
+impl_tuple!(4, A 0, B 1, C 2, D 3);
+impl_tuple!(5, A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(6, A 0, B 1, C 2, D 3, E 4, F 5);
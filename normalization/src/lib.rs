@@ -1,11 +1,10 @@
 pub use normalization_macros::*;
 
-#[derive(Debug, PartialEq)]
-pub enum NormalizationError {
-    MissingField,
-    InvalidFormat,
-    ParseFailure,
-}
+pub mod error;
+mod impls;
+pub mod text;
+
+pub use error::{ErrorKind, NormalizationError};
 
 pub trait Serializable {
     fn serialize(&self) -> String;
@@ -0,0 +1,338 @@
+// The text form shared by the derives and the impls for standard types:
+//
+//   value  = word | string | list | map
+//   word   = 1*( letter | digit | "_" | "-" | "+" | "." )
+//   string = '"' *( any char but '"' and '\' | '\' any char ) '"'
+//   list   = "[" [ value *( "," value ) ] "]"
+//   map    = "{" [ entry *( "," entry ) ] "}"
+//   entry  = value ":" value
+//
+// with any whitespace between tokens. Words are numbers, bools, `null`,
+// field names and unit variants; structs and enums with data are maps, keyed
+// by field or variant name.
+// Inside a string a backslash makes the next character literal. Writers
+// escape \ : " and , which readers from before this parser needed.
+//
+// Deserialize impls are handed the text of one value and parse it one level
+// deep, passing the text of each part on to the part's own impl. Nested values
+// are only scanned for their closing bracket on the way, so a payload is read
+// about as many times as it is deep, never more than MAX_DEPTH. Errors are
+// positioned in the text each function is given.
+use crate::error::offset_of;
+use crate::NormalizationError;
+
+// Deeper input is rejected rather than risk the stack.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    Open(char),
+    Close(char),
+    Comma,
+    Colon,
+    Word(&'a str),
+    // Quotes and escapes included.
+    String(&'a str),
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '+' | '.')
+}
+
+// Tokens with the byte offset each starts at.
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Tokenizer { input, pos: 0 }
+    }
+
+    fn string(&self, start: usize) -> Result<&'a str, NormalizationError> {
+        let mut escaped = false;
+        for (i, c) in self.input[start + 1..].char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Ok(&self.input[start..start + 1 + i + 1]),
+                _ => (),
+            }
+        }
+        Err(NormalizationError::invalid_format(
+            "a closing quote",
+            self.input,
+            start,
+        ))
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<(usize, Token<'a>), NormalizationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.input[self.pos..];
+        let start = self.pos + (rest.len() - rest.trim_start().len());
+        let c = self.input[start..].chars().next()?;
+        let token = match c {
+            '[' | '{' => Token::Open(c),
+            ']' | '}' => Token::Close(c),
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '"' => match self.string(start) {
+                Ok(string) => Token::String(string),
+                Err(e) => {
+                    self.pos = self.input.len();
+                    return Some(Err(e));
+                }
+            },
+            _ if is_word_char(c) => {
+                let len = self.input[start..]
+                    .find(|c| !is_word_char(c))
+                    .unwrap_or(self.input.len() - start);
+                Token::Word(&self.input[start..start + len])
+            }
+            _ => {
+                self.pos = self.input.len();
+                return Some(Err(NormalizationError::invalid_format(
+                    "a value", self.input, start,
+                )));
+            }
+        };
+        self.pos = start
+            + match token {
+                Token::Word(text) | Token::String(text) => text.len(),
+                _ => c.len_utf8(),
+            };
+        Some(Ok((start, token)))
+    }
+}
+
+// A value parsed one level deep: the items of a list or map are the text
+// they were read from, matched up to their closing bracket but not parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node<'a> {
+    pub text: &'a str,
+    pub kind: NodeKind<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeKind<'a> {
+    Word,
+    String,
+    List(Vec<&'a str>),
+    Map(Vec<(&'a str, &'a str)>),
+}
+
+fn closer(open: char) -> char {
+    if open == '[' {
+        ']'
+    } else {
+        '}'
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Tokenizer<'a>,
+    peeked: Option<(usize, Token<'a>)>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self, expected: &str) -> Result<(usize, Token<'a>), NormalizationError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.tokens.next().unwrap_or_else(|| {
+                Err(NormalizationError::invalid_format(
+                    expected,
+                    self.input,
+                    self.input.len(),
+                ))
+            }),
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<Token<'a>>, NormalizationError> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next().transpose()?;
+        }
+        Ok(self.peeked.map(|(_, token)| token))
+    }
+
+    fn too_deep(&self, at: usize) -> NormalizationError {
+        let expected = format!("at most {} levels of nesting", MAX_DEPTH);
+        NormalizationError::invalid_format(expected, self.input, at)
+    }
+
+    // The rest of a list or map after its opening bracket: the items, and
+    // where the closing bracket ends.
+    fn items<T>(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<T, NormalizationError>,
+    ) -> Result<(Vec<T>, usize), NormalizationError> {
+        let mut items = Vec::new();
+        if self.peek()? == Some(Token::Close(close)) {
+            let (at, _) = self.next("")?;
+            return Ok((items, at + 1));
+        }
+        loop {
+            items.push(item(self)?);
+            let expected = format!("`,` or `{}`", close);
+            match self.next(&expected)? {
+                (_, Token::Comma) => (),
+                (at, Token::Close(c)) if c == close => return Ok((items, at + 1)),
+                (at, _) => {
+                    return Err(NormalizationError::invalid_format(expected, self.input, at))
+                }
+            }
+        }
+    }
+
+    // The text of one nested value. Only its brackets are checked here; the
+    // rest is left to whichever impl is handed the text.
+    fn skip(&mut self) -> Result<&'a str, NormalizationError> {
+        let (start, token) = self.next("a value")?;
+        let mut closers = match token {
+            Token::Word(text) | Token::String(text) => return Ok(text),
+            Token::Open(c) => vec![closer(c)],
+            _ => {
+                return Err(NormalizationError::invalid_format(
+                    "a value", self.input, start,
+                ))
+            }
+        };
+        loop {
+            let expected = format!("`{}`", closers[closers.len() - 1]);
+            match self.next(&expected)? {
+                (at, Token::Open(_)) if closers.len() + 1 >= MAX_DEPTH => {
+                    return Err(self.too_deep(at))
+                }
+                (_, Token::Open(c)) => closers.push(closer(c)),
+                (at, Token::Close(c)) if closers.last() == Some(&c) => {
+                    closers.pop();
+                    if closers.is_empty() {
+                        return Ok(&self.input[start..at + 1]);
+                    }
+                }
+                (at, Token::Close(_)) => {
+                    return Err(NormalizationError::invalid_format(expected, self.input, at))
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn entry(&mut self) -> Result<(&'a str, &'a str), NormalizationError> {
+        let key = self.skip()?;
+        match self.next("`:`")? {
+            (_, Token::Colon) => Ok((key, self.skip()?)),
+            (at, _) => Err(NormalizationError::invalid_format("`:`", self.input, at)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Node<'a>, NormalizationError> {
+        let (start, token) = self.next("a value")?;
+        let (kind, end) = match token {
+            Token::Word(text) => (NodeKind::Word, start + text.len()),
+            Token::String(text) => (NodeKind::String, start + text.len()),
+            Token::Open('[') => {
+                let (items, end) = self.items(']', Self::skip)?;
+                (NodeKind::List(items), end)
+            }
+            Token::Open(_) => {
+                let (entries, end) = self.items('}', Self::entry)?;
+                (NodeKind::Map(entries), end)
+            }
+            _ => {
+                return Err(NormalizationError::invalid_format(
+                    "a value", self.input, start,
+                ))
+            }
+        };
+        Ok(Node {
+            text: &self.input[start..end],
+            kind,
+        })
+    }
+}
+
+// Parses `input`, which must hold exactly one value.
+pub fn parse(input: &str) -> Result<Node<'_>, NormalizationError> {
+    let mut parser = Parser {
+        input,
+        tokens: Tokenizer::new(input),
+        peeked: None,
+    };
+    let node = parser.value()?;
+    match parser.peek()? {
+        None => Ok(node),
+        Some(_) => {
+            let (at, _) = parser.next("")?;
+            Err(NormalizationError::invalid_format(
+                "the end of the value",
+                input,
+                at,
+            ))
+        }
+    }
+}
+
+pub fn quote(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace(':', "\\:")
+            .replace('"', "\\\"")
+            .replace(',', "\\,")
+    )
+}
+
+pub fn unquote(input: &str) -> Result<String, NormalizationError> {
+    let node = parse(input)?;
+    if node.kind != NodeKind::String {
+        let at = offset_of(input, node.text);
+        return Err(NormalizationError::invalid_format(
+            "a quoted string",
+            input,
+            at,
+        ));
+    }
+    let mut unescaped = String::with_capacity(node.text.len());
+    let mut chars = node.text[1..node.text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // The tokenizer made sure something follows.
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+    Ok(unescaped)
+}
+
+// The text of each item of a list.
+pub fn split_list(input: &str) -> Result<Vec<&str>, NormalizationError> {
+    let node = parse(input)?;
+    match node.kind {
+        NodeKind::List(items) => Ok(items),
+        _ => Err(NormalizationError::invalid_format(
+            "[...]",
+            input,
+            offset_of(input, node.text),
+        )),
+    }
+}
+
+// The key and value text of each entry of a map, or of a struct.
+pub fn split_entries(input: &str) -> Result<Vec<(&str, &str)>, NormalizationError> {
+    let node = parse(input)?;
+    match node.kind {
+        NodeKind::Map(entries) => Ok(entries),
+        _ => Err(NormalizationError::invalid_format(
+            "{...}",
+            input,
+            offset_of(input, node.text),
+        )),
+    }
+}
@@ -0,0 +1,168 @@
+// Two versions of the same structs, as an older and a newer peer would have
+// them, reading each other's payloads.
+use normalization::{Deserializable, ErrorKind, NormalizationError, Serializable};
+use std::collections::HashMap;
+
+mod v1 {
+    use super::*;
+
+    #[derive(Serializable, Deserializable, Debug, PartialEq)]
+    pub struct PutArgs {
+        pub key: String,
+        pub value: String,
+        pub version: i32,
+    }
+
+    #[derive(Serializable, Deserializable, Debug, PartialEq)]
+    pub enum Consistency {
+        One,
+        Quorum,
+    }
+}
+
+mod v2 {
+    use super::*;
+
+    #[derive(Serializable, Deserializable, Debug, PartialEq)]
+    #[normalization(version = 2)]
+    pub struct PutArgs {
+        pub key: String,
+        // Renamed in the code, still `value` on the wire.
+        #[normalization(rename = "value")]
+        pub data: String,
+        pub version: i32,
+        #[normalization(since = 2)]
+        pub ttl_secs: u64,
+        #[normalization(since = 2)]
+        pub tags: HashMap<String, String>,
+        #[normalization(default)]
+        pub consistency: Option<Consistency>,
+        // Only meaningful to the process that made it.
+        #[normalization(skip)]
+        pub attempts: u32,
+    }
+
+    #[derive(Serializable, Deserializable, Debug, PartialEq)]
+    pub enum Consistency {
+        One,
+        #[normalization(rename = "Quorum")]
+        Majority,
+        All,
+    }
+}
+
+fn new_args() -> v2::PutArgs {
+    let mut tags = HashMap::new();
+    tags.insert("owner".to_string(), "caching".to_string());
+    v2::PutArgs {
+        key: "user/1".to_string(),
+        data: "active".to_string(),
+        version: 3,
+        ttl_secs: 60,
+        tags,
+        consistency: Some(v2::Consistency::Majority),
+        attempts: 2,
+    }
+}
+
+#[test]
+fn test_new_peers_read_old_payloads() {
+    let old = v1::PutArgs {
+        key: "user/1".to_string(),
+        value: "active".to_string(),
+        version: 3,
+    };
+    let new = v2::PutArgs::deserialize(&old.serialize()).unwrap();
+    assert_eq!(new.key, "user/1");
+    assert_eq!(new.data, "active");
+    assert_eq!(new.version, 3);
+    assert_eq!(new.ttl_secs, 0);
+    assert!(new.tags.is_empty());
+    assert_eq!(new.consistency, None);
+    assert_eq!(new.attempts, 0);
+}
+
+#[test]
+fn test_old_peers_read_new_payloads() {
+    let payload = new_args().serialize();
+    let old = v1::PutArgs::deserialize(&payload).unwrap();
+    assert_eq!(
+        old,
+        v1::PutArgs {
+            key: "user/1".to_string(),
+            value: "active".to_string(),
+            version: 3,
+        }
+    );
+    assert_eq!(
+        v1::Consistency::deserialize(&v2::Consistency::Majority.serialize()),
+        Ok(v1::Consistency::Quorum)
+    );
+}
+
+#[test]
+fn test_attributes_shape_the_text_form() {
+    let payload = new_args().serialize();
+    assert_eq!(
+        payload,
+        "{.version: 2,key: \"user/1\",value: \"active\",version: 3,ttl_secs: 60,tags: {\"owner\": \"caching\"},consistency: Quorum}"
+    );
+
+    let read = v2::PutArgs::deserialize(&payload).unwrap();
+    assert_eq!(
+        read,
+        v2::PutArgs {
+            attempts: 0,
+            ..new_args()
+        }
+    );
+    assert_eq!(
+        v2::PutArgs::schema(),
+        vec![
+            ("key", "String"),
+            ("value", "String"),
+            ("version", "i32"),
+            ("ttl_secs", "u64"),
+            ("tags", "HashMap<String,String>"),
+            ("consistency", "Option<Consistency>"),
+        ]
+    );
+}
+
+#[test]
+fn test_required_fields_are_still_required() {
+    let err: NormalizationError =
+        match v2::PutArgs::deserialize("{key: \"a\",version: 1,extra: [1,2]}") {
+            Ok(_) => panic!("read a payload without a value"),
+            Err(e) => e,
+        };
+    assert_eq!(err.kind, ErrorKind::MissingField);
+    assert_eq!(err.location(), "PutArgs.value");
+    assert_eq!(
+        v1::Consistency::deserialize("All").unwrap_err().kind,
+        ErrorKind::InvalidFormat
+    );
+}
+
+#[test]
+fn test_since_fields_are_required_from_peers_at_that_version() {
+    let err = v2::PutArgs::deserialize("{.version: 2,key: \"a\",value: \"b\",version: 1,tags: {}}")
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::MissingField);
+    assert_eq!(err.location(), "PutArgs.ttl_secs");
+
+    let read =
+        v2::PutArgs::deserialize("{.version: 1,key: \"a\",value: \"b\",version: 1}").unwrap();
+    assert_eq!(read.ttl_secs, 0);
+    let read = v2::PutArgs::deserialize(
+        "{.version: 3,key: \"a\",value: \"b\",version: 1,ttl_secs: 5,tags: {}}",
+    )
+    .unwrap();
+    assert_eq!(read.ttl_secs, 5);
+    assert_eq!(
+        v2::PutArgs::deserialize("{.version: two,key: \"a\",value: \"b\",version: 1}")
+            .unwrap_err()
+            .kind,
+        ErrorKind::ParseFailure
+    );
+}
@@ -0,0 +1,5 @@
+// Types that must not compile, with the errors they must give.
+#[test]
+fn test_bad_attributes_fail_to_compile() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,220 @@
+// Round trips of arbitrary values, and arbitrary input thrown at the parser.
+use normalization::{Deserializable, NormalizationError, Serializable};
+use proptest::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serializable, Deserializable, Debug, PartialEq)]
+struct Record {
+    name: String,
+    tags: Vec<String>,
+    labels: HashMap<String, String>,
+    note: Option<String>,
+    pair: (String, i64),
+    change: Change,
+}
+
+#[derive(Serializable, Deserializable, Debug, Clone, PartialEq)]
+enum Change {
+    Cleared,
+    Set(String),
+    Moved(String, String),
+    Annotated { text: String, weight: f64 },
+}
+
+fn change() -> impl Strategy<Value = Change> {
+    prop_oneof![
+        Just(Change::Cleared),
+        any::<String>().prop_map(Change::Set),
+        any::<(String, String)>().prop_map(|(from, to)| Change::Moved(from, to)),
+        (
+            any::<String>(),
+            any::<f64>().prop_filter("NaN is not equal to itself", |w| !w.is_nan())
+        )
+            .prop_map(|(text, weight)| Change::Annotated { text, weight }),
+    ]
+}
+
+fn record() -> impl Strategy<Value = Record> {
+    (
+        any::<String>(),
+        prop::collection::vec(any::<String>(), 0..4),
+        prop::collection::hash_map(any::<String>(), any::<String>(), 0..4),
+        any::<Option<String>>(),
+        any::<(String, i64)>(),
+        change(),
+    )
+        .prop_map(|(name, tags, labels, note, pair, change)| Record {
+            name,
+            tags,
+            labels,
+            note,
+            pair,
+            change,
+        })
+}
+
+fn round_trip<T: Serializable + Deserializable>(value: &T) -> Result<T, NormalizationError> {
+    T::deserialize(&value.serialize())
+}
+
+// Bad input must come back as an error pointing into it, never a panic.
+fn check_rejection<T: Deserializable>(input: &str) {
+    if let Err(e) = T::deserialize(input) {
+        assert!(
+            e.offset <= input.len(),
+            "offset {} past the end of {:?}",
+            e.offset,
+            input
+        );
+    }
+}
+
+// Text close to the format, so the fuzzing gets past the first token.
+fn near_format() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        prop_oneof![
+            Just("{".to_string()),
+            Just("}".to_string()),
+            Just("[".to_string()),
+            Just("]".to_string()),
+            Just(",".to_string()),
+            Just(": ".to_string()),
+            Just("\"".to_string()),
+            Just("\\".to_string()),
+            Just("null".to_string()),
+            Just("name".to_string()),
+            Just("Set".to_string()),
+            "-?[0-9]{1,3}",
+            any::<char>().prop_map(String::from),
+        ],
+        0..24,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+proptest! {
+    #[test]
+    fn test_strings_round_trip(s in any::<String>()) {
+        prop_assert_eq!(round_trip(&s)?, s);
+    }
+
+    #[test]
+    fn test_format_characters_round_trip(s in r#"[\[\]{}:,"\\ é]*"#) {
+        prop_assert_eq!(round_trip(&s)?, s.clone());
+        prop_assert_eq!(round_trip(&vec![s.clone(), s.clone()])?, vec![s.clone(), s.clone()]);
+        prop_assert_eq!(round_trip(&Some(Change::Set(s.clone())))?, Some(Change::Set(s)));
+    }
+
+    #[test]
+    fn test_collections_of_strings_round_trip(
+        list in prop::collection::vec(any::<String>(), 0..8),
+        map in prop::collection::btree_map(any::<String>(), any::<Vec<String>>(), 0..8),
+    ) {
+        prop_assert_eq!(round_trip(&list)?, list);
+        prop_assert_eq!(round_trip(&map)?, map);
+    }
+
+    #[test]
+    fn test_numbers_round_trip(i in any::<i64>(), u in any::<u64>(), f in any::<f64>()) {
+        prop_assert_eq!(round_trip(&i)?, i);
+        prop_assert_eq!(round_trip(&u)?, u);
+        let read = round_trip(&f)?;
+        prop_assert!(read == f || (read.is_nan() && f.is_nan()));
+    }
+
+    #[test]
+    fn test_derived_types_round_trip(record in record()) {
+        prop_assert_eq!(round_trip(&record)?, record);
+    }
+
+    #[test]
+    fn test_arbitrary_input_is_rejected_cleanly(input in any::<String>()) {
+        check_rejection::<Record>(&input);
+        check_rejection::<String>(&input);
+        check_rejection::<Vec<Option<i64>>>(&input);
+        check_rejection::<BTreeMap<String, Change>>(&input);
+    }
+
+    #[test]
+    fn test_near_format_input_is_rejected_cleanly(input in near_format()) {
+        check_rejection::<Record>(&input);
+        check_rejection::<Change>(&input);
+        check_rejection::<Vec<(String, i64)>>(&input);
+        check_rejection::<HashMap<String, Vec<String>>>(&input);
+    }
+
+    #[test]
+    fn test_damaged_payloads_are_rejected_cleanly(
+        record in record(),
+        edits in prop::collection::vec((any::<prop::sample::Index>(), any::<Option<char>>()), 1..4),
+    ) {
+        let mut payload: Vec<char> = record.serialize().chars().collect();
+        for (index, edit) in edits {
+            let at = index.index(payload.len() + 1);
+            match edit {
+                Some(c) => payload.insert(at, c),
+                None if at < payload.len() => {
+                    payload.remove(at);
+                }
+                None => (),
+            }
+        }
+        check_rejection::<Record>(&payload.into_iter().collect::<String>());
+    }
+}
+
+#[test]
+fn test_brackets_in_strings_keep_their_nesting() {
+    let record = Record {
+        name: "}{".to_string(),
+        tags: vec![
+            "[".to_string(),
+            "]],".to_string(),
+            "{\"a\": [1]}".to_string(),
+        ],
+        labels: vec![("}".to_string(), "{".to_string())]
+            .into_iter()
+            .collect(),
+        note: Some("\\".to_string()),
+        pair: ("\\\"".to_string(), -1),
+        change: Change::Moved("[".to_string(), "}".to_string()),
+    };
+    assert_eq!(round_trip(&record), Ok(record));
+}
+
+#[test]
+fn test_unescaped_brackets_in_strings_are_read() {
+    assert_eq!(
+        Vec::<String>::deserialize("[\"}\",\"[\", \"{x]\"]").unwrap(),
+        vec!["}", "[", "{x]"]
+    );
+}
+
+#[test]
+fn test_deep_nesting_is_rejected() {
+    let input = format!("{}{}", "[".repeat(10_000), "]".repeat(10_000));
+    let err = Vec::<String>::deserialize(&input).unwrap_err();
+    assert_eq!(err.expected, "at most 128 levels of nesting");
+}
+
+#[test]
+fn test_trailing_and_missing_commas_are_rejected() {
+    for input in [
+        "[1,2,]", "[1 2]", "[,1]", "{a: 1,}", "{a 1}", "[1,2]]", "[1,2} ",
+    ] {
+        assert!(Vec::<i64>::deserialize(input).is_err(), "read {:?}", input);
+    }
+}
+
+#[test]
+fn test_nested_values_are_left_to_their_own_impls() {
+    let node = normalization::text::parse("[[1 2], {a}]").unwrap();
+    assert_eq!(
+        node.kind,
+        normalization::text::NodeKind::List(vec!["[1 2]", "{a}"])
+    );
+    let err = Vec::<Vec<i64>>::deserialize("[[1], [1 2]]").unwrap_err();
+    assert_eq!(err.location(), "[1]");
+    assert_eq!(err.offset, 9);
+    assert!(normalization::text::parse("[[1}]").is_err());
+}
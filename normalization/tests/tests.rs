@@ -1,8 +1,8 @@
 // use normalization::Sample;
 // use normalization::normalization::{NormalizationError};
 
-use normalization::NormalizationError;
-use normalization_macros::{Deserializable, Serializable};
+use normalization::{Deserializable, ErrorKind, NormalizationError, Serializable};
+use std::collections::HashMap;
 
 #[derive(Serializable, Deserializable)]
 pub struct Sample {
@@ -38,6 +38,29 @@ fn test_serialization() {
     assert_eq!(serialized, "{number: 5,flag: true,text: \"Hello\",nested: {number: 8,string: \"Hi\"}}");
 }
 
+#[test]
+fn test_schema() {
+    assert_eq!(
+        Sample::schema(),
+        vec![
+            ("number", "i32"),
+            ("flag", "bool"),
+            ("text", "String"),
+            ("nested", "Nested"),
+        ]
+    );
+    assert_eq!(
+        TestStruct::schema(),
+        vec![
+            ("numbers", "Vec<i32>"),
+            ("strings", "Vec<String>"),
+            ("structs", "Vec<Nested>"),
+            ("sample", "Sample"),
+            ("samples", "Vec<Sample>"),
+        ]
+    );
+}
+
 #[test]
 fn test_deserialization() {
     let serialized = "{number: 5,flag: true,text: \"Hello\\:\\,\",nested: {number: 8,string: \"Hi\"}}";
@@ -114,6 +137,289 @@ fn test_vector_serialization_deserialization() {
     }
 }
 
+#[derive(Serializable, Deserializable, Debug, PartialEq)]
+pub enum Health {
+    Serving,
+    Draining,
+    Down,
+}
+
+#[derive(Serializable, Deserializable, Debug, PartialEq)]
+pub enum Event {
+    Started,
+    Moved(i64),
+    Renamed(String, String),
+    Failed { code: i32, reason: Option<String> },
+}
+
+#[derive(Serializable, Deserializable, Debug, PartialEq)]
+pub struct Everything {
+    pub ratio: f64,
+    pub offset: i64,
+    pub owner: Option<String>,
+    pub replicas: Option<u32>,
+    pub missing: Option<Nested>,
+    pub services: HashMap<String, Health>,
+    pub versions: HashMap<String, Vec<i64>>,
+    pub nested: Vec<Nested>,
+    pub pair: (i32, String),
+    pub ranges: Vec<(u64, u64)>,
+    pub health: Health,
+    pub events: Vec<Event>,
+}
+
+fn everything() -> Everything {
+    let mut services = HashMap::new();
+    services.insert("storage".to_string(), Health::Serving);
+    services.insert("cach:ing, \\\"quoted\\\"".to_string(), Health::Draining);
+    let mut versions = HashMap::new();
+    versions.insert("a".to_string(), vec![-1, i64::MAX]);
+    versions.insert("b".to_string(), Vec::new());
+    Everything {
+        ratio: -0.1,
+        offset: i64::MIN,
+        owner: Some("null".to_string()),
+        replicas: None,
+        missing: None,
+        services,
+        versions,
+        nested: vec![
+            Nested {
+                number: 1,
+                string: "{[braces]}".to_string(),
+            },
+            Nested {
+                number: 2,
+                string: "".to_string(),
+            },
+        ],
+        pair: (-3, "x, y".to_string()),
+        ranges: vec![(0, 10), (10, u64::MAX)],
+        health: Health::Down,
+        events: vec![
+            Event::Started,
+            Event::Moved(-42),
+            Event::Renamed("old".to_string(), "new: name".to_string()),
+            Event::Failed {
+                code: 7,
+                reason: None,
+            },
+            Event::Failed {
+                code: 8,
+                reason: Some("disk".to_string()),
+            },
+        ],
+    }
+}
+
+#[test]
+fn test_new_types_round_trip() {
+    let original = everything();
+    let serialized = original.serialize();
+    let deserialized = Everything::deserialize(&serialized).unwrap();
+    assert_eq!(deserialized.ratio, -0.1);
+    assert_eq!(deserialized.offset, i64::MIN);
+    assert_eq!(deserialized.owner, Some("null".to_string()));
+    assert_eq!(deserialized.replicas, None);
+    assert_eq!(deserialized.services, original.services);
+    assert_eq!(deserialized.versions, original.versions);
+    assert_eq!(deserialized.nested, original.nested);
+    assert_eq!(deserialized.pair, (-3, "x, y".to_string()));
+    assert_eq!(deserialized.ranges, original.ranges);
+    assert_eq!(deserialized.events, original.events);
+    assert_eq!(deserialized, original);
+    assert_eq!(deserialized.serialize(), serialized);
+}
+
+#[test]
+fn test_new_types_text_form() {
+    let mut services = HashMap::new();
+    services.insert("b".to_string(), 2);
+    services.insert("a".to_string(), 1);
+    #[derive(Serializable, Deserializable)]
+    struct Small {
+        owner: Option<String>,
+        count: Option<i64>,
+        services: HashMap<String, i32>,
+        pair: (bool, f64),
+    }
+    let small = Small {
+        owner: None,
+        count: Some(-5),
+        services,
+        pair: (true, 1.5),
+    };
+    assert_eq!(
+        small.serialize(),
+        "{owner: null,count: -5,services: {\"a\": 1,\"b\": 2},pair: [true,1.5]}"
+    );
+    assert_eq!(
+        Small::schema(),
+        vec![
+            ("owner", "Option<String>"),
+            ("count", "Option<i64>"),
+            ("services", "HashMap<String,i32>"),
+            ("pair", "(bool,f64)"),
+        ]
+    );
+
+    assert_eq!(Health::Draining.serialize(), "Draining");
+    assert_eq!(Event::Moved(3).serialize(), "{Moved: 3}");
+    assert_eq!(
+        Event::Renamed("a".to_string(), "b".to_string()).serialize(),
+        "{Renamed: [\"a\",\"b\"]}"
+    );
+    assert_eq!(
+        Event::Failed {
+            code: 1,
+            reason: None
+        }
+        .serialize(),
+        "{Failed: {code: 1,reason: null}}"
+    );
+    assert_eq!(Event::deserialize(" Started "), Ok(Event::Started));
+}
+
+fn error<T>(result: Result<T, NormalizationError>) -> NormalizationError {
+    match result {
+        Ok(_) => panic!("deserialized malformed input"),
+        Err(e) => e,
+    }
+}
+
+fn kind<T>(result: Result<T, NormalizationError>) -> ErrorKind {
+    error(result).kind
+}
+
+#[test]
+fn test_malformed_input_is_rejected() {
+    assert_eq!(
+        kind(Health::deserialize("Sleeping")),
+        ErrorKind::InvalidFormat
+    );
+    assert_eq!(
+        kind(Event::deserialize("{Moved: 1,Started: 2}")),
+        ErrorKind::InvalidFormat
+    );
+    assert_eq!(
+        kind(Event::deserialize("{Renamed: [\"a\"]}")),
+        ErrorKind::InvalidFormat
+    );
+    assert_eq!(
+        kind(Event::deserialize("{Moved: x}")),
+        ErrorKind::ParseFailure
+    );
+    assert_eq!(
+        kind(Nested::deserialize("{number: 1}")),
+        ErrorKind::MissingField
+    );
+    assert_eq!(
+        kind(Nested::deserialize("{number: 1,string: \"open}")),
+        ErrorKind::InvalidFormat
+    );
+    assert_eq!(
+        kind(Nested::deserialize("{number: 1,string: unquoted}")),
+        ErrorKind::InvalidFormat
+    );
+}
+
+#[test]
+fn test_errors_say_where_and_what() {
+    let input = "{number: 5,flag: true,text: \"Hi\",nested: {number: x8,string: \"Hi\"}}";
+    let err = error(Sample::deserialize(input));
+    assert_eq!(err.kind, ErrorKind::ParseFailure);
+    assert_eq!(err.location(), "Sample.nested.number");
+    assert_eq!(err.expected, "i32");
+    assert_eq!(err.offset, input.find("x8").unwrap());
+    assert_eq!(err.snippet, "x8");
+    assert_eq!(
+        err.to_string(),
+        "Sample.nested.number: parse failure: expected i32 at byte 50, found \"x8\""
+    );
+
+    let input =
+        "{numbers: [],strings: [],structs: [{number: 1,string: \"a\"},{number: 2}],sample: {}}";
+    let err = error(TestStruct::deserialize(input));
+    assert_eq!(
+        err.to_string(),
+        "TestStruct.structs[1].string: missing field of type String"
+    );
+    assert_eq!(err.offset, input.find("{number: 2}").unwrap());
+
+    let mut original = everything();
+    original.versions.insert("c".to_string(), vec![1]);
+    let input = original.serialize().replace("\"c\": [1]", "\"c\": [1,z]");
+    let err = error(Everything::deserialize(&input));
+    assert_eq!(err.location(), "Everything.versions[\"c\"][1]");
+    assert_eq!(&input[err.offset..err.offset + 1], "z");
+
+    let err = error(Event::deserialize("{Failed: {code: 1,reason: nope}}"));
+    assert_eq!(err.location(), "Event.Failed.reason");
+    assert_eq!(err.expected, "a quoted string");
+    assert_eq!(err.offset, 26);
+
+    let err = error(Health::deserialize(" Sleeping"));
+    assert_eq!(err.expected, "a variant of Health");
+    let err = error(Nested::deserialize("{number: 1,string: \"[é"));
+    assert_eq!(err.location(), "Nested");
+    assert_eq!(err.snippet, "\"[é");
+
+    let err: Box<dyn std::error::Error> = Box::new(err);
+    assert!(err
+        .to_string()
+        .starts_with("Nested: invalid format: expected a closing quote at byte 19"));
+}
+
+fn round_trip<T: Serializable + Deserializable>(value: &T) -> T {
+    T::deserialize(&value.serialize()).unwrap()
+}
+
+#[derive(Serializable, Deserializable, Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+}
+
+#[test]
+fn test_standard_types_implement_the_traits() {
+    assert_eq!(round_trip(&-7i64), -7);
+    assert_eq!(round_trip(&2.5f64), 2.5);
+    assert_eq!(round_trip(&"a, \"b\"".to_string()), "a, \"b\"");
+    assert_eq!(round_trip(&Some(vec![1u8, 2])), Some(vec![1, 2]));
+    assert_eq!(
+        round_trip(&(1, "x".to_string(), false)),
+        (1, "x".to_string(), false)
+    );
+    assert_eq!("a:b".serialize(), "a:b".to_string().serialize());
+    assert_eq!(Box::new(3).serialize(), "3");
+
+    let mut by_id = std::collections::BTreeMap::new();
+    by_id.insert(2u32, Health::Serving);
+    by_id.insert(1u32, Health::Down);
+    assert_eq!(by_id.serialize(), "{1: Down,2: Serving}");
+    assert_eq!(round_trip(&by_id), by_id);
+}
+
+#[test]
+fn test_generic_structs() {
+    let page = Page {
+        items: vec![Nested {
+            number: 1,
+            string: "a".to_string(),
+        }],
+        next: Some("cursor".to_string()),
+    };
+    assert_eq!(
+        page.serialize(),
+        "{items: [{number: 1,string: \"a\"}],next: \"cursor\"}"
+    );
+    assert_eq!(round_trip(&page), page);
+    assert_eq!(
+        Page::<i32>::schema(),
+        vec![("items", "Vec<T>"), ("next", "Option<String>")]
+    );
+}
+
 // #[derive(Serializable, Deserializable)]
 // pub struct Sample {
 //     pub number: i32,
@@ -0,0 +1,23 @@
+use normalization::{Deserializable, Serializable};
+
+#[derive(Serializable, Deserializable)]
+pub struct Account {
+    #[normalization(rename = "a b")]
+    pub owner: String,
+    pub balance: i32,
+}
+
+#[derive(Serializable, Deserializable)]
+pub enum Kind {
+    #[normalization(rename = "savings/checking")]
+    Savings,
+    Checking,
+}
+
+#[derive(Serializable, Deserializable)]
+pub struct Limit {
+    #[normalization(rename = "max=10")]
+    pub max: i32,
+}
+
+fn main() {}
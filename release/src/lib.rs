@@ -1,5 +1,6 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::ServiceDescription;
+use rpc::{schema, ProcedureId};
 
 pub const SYSTEM_NAME: &str = "release";
 pub const SYSTEM_ADDRESS: &str = "127.0.0.1:11000";
@@ -84,10 +85,7 @@ pub async fn create_release(
         version,
         description,
     };
-    let request = Request {
-        procedure_id: CREATE_RELEASE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(CREATE_RELEASE_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to create release");
@@ -96,10 +94,7 @@ pub async fn create_release(
 
 pub async fn get_release(addr: &str, release_id: String) -> GetReleaseResult {
     let args = GetReleaseArgs { release_id };
-    let request = Request {
-        procedure_id: GET_RELEASE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(GET_RELEASE_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to get release");
@@ -108,10 +103,7 @@ pub async fn get_release(addr: &str, release_id: String) -> GetReleaseResult {
 
 pub async fn list_releases(addr: &str) -> ListReleasesResult {
     let args = ListReleasesArgs { placeholder: 0 };
-    let request = Request {
-        procedure_id: LIST_RELEASES_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(LIST_RELEASES_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to list releases");
@@ -120,10 +112,7 @@ pub async fn list_releases(addr: &str) -> ListReleasesResult {
 
 pub async fn advance_release(addr: &str, release_id: String) -> AdvanceReleaseResult {
     let args = AdvanceReleaseArgs { release_id };
-    let request = Request {
-        procedure_id: ADVANCE_RELEASE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(ADVANCE_RELEASE_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to advance release");
@@ -132,12 +121,38 @@ pub async fn advance_release(addr: &str, release_id: String) -> AdvanceReleaseRe
 
 pub async fn rollback(addr: &str, service: String) -> RollbackResult {
     let args = RollbackArgs { service };
-    let request = Request {
-        procedure_id: ROLLBACK_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(ROLLBACK_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to rollback");
     RollbackResult::deserialize(&response.payload).expect("Failed to deserialize")
 }
+
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION"))
+        .procedure(
+            CREATE_RELEASE_PROCEDURE,
+            "create_release",
+            schema!(CreateReleaseArgs),
+            schema!(CreateReleaseResult),
+        )
+        .procedure(
+            GET_RELEASE_PROCEDURE,
+            "get_release",
+            schema!(GetReleaseArgs),
+            schema!(GetReleaseResult),
+        )
+        .procedure(
+            LIST_RELEASES_PROCEDURE,
+            "list_releases",
+            schema!(ListReleasesArgs),
+            schema!(ListReleasesResult),
+        )
+        .procedure(
+            ADVANCE_RELEASE_PROCEDURE,
+            "advance_release",
+            schema!(AdvanceReleaseArgs),
+            schema!(AdvanceReleaseResult),
+        )
+        .procedure(ROLLBACK_PROCEDURE, "rollback", schema!(RollbackArgs), schema!(RollbackResult))
+}
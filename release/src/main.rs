@@ -4,7 +4,8 @@ use release::{
     RollbackResult, ADVANCE_RELEASE_PROCEDURE, CREATE_RELEASE_PROCEDURE, GET_RELEASE_PROCEDURE,
     LIST_RELEASES_PROCEDURE, ROLLBACK_PROCEDURE, SYSTEM_ADDRESS, SYSTEM_NAME,
 };
-use rpc::{server, Request, Response};
+use rpc::reflection::Reflection;
+use rpc::{server, Handlers, Request, Response, Shutdown};
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
@@ -88,9 +89,7 @@ mod handlers {
         state.active.insert(args.service, id.clone());
 
         let result = CreateReleaseResult { release_id: id };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn get_release(payload: &str, state: &mut ReleaseState) -> Response {
@@ -108,9 +107,7 @@ mod handlers {
                     status: r.status.clone(),
                     batch_progress: r.batch_progress,
                 };
-                Response {
-                    payload: result.serialize(),
-                }
+                Response::ok(result.serialize())
             }
             None => {
                 let result = GetReleaseResult {
@@ -121,9 +118,7 @@ mod handlers {
                     status: "not_found".to_string(),
                     batch_progress: 0,
                 };
-                Response {
-                    payload: result.serialize(),
-                }
+                Response::ok(result.serialize())
             }
         }
     }
@@ -146,9 +141,7 @@ mod handlers {
         let result = ListReleasesResult {
             releases: entries.join(";"),
         };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn advance_release(payload: &str, state: &mut ReleaseState) -> Response {
@@ -166,9 +159,7 @@ mod handlers {
                     success: 0,
                     status: "not_found".to_string(),
                 };
-                return Response {
-                    payload: result.serialize(),
-                };
+                return Response::ok(result.serialize());
             }
         };
 
@@ -177,9 +168,7 @@ mod handlers {
                 success: 0,
                 status: release.status.clone(),
             };
-            return Response {
-                payload: result.serialize(),
-            };
+            return Response::ok(result.serialize());
         }
 
         release.status = "deploying".to_string();
@@ -217,9 +206,7 @@ mod handlers {
             success: 1,
             status: release.status.clone(),
         };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn rollback(payload: &str, state: &mut ReleaseState) -> Response {
@@ -236,17 +223,13 @@ mod handlers {
                 success: 1,
                 rolled_back_to: "previous".to_string(),
             };
-            Response {
-                payload: result.serialize(),
-            }
+            Response::ok(result.serialize())
         } else {
             let result = RollbackResult {
                 success: 0,
                 rolled_back_to: String::new(),
             };
-            Response {
-                payload: result.serialize(),
-            }
+            Response::ok(result.serialize())
         }
     }
 }
@@ -265,9 +248,7 @@ async fn request_handler(request: Request, shared_state: Arc<Mutex<ReleaseState>
             handlers::advance_release(&request.payload, &mut state).await
         }
         ROLLBACK_PROCEDURE => handlers::rollback(&request.payload, &mut state).await,
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
+        _ => Response::unknown_procedure(request.procedure_id),
     }
 }
 
@@ -278,17 +259,20 @@ async fn main() {
     let addr = std::env::var("PORT")
         .map(|p| format!("127.0.0.1:{}", p))
         .unwrap_or_else(|_| SYSTEM_ADDRESS.to_string());
-    discovery::register(SYSTEM_NAME.to_string(), addr.clone());
+    let shutdown = Shutdown::on_signal();
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.clone(), &shutdown);
 
     println!("Release service starting on {}", addr);
 
-    server::start_server_with_state(
+    server::start_server_with_shutdown(
         &addr,
+        Handlers::standard().with(Reflection::new(release::describe())),
         |request, state| {
             Box::pin(request_handler(request, state))
                 as Pin<Box<dyn Future<Output = Response> + Send>>
         },
         state,
+        shutdown,
     )
     .await
     .expect("Server crashed");
@@ -1,13 +1,13 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
+use normalization::{Deserializable, Serializable};
 use rand::Rng;
-use rpc::{ProcedureId, Response};
-use std::collections::{HashMap, VecDeque};
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use rpc::reflection::{Schema, ServiceDescription};
+use rpc::{hedge, schema, transport, Client, ProcedureId, Request, Response, Status};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tokio::time::Instant;
 
+pub const SYSTEM_NAME: &str = "routing";
+
 pub const ROUTE_PROCEDURE: ProcedureId = 1;
 pub const ROUTE_SET_STRATEGY_PROCEDURE: ProcedureId = 2;
 
@@ -28,31 +28,27 @@ pub struct SetStrategyArgs {
     pub strategy: String,
 }
 
-struct BackendPool {
-    address: String,
-    connections: VecDeque<TcpStream>,
-}
-
+// Tracks which backends exist for one system and picks one per request.
+// Connections are pooled by rpc::Client, and nothing here does network I/O,
+// so a Router can keep all of its pools behind one short-lived lock.
 pub struct ConnectionPool {
     system_name: String,
-    backends: Vec<BackendPool>,
+    backends: Vec<String>,
     strategy: String,
     next_index: usize,
-    max_per_backend: usize,
-    last_refresh: Instant,
+    last_refresh: Option<Instant>,
 }
 
 const REFRESH_INTERVAL_SECS: u64 = 10;
 
 impl ConnectionPool {
-    pub fn new(system_name: String, max_size: usize) -> Self {
+    pub fn new(system_name: String) -> Self {
         Self {
             system_name,
             backends: Vec::new(),
             strategy: "round-robin".to_string(),
             next_index: 0,
-            max_per_backend: max_size,
-            last_refresh: Instant::now() - tokio::time::Duration::from_secs(REFRESH_INTERVAL_SECS + 1),
+            last_refresh: None,
         }
     }
 
@@ -60,57 +56,61 @@ impl ConnectionPool {
         self.strategy = strategy;
     }
 
-    async fn refresh_backends(&mut self) {
-        if self.last_refresh.elapsed().as_secs() < REFRESH_INTERVAL_SECS {
-            return;
+    // Once a pool has backends, only one caller per interval is told to go
+    // and fetch the backend list from discovery.
+    pub fn claim_refresh(&mut self) -> bool {
+        let due = match self.last_refresh {
+            Some(last) => last.elapsed().as_secs() >= REFRESH_INTERVAL_SECS,
+            None => true,
+        };
+        if due || self.backends.is_empty() {
+            self.last_refresh = Some(Instant::now());
+            return true;
         }
-        self.last_refresh = Instant::now();
-
-        let result = discovery::list(self.system_name.clone()).await;
-        let addresses: Vec<String> = result
-            .addresses
-            .split(';')
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .collect();
+        false
+    }
 
+    pub fn set_backends(&mut self, addresses: Vec<String>) {
         // Add new backends
         for addr in &addresses {
-            if !self.backends.iter().any(|b| &b.address == addr) {
+            if !self.backends.contains(addr) {
                 println!("Routing: adding backend {} for {}", addr, self.system_name);
-                self.backends.push(BackendPool {
-                    address: addr.clone(),
-                    connections: VecDeque::new(),
-                });
+                self.backends.push(addr.clone());
+            }
+        }
+
+        // Remove stale backends and drop their pooled connections
+        for addr in &self.backends {
+            if !addresses.contains(addr) {
+                Client::global().evict(addr);
             }
         }
+        self.backends.retain(|b| addresses.contains(b));
+    }
 
-        // Remove stale backends
-        self.backends.retain(|b| addresses.contains(&b.address));
+    fn in_flight(&self, idx: usize) -> usize {
+        Client::global().in_flight(&self.backends[idx])
     }
 
-    fn select_backend(&mut self) -> Option<usize> {
+    pub fn select_backend(&mut self) -> Option<String> {
         if self.backends.is_empty() {
             return None;
         }
 
-        match self.strategy.as_str() {
+        let idx = match self.strategy.as_str() {
             "least-connections" => {
                 let mut best = 0;
                 for i in 1..self.backends.len() {
-                    if self.backends[i].connections.len() < self.backends[best].connections.len() {
+                    if self.in_flight(i) < self.in_flight(best) {
                         best = i;
                     }
                 }
-                Some(best)
-            }
-            "random" => {
-                let idx = rand::thread_rng().gen_range(0..self.backends.len());
-                Some(idx)
+                best
             }
+            "random" => rand::thread_rng().gen_range(0..self.backends.len()),
             "pick-2" => {
                 if self.backends.len() == 1 {
-                    return Some(0);
+                    return Some(self.backends[0].clone());
                 }
                 let mut rng = rand::thread_rng();
                 let a = rng.gen_range(0..self.backends.len());
@@ -118,89 +118,125 @@ impl ConnectionPool {
                 while b == a {
                     b = rng.gen_range(0..self.backends.len());
                 }
-                if self.backends[a].connections.len() <= self.backends[b].connections.len() {
-                    Some(a)
+                if self.in_flight(a) <= self.in_flight(b) {
+                    a
                 } else {
-                    Some(b)
+                    b
                 }
             }
             // round-robin default
             _ => {
                 let idx = self.next_index % self.backends.len();
                 self.next_index = (self.next_index + 1) % self.backends.len();
-                Some(idx)
+                idx
             }
-        }
+        };
+        Some(self.backends[idx].clone())
     }
+}
 
-    pub async fn get(&mut self) -> Option<(TcpStream, String)> {
-        self.refresh_backends().await;
+// storage's and caching's GET_PROCEDURE. Ids are only unique per service, so
+// they are matched together with the name.
+const STORAGE_GET_PROCEDURE: ProcedureId = 1;
+const CACHING_GET_PROCEDURE: ProcedureId = 1;
 
-        let backend_idx = self.select_backend()?;
-        let backend = &mut self.backends[backend_idx];
-        let address = backend.address.clone();
+// Point reads any replica can answer still go to the backend the strategy
+// picks, but are hedged to another backend if it is slow.
+fn hedged(name: &str, procedure_id: ProcedureId) -> bool {
+    match name {
+        "storage" => procedure_id == STORAGE_GET_PROCEDURE,
+        "caching" => procedure_id == CACHING_GET_PROCEDURE,
+        _ => false,
+    }
+}
 
-        // Try to reuse a pooled connection
-        if let Some(conn) = backend.connections.pop_front() {
-            return Some((conn, address));
-        }
+// Routes requests by system name. Clones share the same pools.
+#[derive(Clone, Default)]
+pub struct Router {
+    pools: Arc<Mutex<HashMap<String, ConnectionPool>>>,
+}
 
-        // Open a new connection
-        match TcpStream::connect(&address).await {
-            Ok(conn) => Some((conn, address)),
-            Err(e) => {
-                eprintln!("Failed to connect to {}: {}", address, e);
-                None
-            }
-        }
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
     }
 
-    pub fn release(&mut self, conn: TcpStream, address: String) {
-        if let Some(backend) = self.backends.iter_mut().find(|b| b.address == address) {
-            if backend.connections.len() < self.max_per_backend {
-                backend.connections.push_back(conn);
-            }
+    pub fn set_strategy(&self, strategy: String) {
+        for pool in self.pools.lock().unwrap().values_mut() {
+            pool.set_strategy(strategy.clone());
         }
-        println!("Pool for {}: {} backends", self.system_name, self.backends.len());
     }
 
-    pub async fn send_request(
-        &mut self,
+    // The pools lock is only taken between awaits: discovery lookups and the
+    // forwarded call itself run without it.
+    pub async fn route(
+        &self,
+        name: &str,
         procedure_id: ProcedureId,
         payload: &str,
-    ) -> Result<String, String> {
-        if let Some((mut socket, address)) = self.get().await {
-            let serialized = format!("{}:{}\n", procedure_id, payload);
-            println!("Sending to {}: {}", address, serialized);
-            if let Err(e) = socket.write_all(serialized.as_bytes()).await {
-                eprintln!("Failed to forward request: {}", e);
-                return Err(format!("Failed to send request: {}", e));
-            }
+    ) -> Result<Response, rpc::Error> {
+        let refresh = self
+            .pools
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| ConnectionPool::new(name.to_string()))
+            .claim_refresh();
 
-            let mut buffer = vec![0u8; 1024];
-            let n = socket
-                .read(&mut buffer)
-                .await
-                .expect("Failed to read from socket");
-            let response_data = String::from_utf8_lossy(&buffer[..n]).to_string();
-            println!("Received: {}", response_data);
-
-            self.release(socket, address);
-            Ok(response_data)
-        } else {
-            Err("Service not available".to_string())
+        if refresh {
+            // Keeps the backends it has if discovery can't be reached.
+            match discovery::list(name.to_string()).await {
+                Ok(result) => {
+                    let addresses: Vec<String> = result
+                        .addresses
+                        .split(';')
+                        .filter(|s| !s.is_empty())
+                        .map(|s| s.to_string())
+                        .collect();
+                    if let Some(pool) = self.pools.lock().unwrap().get_mut(name) {
+                        pool.set_backends(transport::one_per_server(addresses));
+                    }
+                }
+                Err(e) => eprintln!("Failed to refresh backends for {}: {}", name, e),
+            }
         }
-    }
-}
 
-pub struct Router {
-    pools: Arc<Mutex<HashMap<String, ConnectionPool>>>,
-}
+        let (backend, others) = match self.pools.lock().unwrap().get_mut(name) {
+            Some(pool) => {
+                let backend = pool.select_backend();
+                let others: Vec<String> = match &backend {
+                    Some(address) if hedged(name, procedure_id) => pool
+                        .backends
+                        .iter()
+                        .filter(|b| *b != address)
+                        .cloned()
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                (backend, others)
+            }
+            None => (None, Vec::new()),
+        };
 
-impl Router {
-    pub fn new() -> Self {
-        Self {
-            pools: Arc::new(Mutex::new(HashMap::new())),
+        match backend {
+            Some(address) => {
+                let request = Request::new(procedure_id, payload.to_string());
+                println!("Sending to {}: {}:{}", address, procedure_id, payload);
+                let result = if others.is_empty() {
+                    Client::global().call(&address, request).await
+                } else {
+                    hedge::send_request_preferring(&address, &others, request).await
+                };
+                match &result {
+                    Ok(response) => println!("Received: {}", response.payload),
+                    Err(e) => eprintln!("Failed to forward request to {}: {}", address, e),
+                }
+                result
+            }
+            None => Err(rpc::Error::Status(
+                Status::Unavailable,
+                format!("No backend available for {}", name),
+            )),
         }
     }
 
@@ -210,28 +246,21 @@ impl Router {
         procedure_id: ProcedureId,
         payload: &str,
     ) -> Response {
-        let mut pools = self.pools.lock().await;
-        let pool = pools
-            .entry(name.clone())
-            .or_insert_with(|| ConnectionPool::new(name.clone(), 10));
-
-        route_request(
-            RouteArgs {
-                name,
-                procedure_id,
-                payload: payload.to_string(),
-            },
-            pool,
-        )
-        .await
+        match self.route(&name, procedure_id, payload).await {
+            Ok(response) => response,
+            Err(e) => e.into_response(),
+        }
     }
 }
 
-async fn route_request(args: RouteArgs, pool: &mut ConnectionPool) -> Response {
-    match pool.send_request(args.procedure_id, &args.payload).await {
-        Ok(response_data) => Response {
-            payload: response_data,
-        },
-        Err(err_msg) => Response { payload: err_msg },
-    }
+// route answers with whatever the backend it forwarded to answered.
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION"))
+        .procedure(ROUTE_PROCEDURE, "route", schema!(RouteArgs), Schema::none())
+        .procedure(
+            ROUTE_SET_STRATEGY_PROCEDURE,
+            "set_strategy",
+            schema!(SetStrategyArgs),
+            Schema::none(),
+        )
 }
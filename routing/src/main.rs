@@ -1,85 +1,62 @@
-use routing::ConnectionPool;
-use routing::{RouteArgs, SetStrategyArgs, ROUTE_PROCEDURE, ROUTE_SET_STRATEGY_PROCEDURE};
-use rpc::{server, Request, Response};
-use std::collections::HashMap;
+use routing::Router;
+use routing::{
+    RouteArgs, SetStrategyArgs, ROUTE_PROCEDURE, ROUTE_SET_STRATEGY_PROCEDURE, SYSTEM_NAME,
+};
+use rpc::reflection::Reflection;
+use rpc::{server, Handlers, Request, Response, Shutdown};
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
-const SYSTEM_NAME: &str = "routing";
 const SYSTEM_ADDRESS: &str = "127.0.0.1:10300";
 
 mod handlers {
     use super::*;
 
-    pub async fn route(
-        payload: &str,
-        pools: Arc<Mutex<HashMap<String, ConnectionPool>>>,
-    ) -> Response {
+    pub async fn route(payload: &str, router: &Router) -> Response {
         println!("{}", payload);
-        let args = RouteArgs::deserialize(&payload).expect("Failed to deserialize payload");
-
-        let mut pools = pools.lock().await;
-        let pool = pools
-            .entry(args.name.clone())
-            .or_insert_with(|| ConnectionPool::new(args.name.clone(), 10));
-
-        match pool.send_request(args.procedure_id, &args.payload).await {
-            Ok(response_data) => Response {
-                payload: response_data,
-            },
-            Err(err_msg) => Response { payload: err_msg },
-        }
+        let args = RouteArgs::deserialize(payload).expect("Failed to deserialize payload");
+        router
+            .send_request(args.name, args.procedure_id, &args.payload)
+            .await
     }
 
-    pub async fn set_strategy(
-        payload: &str,
-        pools: Arc<Mutex<HashMap<String, ConnectionPool>>>,
-    ) -> Response {
+    pub async fn set_strategy(payload: &str, router: &Router) -> Response {
         let args =
-            SetStrategyArgs::deserialize(&payload).expect("Failed to deserialize payload");
-        let mut pools = pools.lock().await;
-        for pool in pools.values_mut() {
-            pool.set_strategy(args.strategy.clone());
-        }
+            SetStrategyArgs::deserialize(payload).expect("Failed to deserialize payload");
+        router.set_strategy(args.strategy.clone());
         println!("Routing strategy changed to: {}", args.strategy);
-        Response {
-            payload: "OK".to_string(),
-        }
+        Response::ok("OK")
     }
 }
 
-async fn request_handler(
-    request: Request,
-    shared_state: Arc<Mutex<HashMap<String, ConnectionPool>>>,
-) -> Response {
+async fn request_handler(request: Request, router: Router) -> Response {
     match request.procedure_id {
-        ROUTE_PROCEDURE => handlers::route(&request.payload, shared_state).await,
+        ROUTE_PROCEDURE => handlers::route(&request.payload, &router).await,
         ROUTE_SET_STRATEGY_PROCEDURE => {
-            handlers::set_strategy(&request.payload, shared_state).await
+            handlers::set_strategy(&request.payload, &router).await
         }
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
+        _ => Response::unknown_procedure(request.procedure_id),
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let service_pools = Arc::new(Mutex::new(HashMap::new()));
+    let router = Router::new();
     let addr = std::env::var("PORT")
         .map(|p| format!("127.0.0.1:{}", p))
         .unwrap_or_else(|_| SYSTEM_ADDRESS.to_string());
-    discovery::register(SYSTEM_NAME.to_string(), addr.clone());
+    let shutdown = Shutdown::on_signal();
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.clone(), &shutdown);
 
-    server::start_server_with_state(
+    server::start_server_with_shutdown(
         &addr,
+        Handlers::standard().with(Reflection::new(routing::describe())),
         |request, state| {
             Box::pin(request_handler(request, state))
                 as Pin<Box<dyn Future<Output = Response> + Send>>
         },
-        service_pools,
+        router,
+        shutdown,
     )
     .await
     .expect("Server crashed");
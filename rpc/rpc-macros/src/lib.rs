@@ -0,0 +1,340 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use std::collections::HashMap;
+use syn::{
+    FnArg, GenericArgument, Ident, ItemTrait, LitInt, Pat, PathArguments, ReturnType, TraitItem,
+    TraitItemMethod, Type,
+};
+
+// What a procedure hands back to its caller.
+enum Output {
+    // No result struct; the response is the usual "OK".
+    Unit,
+    Value(Type),
+    // Result<T, E>: an Err is sent back as an error status, so E must
+    // convert into rpc::Error.
+    Fallible(Type),
+}
+
+struct Procedure {
+    method: Ident,
+    id: i32,
+    args: Type,
+    output: Output,
+}
+
+// Turns a trait of `async fn name(&self, args: Args) -> Result` methods into
+// a service: a `NAME_PROCEDURE` constant per method, a provided `dispatch`
+// method that decodes a request and calls the right method, and a
+// `<Trait>Client` with one typed async method per procedure. `dispatch` also
+// answers the reflection procedure with the trait's `describe`.
+//
+// Ids follow declaration order starting at 1, so they match the constants
+// services used to write by hand. `#[procedure(n)]` pins a method to `n`.
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return syn::Error::new_spanned(attr, "#[rpc::service] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = syn::parse_macro_input!(item as ItemTrait);
+    match expand(item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(mut item: ItemTrait) -> syn::Result<proc_macro2::TokenStream> {
+    let mut procedures = Vec::new();
+    let mut used: HashMap<i32, Ident> = HashMap::new();
+
+    for (index, trait_item) in item.items.iter_mut().enumerate() {
+        let method = match trait_item {
+            TraitItem::Method(method) => method,
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "a service trait can only contain procedures",
+                ))
+            }
+        };
+
+        let id = match take_procedure_id(method)? {
+            Some(id) => id,
+            None => index as i32 + 1,
+        };
+        // Kept in sync with rpc::reflection::REFLECTION_PROCEDURE.
+        if id == 0 {
+            return Err(syn::Error::new_spanned(
+                &method.sig.ident,
+                "procedure id 0 is reserved for reflection",
+            ));
+        }
+        if let Some(other) = used.get(&id) {
+            return Err(syn::Error::new_spanned(
+                &method.sig.ident,
+                format!("procedure id {} is already used by `{}`", id, other),
+            ));
+        }
+        used.insert(id, method.sig.ident.clone());
+
+        procedures.push(parse_procedure(method, id)?);
+        rewrite_as_send_future(method);
+    }
+
+    let vis = &item.vis;
+    let trait_name = &item.ident;
+    let client_name = format_ident!("{}Client", trait_name);
+
+    item.supertraits
+        .push(syn::parse_quote!(::std::marker::Send));
+    item.supertraits
+        .push(syn::parse_quote!(::std::marker::Sync));
+
+    let constants = procedures.iter().map(|p| {
+        let constant = procedure_constant(&p.method);
+        let id = p.id;
+        quote! { #vis const #constant: ::rpc::ProcedureId = #id; }
+    });
+
+    let arms = procedures.iter().map(|p| {
+        let constant = procedure_constant(&p.method);
+        let method = &p.method;
+        let args = &p.args;
+        let respond = match &p.output {
+            Output::Unit => quote! {
+                self.#method(args).await;
+                ::rpc::Response::default()
+            },
+            Output::Value(_) => quote! {
+                let result = self.#method(args).await;
+                ::rpc::Response::ok(::normalization::Serializable::serialize(&result))
+            },
+            Output::Fallible(_) => quote! {
+                match self.#method(args).await {
+                    Ok(result) => ::rpc::Response::ok(::normalization::Serializable::serialize(&result)),
+                    Err(e) => ::rpc::Error::from(e).into_response(),
+                }
+            },
+        };
+        quote! {
+            #constant => {
+                let args = <#args as ::normalization::Deserializable>::deserialize(&request.payload);
+                let args = match args {
+                    Ok(args) => args,
+                    Err(e) => {
+                        return ::rpc::Response::error(
+                            ::rpc::Status::InvalidArgument,
+                            format!("Failed to deserialize {}: {}", stringify!(#args), e),
+                        )
+                    }
+                };
+                #respond
+            }
+        }
+    });
+
+    let service_name = trait_name.to_string().to_lowercase();
+    let descriptions = procedures.iter().map(|p| {
+        let id = p.id;
+        let name = p.method.to_string();
+        let args = &p.args;
+        let result = match &p.output {
+            Output::Unit => quote! { ::rpc::reflection::Schema::none() },
+            Output::Value(result) | Output::Fallible(result) => quote! { ::rpc::schema!(#result) },
+        };
+        quote! { .procedure(#id, #name, ::rpc::schema!(#args), #result) }
+    });
+
+    item.items.push(syn::parse_quote! {
+        // Answers the reflection procedure. Override it to describe the
+        // service under a different name.
+        fn describe(&self) -> ::rpc::reflection::ServiceDescription {
+            ::rpc::reflection::ServiceDescription::new(#service_name, env!("CARGO_PKG_VERSION"))
+                #(#descriptions)*
+        }
+    });
+
+    item.items.push(syn::parse_quote! {
+        fn dispatch<'a>(
+            &'a self,
+            request: ::rpc::Request,
+        ) -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = ::rpc::Response> + Send + 'a>> {
+            Box::pin(async move {
+                match request.procedure_id {
+                    #(#arms)*
+                    ::rpc::reflection::REFLECTION_PROCEDURE => self.describe().respond(),
+                    _ => ::rpc::Response::unknown_procedure(request.procedure_id),
+                }
+            })
+        }
+    });
+
+    let client_methods = procedures.iter().map(|p| {
+        let constant = procedure_constant(&p.method);
+        let method = &p.method;
+        let args = &p.args;
+        match &p.output {
+            Output::Unit => quote! {
+                #vis async fn #method(&self, args: #args) -> Result<(), ::rpc::Error> {
+                    let payload = ::normalization::Serializable::serialize(&args);
+                    self.client
+                        .call(&self.addr, ::rpc::Request::new(#constant, payload))
+                        .await?;
+                    Ok(())
+                }
+            },
+            Output::Value(result) | Output::Fallible(result) => quote! {
+                #vis async fn #method(&self, args: #args) -> Result<#result, ::rpc::Error> {
+                    self.client.call_typed(&self.addr, #constant, &args).await
+                }
+            },
+        }
+    });
+
+    Ok(quote! {
+        #(#constants)*
+
+        #item
+
+        #[derive(Clone)]
+        #vis struct #client_name {
+            addr: String,
+            client: ::rpc::Client,
+        }
+
+        impl #client_name {
+            #vis fn new(addr: impl Into<String>) -> Self {
+                #client_name::with_client(addr, ::rpc::Client::global().clone())
+            }
+
+            #vis fn with_client(addr: impl Into<String>, client: ::rpc::Client) -> Self {
+                #client_name {
+                    addr: addr.into(),
+                    client,
+                }
+            }
+
+            #vis fn address(&self) -> &str {
+                &self.addr
+            }
+
+            #(#client_methods)*
+        }
+    })
+}
+
+fn procedure_constant(method: &Ident) -> Ident {
+    format_ident!("{}_PROCEDURE", method.to_string().to_uppercase())
+}
+
+// Removes `#[procedure(n)]` from the method and returns `n`.
+fn take_procedure_id(method: &mut TraitItemMethod) -> syn::Result<Option<i32>> {
+    let mut id = None;
+    let mut error = None;
+    method.attrs.retain(|attr| {
+        if !attr.path.is_ident("procedure") {
+            return true;
+        }
+        match attr
+            .parse_args::<LitInt>()
+            .and_then(|lit| lit.base10_parse::<i32>())
+        {
+            Ok(value) => id = Some(value),
+            Err(e) => error = Some(e),
+        }
+        false
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(id),
+    }
+}
+
+fn parse_procedure(method: &TraitItemMethod, id: i32) -> syn::Result<Procedure> {
+    let sig = &method.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig,
+            "procedures must be `async fn`",
+        ));
+    }
+    if method.default.is_some() {
+        return Err(syn::Error::new_spanned(
+            &method.default,
+            "procedures can't have a default body",
+        ));
+    }
+
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(receiver))
+            if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+        _ => {
+            return Err(syn::Error::new_spanned(
+                sig,
+                "procedures must take `&self` and one arguments struct",
+            ))
+        }
+    }
+    let args = match (inputs.next(), inputs.next()) {
+        (Some(FnArg::Typed(arg)), None) if matches!(*arg.pat, Pat::Ident(_)) => (*arg.ty).clone(),
+        _ => {
+            return Err(syn::Error::new_spanned(
+                sig,
+                "procedures must take `&self` and one arguments struct",
+            ))
+        }
+    };
+
+    let output = match &sig.output {
+        ReturnType::Default => Output::Unit,
+        ReturnType::Type(_, ty) => match result_ok_type(ty) {
+            Some(ok) => Output::Fallible(ok),
+            None => Output::Value((**ty).clone()),
+        },
+    };
+
+    Ok(Procedure {
+        method: sig.ident.clone(),
+        id,
+        args,
+        output,
+    })
+}
+
+fn result_ok_type(ty: &Type) -> Option<Type> {
+    let segment = match ty {
+        Type::Path(path) => path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Result" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(GenericArgument::Type(ok)) => Some(ok.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// `async fn` in a trait makes no promise that its future is Send, which the
+// server needs, so the method is declared as returning a Send future
+// instead. Implementations can still be written as `async fn`.
+fn rewrite_as_send_future(method: &mut TraitItemMethod) {
+    let sig = &mut method.sig;
+    sig.asyncness = None;
+    let output = match &sig.output {
+        ReturnType::Default => quote! { () },
+        ReturnType::Type(_, ty) => quote! { #ty },
+    };
+    sig.output = syn::parse_quote! {
+        -> impl ::std::future::Future<Output = #output> + Send
+    };
+}
@@ -0,0 +1,198 @@
+use crate::reflection::REFLECTION_PROCEDURE;
+use crate::{Handler, HandlerNode, Request, Response, Status};
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+static ADMISSION_INITIAL_LIMIT: Lazy<f64> = Lazy::new(|| {
+    std::env::var("ADMISSION_INITIAL_LIMIT")
+        .map(|val| {
+            val.parse()
+                .expect("ADMISSION_INITIAL_LIMIT must be a number")
+        })
+        .unwrap_or(100.0)
+});
+
+static ADMISSION_MIN_LIMIT: Lazy<f64> = Lazy::new(|| {
+    std::env::var("ADMISSION_MIN_LIMIT")
+        .map(|val| val.parse().expect("ADMISSION_MIN_LIMIT must be a number"))
+        .unwrap_or(4.0)
+});
+
+static ADMISSION_MAX_LIMIT: Lazy<f64> = Lazy::new(|| {
+    std::env::var("ADMISSION_MAX_LIMIT")
+        .map(|val| val.parse().expect("ADMISSION_MAX_LIMIT must be a number"))
+        .unwrap_or(1000.0)
+});
+
+// How many times slower than the fastest recent requests the average may get
+// before the limit is cut.
+static ADMISSION_LATENCY_TOLERANCE: Lazy<f64> = Lazy::new(|| {
+    std::env::var("ADMISSION_LATENCY_TOLERANCE")
+        .map(|val| {
+            val.parse()
+                .expect("ADMISSION_LATENCY_TOLERANCE must be a number")
+        })
+        .unwrap_or(2.0)
+});
+
+pub(crate) static ADMISSION_MIN_RETRY_AFTER_MS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("ADMISSION_MIN_RETRY_AFTER_MS")
+        .map(|val| {
+            val.parse()
+                .expect("ADMISSION_MIN_RETRY_AFTER_MS must be a number")
+        })
+        .unwrap_or(50)
+});
+
+// Latency jitter below this never counts as the server slowing down, so
+// handlers that take microseconds don't shrink the limit on noise.
+const LATENCY_SLACK: Duration = Duration::from_millis(5);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5);
+const BACKOFF: f64 = 0.9;
+// Weight of each new request in the smoothed latency.
+const SMOOTHING: f64 = 0.1;
+
+struct Limiter {
+    limit: f64,
+    in_flight: usize,
+    // The fastest recent latency, taken as what the server does unloaded.
+    // It creeps up towards slower requests so a lasting change is accepted.
+    baseline: Option<Duration>,
+    smoothed: Duration,
+    last_backoff: Instant,
+}
+
+// Caps how many requests run at once with an AIMD limit: it grows by one
+// for every `limit` requests answered while busy, and shrinks by a tenth
+// whenever the smoothed latency drifts from the baseline by more than the
+// tolerance or a request runs out of time. Requests over the limit are
+// answered Overloaded straight away, with a hint of how long to back off.
+// Reflection is always admitted, so rpcctl works on an overloaded server.
+#[derive(Clone)]
+pub struct AdmissionControl {
+    limiter: Arc<Mutex<Limiter>>,
+    min_limit: f64,
+    max_limit: f64,
+}
+
+impl Default for AdmissionControl {
+    fn default() -> Self {
+        AdmissionControl::with_limits(
+            *ADMISSION_INITIAL_LIMIT,
+            *ADMISSION_MIN_LIMIT,
+            *ADMISSION_MAX_LIMIT,
+        )
+    }
+}
+
+impl AdmissionControl {
+    pub fn new() -> Self {
+        AdmissionControl::default()
+    }
+
+    pub fn with_limits(initial: f64, min: f64, max: f64) -> Self {
+        AdmissionControl {
+            limiter: Arc::new(Mutex::new(Limiter {
+                limit: initial.clamp(min, max),
+                in_flight: 0,
+                baseline: None,
+                smoothed: Duration::ZERO,
+                last_backoff: Instant::now(),
+            })),
+            min_limit: min,
+            max_limit: max,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limiter.lock().unwrap().limit as usize
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.limiter.lock().unwrap().in_flight
+    }
+
+    fn try_admit(&self) -> Result<Admitted<'_>, Duration> {
+        let mut limiter = self.limiter.lock().unwrap();
+        if limiter.in_flight >= limiter.limit as usize {
+            let min = Duration::from_millis(*ADMISSION_MIN_RETRY_AFTER_MS);
+            return Err(limiter.smoothed.clamp(min, MAX_RETRY_AFTER.max(min)));
+        }
+        limiter.in_flight += 1;
+        Ok(Admitted {
+            admission: self,
+            started: Instant::now(),
+            status: None,
+        })
+    }
+
+    fn complete(&self, status: Status, elapsed: Duration) {
+        let mut limiter = self.limiter.lock().unwrap();
+        let busy = limiter.in_flight * 2 >= limiter.limit as usize;
+        limiter.in_flight = limiter.in_flight.saturating_sub(1);
+
+        let baseline = match limiter.baseline {
+            Some(baseline) if elapsed > baseline => baseline + (elapsed - baseline).mul_f64(0.01),
+            _ => elapsed,
+        };
+        limiter.baseline = Some(baseline);
+        limiter.smoothed = limiter.smoothed.mul_f64(1.0 - SMOOTHING) + elapsed.mul_f64(SMOOTHING);
+
+        let threshold = baseline
+            .mul_f64(*ADMISSION_LATENCY_TOLERANCE)
+            .max(baseline + LATENCY_SLACK);
+        let slow = limiter.smoothed > threshold || status == Status::DeadlineExceeded;
+        // One cut per round trip, so a burst of slow answers to requests that
+        // were all admitted together doesn't collapse the limit.
+        if slow && limiter.last_backoff.elapsed() >= limiter.smoothed {
+            limiter.limit = (limiter.limit * BACKOFF).max(self.min_limit);
+            limiter.last_backoff = Instant::now();
+        } else if !slow && busy {
+            limiter.limit = (limiter.limit + 1.0 / limiter.limit).min(self.max_limit);
+        }
+    }
+}
+
+// Holds a slot until it is dropped. A handler dropped before it answered was
+// cut off at its deadline, which counts against the limit.
+struct Admitted<'a> {
+    admission: &'a AdmissionControl,
+    started: Instant,
+    status: Option<Status>,
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        let status = self.status.unwrap_or(Status::DeadlineExceeded);
+        self.admission.complete(status, self.started.elapsed());
+    }
+}
+
+impl Handler for AdmissionControl {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        node: &'a HandlerNode,
+    ) -> Pin<Box<dyn Future<Output = Response> + Send + 'a>> {
+        Box::pin(async move {
+            if request.procedure_id == REFLECTION_PROCEDURE {
+                return node.call_next(request).await;
+            }
+            let mut admitted = match self.try_admit() {
+                Ok(admitted) => admitted,
+                Err(retry_after) => {
+                    return Response::overloaded(
+                        format!("too many requests in flight (limit {})", self.limit()),
+                        retry_after,
+                    )
+                }
+            };
+            let response = node.call_next(request).await;
+            admitted.status = Some(response.status);
+            response
+        })
+    }
+}
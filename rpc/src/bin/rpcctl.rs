@@ -0,0 +1,66 @@
+use rpc::reflection::{self, ServiceDescription};
+use rpc::{Client, Request};
+use std::collections::HashMap;
+use std::process;
+
+const USAGE: &str = "usage: rpcctl <address> describe
+       rpcctl <address> <procedure name or id> [field=value ...]
+
+Vec fields take comma-separated values, e.g. ids=1,2,3.";
+
+fn print_description(description: &ServiceDescription) {
+    println!("{} {}", description.name, description.version);
+    for procedure in &description.procedures {
+        println!("  {}", procedure);
+    }
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        fail(USAGE);
+    }
+    let addr = &args[0];
+
+    let description = reflection::describe(addr)
+        .await
+        .unwrap_or_else(|e| fail(format!("Failed to describe {}: {}", addr, e)));
+    if args[1] == "describe" {
+        print_description(&description);
+        if let Ok(protocol) = Client::global().protocol(addr).await {
+            println!("protocol {}", protocol);
+        }
+        return;
+    }
+
+    let procedure = description.find(&args[1]).unwrap_or_else(|| {
+        print_description(&description);
+        fail(format!("{} has no procedure {}", description.name, args[1]))
+    });
+
+    let mut values = HashMap::new();
+    for arg in &args[2..] {
+        match arg.split_once('=') {
+            Some((field, value)) => values.insert(field.to_string(), value.to_string()),
+            None => fail(format!("expected field=value, got {}\n\n{}", arg, USAGE)),
+        };
+    }
+    let payload = procedure
+        .args
+        .encode(&values)
+        .unwrap_or_else(|e| fail(format!("{}\n  {}", e, procedure)));
+
+    match Client::global()
+        .call(addr, Request::new(procedure.id, payload))
+        .await
+    {
+        Ok(response) => println!("{}", response.payload),
+        Err(e) => fail(e),
+    }
+}
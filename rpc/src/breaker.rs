@@ -0,0 +1,130 @@
+use crate::{Error, Response, Status};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static FAILURE_THRESHOLD: Lazy<u32> = Lazy::new(|| {
+    std::env::var("BREAKER_FAILURE_THRESHOLD")
+        .map(|val| {
+            val.parse()
+                .expect("BREAKER_FAILURE_THRESHOLD must be a number")
+        })
+        .unwrap_or(5)
+});
+
+static OPEN_MS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("BREAKER_OPEN_MS")
+        .map(|val| val.parse().expect("BREAKER_OPEN_MS must be a number"))
+        .unwrap_or(5000)
+});
+
+static GLOBAL: Lazy<CircuitBreakers> =
+    Lazy::new(|| CircuitBreakers::new(*FAILURE_THRESHOLD, Duration::from_millis(*OPEN_MS)));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    // Calls go through; consecutive failures are counted.
+    Closed,
+    // Calls fail immediately until the open period is over.
+    Open,
+    // One probe call is let through; its outcome closes or reopens the breaker.
+    HalfOpen,
+}
+
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+}
+
+// One breaker per address. Only failures that say something about the
+// target's health (unreachable, timed out) count; an error status like
+// InvalidArgument means the server is up and answering.
+pub struct CircuitBreakers {
+    failure_threshold: u32,
+    open_duration: Duration,
+    breakers: Mutex<HashMap<String, Breaker>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        CircuitBreakers {
+            failure_threshold,
+            open_duration,
+            breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn global() -> &'static CircuitBreakers {
+        &GLOBAL
+    }
+
+    pub fn state(&self, addr: &str) -> BreakerState {
+        self.breakers
+            .lock()
+            .unwrap()
+            .get(addr)
+            .map(|b| b.state)
+            .unwrap_or(BreakerState::Closed)
+    }
+
+    // Must be followed by `record` for the call it lets through.
+    pub fn acquire(&self, addr: &str) -> Result<(), Error> {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = match breakers.get_mut(addr) {
+            Some(breaker) => breaker,
+            None => return Ok(()),
+        };
+
+        match breaker.state {
+            BreakerState::Closed => Ok(()),
+            // Re-checked while half-open too, so a probe whose outcome is
+            // never recorded can't keep the circuit shut for good.
+            _ if breaker.opened_at.elapsed() >= self.open_duration => {
+                println!("Circuit for {} is half-open, sending a probe", addr);
+                breaker.state = BreakerState::HalfOpen;
+                breaker.opened_at = Instant::now();
+                Ok(())
+            }
+            BreakerState::Open | BreakerState::HalfOpen => Err(Error::Status(
+                Status::Unavailable,
+                format!("circuit open for {}", addr),
+            )),
+        }
+    }
+
+    pub fn record(&self, addr: &str, result: &Result<Response, Error>) {
+        let failed = matches!(result, Err(err) if err.is_retryable());
+        let mut breakers = self.breakers.lock().unwrap();
+
+        if !failed {
+            if let Some(breaker) = breakers.remove(addr) {
+                if breaker.state != BreakerState::Closed {
+                    println!("Circuit for {} closed", addr);
+                }
+            }
+            return;
+        }
+
+        let breaker = breakers.entry(addr.to_string()).or_insert(Breaker {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_at: Instant::now(),
+        });
+        breaker.consecutive_failures += 1;
+        let trips = match breaker.state {
+            BreakerState::Closed => breaker.consecutive_failures >= self.failure_threshold,
+            BreakerState::HalfOpen => true,
+            BreakerState::Open => false,
+        };
+        if trips {
+            println!(
+                "Circuit for {} opened after {} consecutive failures",
+                addr, breaker.consecutive_failures
+            );
+            breaker.state = BreakerState::Open;
+            breaker.opened_at = Instant::now();
+        }
+    }
+}
@@ -1,18 +1,263 @@
-use crate::{Request, Response};
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-
-pub async fn send_request(server_addr: &str, request: Request) -> io::Result<Response> {
-    let mut stream = TcpStream::connect(server_addr).await?;
-    let serialized = format!("{}:{}\n", request.procedure_id, request.payload);
-    println!("Sending: {}", serialized);
-    stream.write_all(serialized.as_bytes()).await?;
-
-    let mut buffer = vec![0u8; 1024];
-    let n = stream.read(&mut buffer).await?;
-    let response_data = String::from_utf8_lossy(&buffer[..n]);
-
-    Ok(Response {
-        payload: response_data.to_string(),
+use crate::connection::{Connection, IO_RUNTIME};
+use crate::protocol::Protocol;
+use crate::{deadline, frame, trace, Error, ProcedureId, Request, Response, Status};
+use normalization::{Deserializable, Serializable};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::time::{sleep, Duration, Instant};
+
+static MAX_CONNECTIONS_PER_ADDRESS: Lazy<usize> = Lazy::new(|| {
+    std::env::var("MAX_CONNECTIONS_PER_ADDRESS")
+        .map(|val| {
+            val.parse()
+                .expect("MAX_CONNECTIONS_PER_ADDRESS must be a number")
+        })
+        .unwrap_or(4)
+});
+
+static MAX_IN_FLIGHT_PER_CONNECTION: Lazy<usize> = Lazy::new(|| {
+    std::env::var("MAX_IN_FLIGHT_PER_CONNECTION")
+        .map(|val| {
+            val.parse()
+                .expect("MAX_IN_FLIGHT_PER_CONNECTION must be a number")
+        })
+        .unwrap_or(32)
+});
+
+static IDLE_TIMEOUT_SECS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("IDLE_TIMEOUT_SECS")
+        .map(|val| val.parse().expect("IDLE_TIMEOUT_SECS must be a number"))
+        .unwrap_or(60)
+});
+
+static GLOBAL: Lazy<Client> = Lazy::new(|| {
+    Client::new(ClientConfig {
+        max_connections_per_address: *MAX_CONNECTIONS_PER_ADDRESS,
+        max_in_flight_per_connection: *MAX_IN_FLIGHT_PER_CONNECTION,
+        idle_timeout: Duration::from_secs(*IDLE_TIMEOUT_SECS),
+        failure_threshold: 3,
     })
+});
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub max_connections_per_address: usize,
+    // A new connection is opened once every pooled one has this many calls waiting.
+    pub max_in_flight_per_connection: usize,
+    pub idle_timeout: Duration,
+    // Consecutive transport failures before every connection to an address is dropped.
+    pub failure_threshold: u32,
+}
+
+struct PooledConnection {
+    conn: Arc<Connection>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct AddressPool {
+    connections: Vec<PooledConnection>,
+    consecutive_failures: u32,
+}
+
+struct Inner {
+    config: ClientConfig,
+    pools: Mutex<HashMap<String, AddressPool>>,
+}
+
+// Keeps a pool of multiplexed connections per address. Clones share the same pools.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Client {
+        let inner = Arc::new(Inner {
+            config,
+            pools: Mutex::new(HashMap::new()),
+        });
+        IO_RUNTIME.spawn(reap_idle(Arc::downgrade(&inner)));
+        Client { inner }
+    }
+
+    // The process-wide client used by send_request and the service helpers.
+    pub fn global() -> &'static Client {
+        &GLOBAL
+    }
+
+    pub async fn call(&self, addr: &str, mut request: Request) -> Result<Response, Error> {
+        // Calls made while handling a request share what is left of its budget.
+        request.deadline = deadline::earliest(request.deadline, deadline::current());
+        // ...and are recorded as children of the span that made them.
+        if request.trace.is_none() {
+            request.trace = trace::current();
+        }
+        if request.is_expired() {
+            return Err(Error::Status(
+                Status::DeadlineExceeded,
+                format!("Deadline expired before calling {}", addr),
+            ));
+        }
+
+        let conn = self.checkout(addr).await?;
+        let result = conn.call(request).await;
+        match &result {
+            Err(Error::Io(_)) => self.record_failure(addr, &conn),
+            _ => self.record_success(addr),
+        }
+        result
+    }
+
+    // Calls a procedure that takes and returns normalization types.
+    pub async fn call_typed<A, R>(
+        &self,
+        addr: &str,
+        procedure_id: ProcedureId,
+        args: &A,
+    ) -> Result<R, Error>
+    where
+        A: Serializable + ?Sized,
+        R: Deserializable,
+    {
+        let response = self
+            .call(addr, Request::new(procedure_id, args.serialize()))
+            .await?;
+        R::deserialize(&response.payload).map_err(|e| {
+            Error::Status(
+                Status::Internal,
+                format!(
+                    "Failed to deserialize {}: {}",
+                    std::any::type_name::<R>(),
+                    e
+                ),
+            )
+        })
+    }
+
+    // What the server at `addr` agreed to speak, connecting if need be.
+    pub async fn protocol(&self, addr: &str) -> Result<Protocol, Error> {
+        Ok(self.checkout(addr).await?.protocol().clone())
+    }
+
+    pub fn connection_count(&self, addr: &str) -> usize {
+        let pools = self.inner.pools.lock().unwrap();
+        pools.get(addr).map(|p| p.connections.len()).unwrap_or(0)
+    }
+
+    pub fn in_flight(&self, addr: &str) -> usize {
+        let pools = self.inner.pools.lock().unwrap();
+        pools
+            .get(addr)
+            .map(|p| p.connections.iter().map(|c| c.conn.in_flight()).sum())
+            .unwrap_or(0)
+    }
+
+    // Drops every pooled connection to an address, e.g. once discovery stops listing it.
+    pub fn evict(&self, addr: &str) {
+        if self.inner.pools.lock().unwrap().remove(addr).is_some() {
+            println!("Evicted connections to {}", addr);
+        }
+    }
+
+    async fn checkout(&self, addr: &str) -> Result<Arc<Connection>, Error> {
+        let config = &self.inner.config;
+        {
+            let mut pools = self.inner.pools.lock().unwrap();
+            let pool = pools.entry(addr.to_string()).or_default();
+            pool.connections.retain(|c| !c.conn.is_closed());
+
+            let full = pool.connections.len() >= config.max_connections_per_address;
+            let least_busy = pool
+                .connections
+                .iter_mut()
+                .min_by_key(|c| c.conn.in_flight());
+            if let Some(pooled) = least_busy {
+                let saturated = pooled.conn.in_flight() >= config.max_in_flight_per_connection;
+                if !saturated || full {
+                    pooled.last_used = Instant::now();
+                    return Ok(pooled.conn.clone());
+                }
+            }
+        }
+
+        let conn = Arc::new(Connection::connect(addr).await?);
+        let mut pools = self.inner.pools.lock().unwrap();
+        let pool = pools.entry(addr.to_string()).or_default();
+        // Callers racing to open a connection may overshoot the limit; the
+        // extra connection is still used for this call, just not pooled.
+        if pool.connections.len() < config.max_connections_per_address {
+            pool.connections.push(PooledConnection {
+                conn: conn.clone(),
+                last_used: Instant::now(),
+            });
+        }
+        Ok(conn)
+    }
+
+    fn record_success(&self, addr: &str) {
+        if let Some(pool) = self.inner.pools.lock().unwrap().get_mut(addr) {
+            pool.consecutive_failures = 0;
+        }
+    }
+
+    fn record_failure(&self, addr: &str, conn: &Arc<Connection>) {
+        let mut pools = self.inner.pools.lock().unwrap();
+        let evict = match pools.get_mut(addr) {
+            Some(pool) => {
+                pool.connections.retain(|c| !Arc::ptr_eq(&c.conn, conn));
+                pool.consecutive_failures += 1;
+                pool.consecutive_failures >= self.inner.config.failure_threshold
+            }
+            None => false,
+        };
+        if evict {
+            println!("Evicting unhealthy connections to {}", addr);
+            pools.remove(addr);
+        }
+    }
+}
+
+async fn reap_idle(inner: Weak<Inner>) {
+    loop {
+        let idle_timeout = match inner.upgrade() {
+            Some(inner) => inner.config.idle_timeout,
+            None => return,
+        };
+        sleep(idle_timeout / 2).await;
+
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let mut pools = inner.pools.lock().unwrap();
+        for pool in pools.values_mut() {
+            pool.connections.retain(|c| {
+                !c.conn.is_closed()
+                    && (c.conn.in_flight() > 0 || c.last_used.elapsed() < idle_timeout)
+            });
+        }
+        pools.retain(|_, pool| !pool.connections.is_empty());
+    }
+}
+
+pub async fn send_request(server_addr: &str, request: Request) -> Result<Response, Error> {
+    println!(
+        "Sending: {}:{}",
+        request.procedure_id,
+        frame::preview(&request.payload)
+    );
+    Client::global().call(server_addr, request).await
+}
+
+// Client::call_typed on the global client.
+pub async fn call<A, R>(server_addr: &str, procedure_id: ProcedureId, args: &A) -> Result<R, Error>
+where
+    A: Serializable + ?Sized,
+    R: Deserializable,
+{
+    Client::global()
+        .call_typed(server_addr, procedure_id, args)
+        .await
 }
@@ -1,9 +1,10 @@
-use crate::{Request, Response};
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::time::{sleep, Duration};
-use rand::Rng;
+use crate::breaker::CircuitBreakers;
+use crate::retry::RetryBudget;
+use crate::{deadline, frame, Client, Error, Procedures, Request, Response};
 use once_cell::sync::Lazy;
+use rand::Rng;
+use tokio::io;
+use tokio::time::{sleep, Duration};
 
 // const MAX_RETRIES: usize = 3;
 // const BASE_DELAY_MS: u64 = 100;
@@ -27,49 +28,84 @@ static JITTER_MS: Lazy<u64> = Lazy::new(|| {
         .unwrap_or(50)
 });
 
-pub async fn send_request(server_addr: &str, request: Request) -> io::Result<Response> {
-    let serialized = format!("{}:{}\n", request.procedure_id, request.payload);
+// The timeout covers every attempt, including the delays between retries.
+pub async fn send_request_with_timeout(
+    server_addr: &str,
+    request: Request,
+    timeout: Duration,
+) -> Result<Response, Error> {
+    send_request(server_addr, request.with_timeout(timeout)).await
+}
+
+// Without the service's Procedures, every call is taken to be unsafe to repeat.
+pub async fn send_request(server_addr: &str, request: Request) -> Result<Response, Error> {
+    send_request_with_procedures(server_addr, request, &Procedures::new(&[])).await
+}
+
+pub async fn send_request_with_procedures(
+    server_addr: &str,
+    mut request: Request,
+    procedures: &Procedures,
+) -> Result<Response, Error> {
+    request.deadline = deadline::earliest(request.deadline, deadline::current());
+    RetryBudget::global().deposit();
 
     for attempt in 1..=*MAX_RETRIES {
-        match attempt_request(server_addr, &serialized).await {
+        // An open circuit fails fast, and retrying it would only do the same.
+        CircuitBreakers::global().acquire(server_addr)?;
+
+        let result = attempt_request(server_addr, &request).await;
+        CircuitBreakers::global().record(server_addr, &result);
+
+        match result {
             Ok(response) => return Ok(response),
             Err(err) => {
-                if attempt == *MAX_RETRIES {
+                if attempt == *MAX_RETRIES
+                    || !should_retry(procedures, &request, &err)
+                    || request.is_expired()
+                {
                     return Err(err);
                 }
-                eprintln!("Retrying request");
-                let jittered_delay = *BASE_DELAY_MS + rand::thread_rng().gen_range(0..*JITTER_MS);
-                sleep(Duration::from_millis(jittered_delay)).await;
+                if !RetryBudget::global().try_withdraw() {
+                    eprintln!(
+                        "Retry budget exhausted, not retrying request to {}",
+                        server_addr
+                    );
+                    return Err(err);
+                }
+                // An overloaded server says how long to back off; the jitter
+                // keeps callers it turned away together from coming back
+                // together.
+                let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..*JITTER_MS));
+                let delay = match err.retry_after() {
+                    Some(retry_after) => retry_after + jitter,
+                    None => Duration::from_millis(*BASE_DELAY_MS) + jitter,
+                };
+                if let Some(deadline) = request.deadline {
+                    if deadline::remaining(deadline) < delay {
+                        return Err(err);
+                    }
+                }
+                eprintln!("Retrying request in {:?}", delay);
+                sleep(delay).await;
             }
         }
     }
 
-    Err(io::Error::new(io::ErrorKind::Other, "Reached max retries"))
+    Err(Error::Io(io::Error::other("Reached max retries")))
 }
 
-async fn attempt_request(server_addr: &str, serialized: &str) -> io::Result<Response> {
-    let mut stream = TcpStream::connect(server_addr).await?;
-    println!("Sending: {}", serialized);
-    stream.write_all(serialized.as_bytes()).await?;
-
-    let mut buffer = vec![0u8; 1024];
-    let n = stream.read(&mut buffer).await?;
-    let response_data = String::from_utf8_lossy(&buffer[..n]);
+// Errors like an unknown procedure will fail the same way every time, and a
+// call that may have run on the server is only repeated if it is idempotent.
+fn should_retry(procedures: &Procedures, request: &Request, err: &Error) -> bool {
+    err.is_retryable() && (procedures.is_idempotent(request.procedure_id) || err.was_rejected())
+}
 
-    match &*response_data {
-        "Operation timed out" => {
-            Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"))
-        }
-        "Unknown procedure" => {
-            Err(io::Error::new(io::ErrorKind::Unsupported, "Unknown procedure"))
-        }
-        "Service not available" => {
-            Err(io::Error::new(io::ErrorKind::NotFound, "Service not available"))
-        }
-        _ => {
-            Ok(Response {
-                payload: response_data.to_string(),
-            })
-        }
-    }
+async fn attempt_request(server_addr: &str, request: &Request) -> Result<Response, Error> {
+    println!(
+        "Sending: {}:{}",
+        request.procedure_id,
+        frame::preview(&request.payload)
+    );
+    Client::global().call(server_addr, request.clone()).await
 }
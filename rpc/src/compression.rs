@@ -0,0 +1,143 @@
+use crate::metrics::Metrics;
+use once_cell::sync::Lazy;
+use tokio::io;
+
+// Payloads at least this many bytes long are compressed, on connections
+// where both sides negotiated the compression capability.
+pub static COMPRESSION_THRESHOLD: Lazy<usize> = Lazy::new(|| {
+    std::env::var("COMPRESSION_THRESHOLD")
+        .map(|val| val.parse().expect("COMPRESSION_THRESHOLD must be a number"))
+        .unwrap_or(4096)
+});
+
+// The frame header value for payloads compressed with `compress`.
+pub const ENCODING: &str = "lz";
+
+// A compressed payload is the original length as 4 big-endian bytes, then a
+// sequence of tokens. A token byte below 0x80 is followed by that many plus
+// one literal bytes. Otherwise its low seven bits plus MIN_MATCH are the
+// length of a copy of earlier output, and the next two big-endian bytes are
+// how far back it starts. Copies may overlap what they produce, so a run of
+// one byte is a literal and a single copy.
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+const MAX_LITERALS: usize = 0x80;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn flush_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+// Greedy LZ77: each position is looked up in a table of where its first four
+// bytes were last seen, and a match is taken whenever there is one.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 8);
+    out.extend_from_slice(&(input.len() as u32).to_be_bytes());
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    while pos + MIN_MATCH <= input.len() {
+        let slot = hash(&input[pos..]);
+        let candidate = table[slot];
+        table[slot] = pos;
+        let found = candidate != usize::MAX
+            && pos - candidate <= MAX_OFFSET
+            && input[candidate..candidate + MIN_MATCH] == input[pos..pos + MIN_MATCH];
+        if !found {
+            pos += 1;
+            continue;
+        }
+
+        let mut len = MIN_MATCH;
+        while len < MAX_MATCH
+            && pos + len < input.len()
+            && input[candidate + len] == input[pos + len]
+        {
+            len += 1;
+        }
+        flush_literals(&mut out, &input[literal_start..pos]);
+        out.push(0x80 | (len - MIN_MATCH) as u8);
+        out.extend_from_slice(&((pos - candidate) as u16).to_be_bytes());
+        pos += len;
+        literal_start = pos;
+    }
+    flush_literals(&mut out, &input[literal_start..]);
+    out
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Bad compressed payload: {}", message),
+    )
+}
+
+pub fn decompress(input: &[u8]) -> io::Result<Vec<u8>> {
+    if input.len() < 4 {
+        return Err(invalid("missing length"));
+    }
+    let len = u32::from_be_bytes([input[0], input[1], input[2], input[3]]) as usize;
+    if len > *crate::frame::MAX_FRAME_SIZE {
+        return Err(invalid("too long"));
+    }
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 4;
+
+    while pos < input.len() {
+        let token = input[pos] as usize;
+        pos += 1;
+        if token < 0x80 {
+            let literals = input
+                .get(pos..pos + token + 1)
+                .ok_or_else(|| invalid("truncated literals"))?;
+            out.extend_from_slice(literals);
+            pos += token + 1;
+        } else {
+            let offset = input
+                .get(pos..pos + 2)
+                .ok_or_else(|| invalid("truncated copy"))?;
+            let offset = u16::from_be_bytes([offset[0], offset[1]]) as usize;
+            pos += 2;
+            if offset == 0 || offset > out.len() {
+                return Err(invalid("copy from before the start"));
+            }
+            let start = out.len() - offset;
+            for i in 0..(token & 0x7f) + MIN_MATCH {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+        if out.len() > len {
+            return Err(invalid("longer than its length"));
+        }
+    }
+    if out.len() != len {
+        return Err(invalid("shorter than its length"));
+    }
+    Ok(out)
+}
+
+// The compressed payload if it is worth sending instead, recording how well
+// it compressed.
+pub(crate) fn compress_payload(payload: &str) -> Option<Vec<u8>> {
+    if payload.len() < *COMPRESSION_THRESHOLD {
+        return None;
+    }
+    let compressed = compress(payload.as_bytes());
+    Metrics::global().record_compression(payload.len(), compressed.len());
+    if compressed.len() < payload.len() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
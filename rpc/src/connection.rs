@@ -0,0 +1,251 @@
+use crate::protocol::{self, Protocol};
+use crate::{deadline, frame, transport, Error, Request, RequestId, Response, Status};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+type Pending = Arc<Mutex<HashMap<RequestId, oneshot::Sender<Response>>>>;
+
+// Shared connections outlive the runtime of whoever opened them (discovery
+// registers from its own thread, tests each get a runtime), so their sockets
+// are driven by a runtime that belongs to the rpc crate.
+pub(crate) static IO_RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("rpc-io")
+        .enable_all()
+        .build()
+        .expect("Failed to start rpc io runtime")
+});
+
+// A single connection that many callers can share. Each call gets its own
+// request id, so calls can be pipelined and answered in any order. The
+// protocol is agreed with the server before the first call.
+pub struct Connection {
+    address: String,
+    protocol: Protocol,
+    outgoing: mpsc::UnboundedSender<Request>,
+    pending: Pending,
+    next_id: AtomicU64,
+    closed: Arc<AtomicBool>,
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    pub async fn connect(address: &str) -> io::Result<Connection> {
+        let connect_address = address.to_string();
+        let (stream, protocol) = IO_RUNTIME
+            .spawn(async move {
+                let mut stream = transport::connect(&connect_address).await?;
+                let protocol = protocol::handshake(&mut stream, &connect_address).await?;
+                if !protocol.supports(protocol::FRAMING) {
+                    stream = transport::connect(&connect_address).await?;
+                }
+                Ok::<_, io::Error>((stream, protocol))
+            })
+            .await
+            .map_err(io::Error::other)??;
+
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (outgoing, mut queued) = mpsc::unbounded_channel::<Request>();
+        if !protocol.supports(protocol::FRAMING) {
+            let reader = spawn_unframed(stream, queued, pending.clone(), closed.clone(), address);
+            return Ok(Connection {
+                address: address.to_string(),
+                protocol,
+                outgoing,
+                pending,
+                next_id: AtomicU64::new(1),
+                closed,
+                reader,
+            });
+        }
+        let (mut read_half, mut write_half) = io::split(stream);
+
+        let writer_closed = closed.clone();
+        let writer_pending = pending.clone();
+        let compress = protocol.supports(protocol::COMPRESSION);
+        IO_RUNTIME.spawn(async move {
+            while let Some(request) = queued.recv().await {
+                let body = frame::encode_request_with(&request, compress);
+                if let Err(e) = frame::write_frame(&mut write_half, &body).await {
+                    eprintln!("Failed to send request {}: {}", request.id, e);
+                    writer_closed.store(true, Ordering::SeqCst);
+                    writer_pending.lock().unwrap().clear();
+                    break;
+                }
+            }
+        });
+
+        let reader_pending = pending.clone();
+        let reader_closed = closed.clone();
+        let reader_address = address.to_string();
+        let reader = IO_RUNTIME.spawn(async move {
+            loop {
+                match frame::read_response(&mut read_half).await {
+                    Ok(response) => {
+                        let waiter = reader_pending.lock().unwrap().remove(&response.id);
+                        match waiter {
+                            Some(waiter) => {
+                                let _ = waiter.send(response);
+                            }
+                            None => eprintln!(
+                                "Dropping response {} from {} with no caller",
+                                response.id, reader_address
+                            ),
+                        }
+                    }
+                    Err(e) => {
+                        if e.kind() != io::ErrorKind::UnexpectedEof {
+                            eprintln!("Connection to {} failed: {}", reader_address, e);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            // Dropping the senders wakes every caller still waiting on this connection.
+            reader_closed.store(true, Ordering::SeqCst);
+            reader_pending.lock().unwrap().clear();
+        });
+
+        Ok(Connection {
+            address: address.to_string(),
+            protocol,
+            outgoing,
+            pending,
+            next_id: AtomicU64::new(1),
+            closed,
+            reader,
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub async fn call(&self, mut request: Request) -> Result<Response, Error> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        request.id = id;
+        let deadline = request.deadline;
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        let _pending = PendingCall {
+            pending: &self.pending,
+            id,
+        };
+
+        // Checked after registering, so a connection that shuts down
+        // concurrently either rejects the call here or drops its sender.
+        if self.is_closed() || self.outgoing.send(request).is_err() {
+            return Err(connection_closed(&self.address));
+        }
+
+        let response = match deadline {
+            Some(deadline) => {
+                match tokio::time::timeout(deadline::remaining(deadline), receiver).await {
+                    Ok(response) => response,
+                    Err(_) => {
+                        // The server gives up at the same deadline; a late response is dropped.
+                        return Err(Error::Status(
+                            Status::DeadlineExceeded,
+                            format!("No response from {} before the deadline", self.address),
+                        ));
+                    }
+                }
+            }
+            None => receiver.await,
+        };
+
+        match response {
+            Ok(response) => response.into_result(),
+            Err(_) => Err(connection_closed(&self.address)),
+        }
+    }
+}
+
+// Forgets a call once its caller stops waiting, whether it timed out or was
+// dropped (like the losing copy of a hedged request), so the connection no
+// longer counts it as in flight. A late response is dropped by the reader.
+struct PendingCall<'a> {
+    pending: &'a Pending,
+    id: RequestId,
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+// Servers from before framing read one request, answer it and only then
+// read the next, with nothing to tell answers apart, so calls go out one at a
+// time and each answer belongs to the call before it.
+fn spawn_unframed(
+    mut stream: transport::BoxStream,
+    mut queued: mpsc::UnboundedReceiver<Request>,
+    pending: Pending,
+    closed: Arc<AtomicBool>,
+    address: &str,
+) -> JoinHandle<()> {
+    let address = address.to_string();
+    IO_RUNTIME.spawn(async move {
+        while let Some(request) = queued.recv().await {
+            if !pending.lock().unwrap().contains_key(&request.id) {
+                continue;
+            }
+            let exchange = async {
+                frame::write_legacy_request(&mut stream, &request).await?;
+                frame::read_legacy_response(&mut stream).await
+            };
+            match exchange.await {
+                Ok(mut response) => {
+                    response.id = request.id;
+                    if let Some(waiter) = pending.lock().unwrap().remove(&request.id) {
+                        let _ = waiter.send(response);
+                    }
+                }
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::UnexpectedEof {
+                        eprintln!("Connection to {} failed: {}", address, e);
+                    }
+                    break;
+                }
+            }
+        }
+
+        closed.store(true, Ordering::SeqCst);
+        pending.lock().unwrap().clear();
+    })
+}
+
+fn connection_closed(address: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        format!("Connection to {} closed", address),
+    ))
+}
@@ -0,0 +1,75 @@
+use crate::{Response, Status};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Deadlines travel between processes, so they are absolute wall-clock times
+// in milliseconds since the unix epoch rather than Instants.
+pub type Deadline = u64;
+
+tokio::task_local! {
+    static CURRENT: Option<Deadline>;
+}
+
+pub fn now() -> Deadline {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as Deadline)
+        .unwrap_or(0)
+}
+
+pub fn after(timeout: Duration) -> Deadline {
+    now() + timeout.as_millis() as Deadline
+}
+
+pub fn remaining(deadline: Deadline) -> Duration {
+    Duration::from_millis(deadline.saturating_sub(now()))
+}
+
+pub fn is_expired(deadline: Deadline) -> bool {
+    deadline <= now()
+}
+
+pub fn earliest(a: Option<Deadline>, b: Option<Deadline>) -> Option<Deadline> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// The deadline of the request being handled by the current task, if any.
+// Outgoing calls made from inside a handler inherit it automatically.
+pub fn current() -> Option<Deadline> {
+    CURRENT.try_with(|deadline| *deadline).ok().flatten()
+}
+
+pub async fn scope<F: Future>(deadline: Option<Deadline>, future: F) -> F::Output {
+    CURRENT.scope(deadline, future).await
+}
+
+// Runs a handler under the request's deadline: expired requests are rejected
+// without running the handler, and a handler still running at the deadline
+// is dropped.
+pub async fn serve<F: Future<Output = Response>>(
+    deadline: Option<Deadline>,
+    handler: F,
+) -> Response {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return handler.await,
+    };
+
+    if is_expired(deadline) {
+        return Response::error(
+            Status::DeadlineExceeded,
+            "Deadline expired before the request was handled",
+        );
+    }
+
+    match tokio::time::timeout(remaining(deadline), scope(Some(deadline), handler)).await {
+        Ok(response) => response,
+        Err(_) => Response::error(
+            Status::DeadlineExceeded,
+            "Deadline expired while handling the request",
+        ),
+    }
+}
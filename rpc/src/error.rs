@@ -0,0 +1,90 @@
+use crate::{Response, Status};
+use std::fmt;
+use std::time::Duration;
+use tokio::io;
+
+#[derive(Debug)]
+pub enum Error {
+    // The request never got a response: connect, read or write failed.
+    Io(io::Error),
+    // The server answered with a non-OK status and an error message.
+    Status(Status, String),
+    // The server turned the request away without running it, and asked to
+    // be left alone for the given time.
+    Overloaded(String, Duration),
+}
+
+impl Error {
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Io(e) if e.kind() == io::ErrorKind::TimedOut => Status::DeadlineExceeded,
+            Error::Io(_) => Status::Unavailable,
+            Error::Status(status, _) => *status,
+            Error::Overloaded(_, _) => Status::Overloaded,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Overloaded(_, retry_after) => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    // Lets a server pass a downstream failure back to its own caller.
+    pub fn into_response(self) -> Response {
+        let status = self.status();
+        match self {
+            Error::Io(e) => Response::error(status, e.to_string()),
+            Error::Status(_, message) => Response::error(status, message),
+            Error::Overloaded(message, retry_after) => Response::overloaded(message, retry_after),
+        }
+    }
+
+    // Whether sending the same request again could reasonably succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.status(),
+            Status::Unavailable | Status::DeadlineExceeded | Status::Overloaded
+        )
+    }
+
+    // The connection was never established, so the request can't have been
+    // seen by the server and is safe to send again even if not idempotent.
+    pub fn is_connect_failure(&self) -> bool {
+        matches!(self, Error::Io(e) if e.kind() == io::ErrorKind::ConnectionRefused)
+    }
+
+    // Like a connect failure, the server never ran the request.
+    pub fn was_rejected(&self) -> bool {
+        self.is_connect_failure() || matches!(self, Error::Overloaded(_, _))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Status(status, message) if message.is_empty() => write!(f, "{}", status),
+            Error::Status(status, message) => write!(f, "{}: {}", status, message),
+            Error::Overloaded(message, retry_after) => {
+                write!(f, "Overloaded: {} (retry after {:?})", message, retry_after)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Status(_, _) | Error::Overloaded(_, _) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,305 @@
+use crate::connection::IO_RUNTIME;
+use crate::reflection::REFLECTION_PROCEDURE;
+use crate::{
+    trace, Client, Error, Handler, HandlerNode, ProcedureId, Request, Response, Status,
+    SERVICE_NAME,
+};
+use normalization::{Deserializable, Serializable};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// Rules are read from the configuration service, under
+// `faults/<service>/<procedure>`. The procedure is a name, an id or `*`, and
+// the value lists what to inject, e.g. `latency_ms=200,percent=25`,
+// `error=unavailable`, `drop,percent=5` or `corrupt`.
+pub static FAULTS_CONFIG_ADDR: Lazy<String> = Lazy::new(|| {
+    std::env::var("FAULTS_CONFIG_ADDR").unwrap_or_else(|_| "127.0.0.1:10500".to_string())
+});
+
+static FAULTS_POLL_MS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("FAULTS_POLL_MS")
+        .map(|val| val.parse().expect("FAULTS_POLL_MS must be a number"))
+        .unwrap_or(2000)
+});
+
+pub const KEY_PREFIX: &str = "faults/";
+
+// The configuration procedures the rules are read with. rpc can't depend on
+// the configuration crate, which checks these still match its own.
+pub const CONFIG_GET_PROCEDURE: ProcedureId = 1;
+pub const CONFIG_LIST_PROCEDURE: ProcedureId = 4;
+
+#[derive(Debug, Serializable, Deserializable)]
+struct ConfigGetArgs {
+    key: String,
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+struct ConfigGetResult {
+    value: String,
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+struct ConfigListArgs {
+    prefix: String,
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+struct ConfigListResult {
+    keys: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultRule {
+    // A procedure name, an id, or `*` for every procedure.
+    pub procedure: String,
+    pub latency: Option<Duration>,
+    pub error: Option<Status>,
+    // Closes the connection instead of answering.
+    pub drop: bool,
+    // Mangles the response payload so the caller can't decode it.
+    pub corrupt: bool,
+    // How many requests out of 100 are affected.
+    pub percent: f64,
+}
+
+impl FaultRule {
+    pub fn parse(procedure: &str, value: &str) -> Result<FaultRule, String> {
+        let mut rule = FaultRule {
+            procedure: procedure.to_string(),
+            latency: None,
+            error: None,
+            drop: false,
+            corrupt: false,
+            percent: 100.0,
+        };
+        for part in value
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let (key, arg) = match part.split_once('=') {
+                Some((key, arg)) => (key.trim(), Some(arg.trim())),
+                None => (part, None),
+            };
+            let invalid = || format!("invalid fault {} in {}", part, value);
+            match (key, arg) {
+                ("latency_ms", Some(arg)) => {
+                    rule.latency = Some(Duration::from_millis(arg.parse().map_err(|_| invalid())?))
+                }
+                ("error", Some(arg)) => {
+                    rule.error = Some(
+                        Status::from_name(arg)
+                            .filter(|s| *s != Status::Ok)
+                            .ok_or_else(invalid)?,
+                    )
+                }
+                ("percent", Some(arg)) => {
+                    rule.percent = arg.parse().map_err(|_| invalid())?;
+                    if !(0.0..=100.0).contains(&rule.percent) {
+                        return Err(invalid());
+                    }
+                }
+                ("drop", None) => rule.drop = true,
+                ("corrupt", None) => rule.corrupt = true,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(rule)
+    }
+
+    // `name` is what this server's handlers call the procedure, if anything.
+    pub fn matches(&self, procedure_id: ProcedureId, name: Option<&str>) -> bool {
+        self.procedure == "*"
+            || self.procedure == procedure_id.to_string()
+            || Some(self.procedure.as_str()) == name
+    }
+}
+
+// Lets a handler close the connection its request arrived on, for the drop
+// fault. The server checks it before writing each response.
+#[derive(Default)]
+pub(crate) struct DropSignal {
+    dropped: AtomicBool,
+    notify: Notify,
+}
+
+impl DropSignal {
+    pub(crate) fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    pub(crate) async fn dropped(&self) {
+        self.notify.notified().await
+    }
+
+    fn drop_connection(&self) {
+        self.dropped.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
+tokio::task_local! {
+    static CONNECTION: Arc<DropSignal>;
+}
+
+pub(crate) async fn connection_scope<F: Future>(signal: Arc<DropSignal>, future: F) -> F::Output {
+    CONNECTION.scope(signal, future).await
+}
+
+// Injects the faults configured for this service. The reflection procedure
+// is never faulted, so rpcctl keeps working during a game day. A `*` rule on
+// configuration itself also faults the requests that would remove it, so
+// target specific procedures there.
+#[derive(Clone, Default)]
+pub struct FaultInjection {
+    rules: Arc<RwLock<Vec<FaultRule>>>,
+}
+
+static GLOBAL: Lazy<FaultInjection> = Lazy::new(FaultInjection::new);
+static START_POLLING: Once = Once::new();
+
+impl FaultInjection {
+    pub fn new() -> Self {
+        FaultInjection::default()
+    }
+
+    // The one in Handlers::standard(), whose rules are polled from the
+    // configuration service every FAULTS_POLL_MS.
+    pub fn global() -> &'static FaultInjection {
+        START_POLLING.call_once(|| {
+            IO_RUNTIME.spawn(async {
+                loop {
+                    poll().await;
+                    tokio::time::sleep(Duration::from_millis(*FAULTS_POLL_MS)).await;
+                }
+            });
+        });
+        &GLOBAL
+    }
+
+    pub fn rules(&self) -> Vec<FaultRule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    pub fn set_rules(&self, rules: Vec<FaultRule>) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    // The first matching rule whose dice roll comes up, if any.
+    fn pick(&self, procedure_id: ProcedureId, name: Option<&str>) -> Option<FaultRule> {
+        if procedure_id == REFLECTION_PROCEDURE {
+            return None;
+        }
+        let rules = self.rules.read().unwrap();
+        let mut rng = rand::thread_rng();
+        rules
+            .iter()
+            .filter(|rule| rule.matches(procedure_id, name))
+            .find(|rule| rng.gen_range(0.0..100.0) < rule.percent)
+            .cloned()
+    }
+}
+
+impl Handler for FaultInjection {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        node: &'a HandlerNode,
+    ) -> Pin<Box<dyn Future<Output = Response> + Send + 'a>> {
+        Box::pin(async move {
+            let name = node.procedure_name(request.procedure_id);
+            let rule = match self.pick(request.procedure_id, name) {
+                Some(rule) => rule,
+                None => return node.call_next(request).await,
+            };
+            if let Some(latency) = rule.latency {
+                tokio::time::sleep(latency).await;
+            }
+            if rule.drop {
+                let _ = CONNECTION.try_with(|signal| signal.drop_connection());
+                return Response::error(Status::Unavailable, "fault injected: connection dropped");
+            }
+            if let Some(status) = rule.error {
+                return Response::error(status, "fault injected");
+            }
+            let mut response = node.call_next(request).await;
+            if rule.corrupt {
+                response.payload = corrupt(&response.payload);
+            }
+            response
+        })
+    }
+}
+
+fn corrupt(payload: &str) -> String {
+    let half: String = payload.chars().take(payload.chars().count() / 2).collect();
+    format!("{}#corrupted#", half)
+}
+
+// Replaces the global rules with the ones configured for this service. If
+// the configuration service can't be reached the current rules stay.
+async fn poll() {
+    let prefix = format!("{}{}/", KEY_PREFIX, *SERVICE_NAME);
+    match trace::scope(None, read_rules(&prefix)).await {
+        Ok(rules) => {
+            if rules != GLOBAL.rules() {
+                let procedures: Vec<&str> =
+                    rules.iter().map(|rule| rule.procedure.as_str()).collect();
+                println!(
+                    "Fault rules for {}: [{}]",
+                    *SERVICE_NAME,
+                    procedures.join(", ")
+                );
+                GLOBAL.set_rules(rules);
+            }
+        }
+        Err(e) if e.is_connect_failure() => {}
+        Err(e) => println!("Failed to read fault rules: {}", e),
+    }
+}
+
+async fn read_rules(prefix: &str) -> Result<Vec<FaultRule>, Error> {
+    let client = Client::global();
+    let args = ConfigListArgs {
+        prefix: prefix.to_string(),
+    };
+    let response = client
+        .call(
+            &FAULTS_CONFIG_ADDR,
+            Request::new(CONFIG_LIST_PROCEDURE, args.serialize()),
+        )
+        .await?;
+    let keys = ConfigListResult::deserialize(&response.payload)
+        .map_err(|e| Error::Status(Status::Internal, format!("{:?}", e)))?
+        .keys;
+
+    let mut keys: Vec<&str> = keys.split(',').filter(|key| !key.is_empty()).collect();
+    keys.sort_unstable();
+    let mut rules = Vec::new();
+    for key in keys {
+        let args = ConfigGetArgs {
+            key: key.to_string(),
+        };
+        let response = client
+            .call(
+                &FAULTS_CONFIG_ADDR,
+                Request::new(CONFIG_GET_PROCEDURE, args.serialize()),
+            )
+            .await?;
+        let value = ConfigGetResult::deserialize(&response.payload)
+            .map_err(|e| Error::Status(Status::Internal, format!("{:?}", e)))?
+            .value;
+        match FaultRule::parse(&key[prefix.len()..], &value) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => println!("Ignoring {}: {}", key, e),
+        }
+    }
+    Ok(rules)
+}
@@ -0,0 +1,388 @@
+use crate::{compression, trace, Payload, Request, Response, Status, TraceContext};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{
+    self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+
+// Every message on the wire is a 4-byte big-endian length header followed by
+// exactly that many bytes of body.
+const HEADER_LEN: usize = 4;
+
+pub static MAX_FRAME_SIZE: Lazy<usize> = Lazy::new(|| {
+    std::env::var("MAX_FRAME_SIZE")
+        .map(|val| val.parse().expect("MAX_FRAME_SIZE must be a number"))
+        .unwrap_or(16 * 1024 * 1024)
+});
+
+// Returns Ok(None) when the peer closes the connection cleanly between frames.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; HEADER_LEN];
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        let n = reader.read(&mut header[filled..]).await?;
+        if n == 0 {
+            if filled == 0 {
+                return Ok(None);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed inside a frame header",
+            ));
+        }
+        filled += n;
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > *MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Frame of {} bytes exceeds maximum of {}",
+                len, *MAX_FRAME_SIZE
+            ),
+        ));
+    }
+
+    // read_exact keeps reading across partial reads until the body is complete.
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+// A procedure id is an i32, so it has at most this many digits.
+const LEGACY_ID_DIGITS: usize = 10;
+
+// Clients from before framing send `procedure_id:payload\n` and read back the
+// bare payload. A length header can't start with a digit under the default
+// MAX_FRAME_SIZE, so a connection's first bytes tell the two apart: digits,
+// up to LEGACY_ID_DIGITS of them, and then `:`. Returns the bytes read to
+// decide, which still have to be parsed, and whether they are the legacy
+// format.
+pub async fn read_prefix<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(Vec<u8>, bool)> {
+    let mut prefix = Vec::with_capacity(LEGACY_ID_DIGITS + 1);
+    while prefix.len() <= LEGACY_ID_DIGITS {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte).await? == 0 {
+            break;
+        }
+        prefix.push(byte[0]);
+        if !byte[0].is_ascii_digit() {
+            break;
+        }
+    }
+    let digits = prefix
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+    let legacy = digits > 0 && prefix.get(digits) == Some(&b':');
+    Ok((prefix, legacy))
+}
+
+// One `procedure_id:payload` line, or None when the peer has closed the
+// connection. The payload is trimmed, as servers before framing did.
+pub async fn read_legacy_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let (procedure_id, payload) = line
+        .split_once(':')
+        .and_then(|(id, payload)| Some((id.trim().parse().ok()?, payload.trim())))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed legacy request"))?;
+    Ok(Some(Request::new(procedure_id, payload.to_string())))
+}
+
+// Legacy clients had no status and recognised failures by these payloads;
+// errors they had no string for were sent as `ERROR: message`.
+fn legacy_payload(response: &Response) -> String {
+    match response.status {
+        Status::Ok => response.payload.clone(),
+        Status::UnknownProcedure => "Unknown procedure".to_string(),
+        Status::DeadlineExceeded => "Operation timed out".to_string(),
+        Status::Unavailable | Status::Overloaded => "Service not available".to_string(),
+        Status::InvalidArgument | Status::Unauthenticated | Status::Internal => {
+            format!("ERROR: {}", response.payload)
+        }
+    }
+}
+
+pub async fn write_legacy_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: &Response,
+) -> io::Result<()> {
+    writer
+        .write_all(legacy_payload(response).as_bytes())
+        .await?;
+    writer.flush().await
+}
+
+// The client's side of the same format, for servers from before framing.
+pub async fn write_legacy_request<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    request: &Request,
+) -> io::Result<()> {
+    let line = format!("{}:{}\n", request.procedure_id, request.payload);
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await
+}
+
+// Those servers answered with whatever a single read of their reply got, up
+// to this many bytes.
+const LEGACY_RESPONSE_LEN: usize = 1024;
+
+// The old failure strings are turned back into statuses, so callers see the
+// same errors from either kind of server.
+pub async fn read_legacy_response<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Response> {
+    let mut buffer = vec![0u8; LEGACY_RESPONSE_LEN];
+    let n = reader.read(&mut buffer).await?;
+    if n == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed before a response was received",
+        ));
+    }
+    let payload = String::from_utf8_lossy(&buffer[..n]).to_string();
+    Ok(match payload.as_str() {
+        "Unknown procedure" => Response::error(Status::UnknownProcedure, payload),
+        "Operation timed out" => Response::error(Status::DeadlineExceeded, payload),
+        "Service not available" => Response::error(Status::Unavailable, payload),
+        _ => match payload.strip_prefix("ERROR: ") {
+            Some(message) => Response::error(Status::Internal, message),
+            None => Response::ok(payload),
+        },
+    })
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, body: &[u8]) -> io::Result<()> {
+    if body.len() > *MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Frame of {} bytes exceeds maximum of {}",
+                body.len(),
+                *MAX_FRAME_SIZE
+            ),
+        ));
+    }
+
+    let mut buffer = Vec::with_capacity(HEADER_LEN + body.len());
+    buffer.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(body);
+    writer.write_all(&buffer).await?;
+    writer.flush().await
+}
+
+// A message body is one header line of `key=value` pairs separated by `;`,
+// a newline, and then the payload. Values are percent-encoded where they
+// would otherwise end a value or the header line. Unknown header keys are ignored. With
+// `compress`, a large payload is sent compressed and marked with an
+// `encoding` header, which only peers that negotiated compression get.
+fn encode_message(header: &[(&str, String)], payload: &str, compress: bool) -> Vec<u8> {
+    let mut header: Vec<String> = header
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, escape_value(value)))
+        .collect();
+    let compressed = if compress {
+        compression::compress_payload(payload)
+    } else {
+        None
+    };
+    if compressed.is_some() {
+        header.push(format!("encoding={}", compression::ENCODING));
+    }
+    let mut body = header.join(";").into_bytes();
+    body.push(b'\n');
+    match compressed {
+        Some(compressed) => body.extend_from_slice(&compressed),
+        None => body.extend_from_slice(payload.as_bytes()),
+    }
+    body
+}
+
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '%' | ';' | '=' | '\n' | '\r' => escaped.push_str(&format!("%{:02X}", c as u8)),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Anything that isn't a valid escape is kept as it is.
+fn unescape_value(value: &str) -> String {
+    let mut unescaped = Vec::with_capacity(value.len());
+    let bytes = value.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escape {
+            Some(byte) if bytes[i] == b'%' => {
+                unescaped.push(byte);
+                i += 3;
+            }
+            _ => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+
+// At most this much of a payload goes into a log line.
+const PREVIEW_LEN: usize = 200;
+
+pub(crate) fn preview(payload: &str) -> String {
+    if payload.len() <= PREVIEW_LEN {
+        return payload.to_string();
+    }
+    let mut end = PREVIEW_LEN;
+    while !payload.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes)", &payload[..end], payload.len())
+}
+
+fn decode_message(body: &[u8]) -> io::Result<(HashMap<String, String>, Payload)> {
+    let newline = body
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing message header"))?;
+    let header: HashMap<String, String> = String::from_utf8_lossy(&body[..newline])
+        .split(';')
+        .filter_map(|field| field.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), unescape_value(value.trim())))
+        .collect();
+    let payload = &body[newline + 1..];
+    let payload = match header.get("encoding").map(String::as_str) {
+        None => String::from_utf8_lossy(payload).to_string(),
+        Some(compression::ENCODING) => String::from_utf8(compression::decompress(payload)?)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Compressed payload is not UTF-8",
+                )
+            })?,
+        Some(encoding) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown payload encoding {}", encoding),
+            ))
+        }
+    };
+    Ok((header, payload))
+}
+
+fn parse_field<T: std::str::FromStr>(
+    header: &HashMap<String, String>,
+    key: &str,
+) -> io::Result<Option<T>> {
+    match header.get(key) {
+        Some(value) => value.parse().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid {} header: {}", key, value),
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+pub fn encode_request(request: &Request) -> Vec<u8> {
+    encode_request_with(request, false)
+}
+
+// Compresses the payload if it is large enough; only for peers that
+// negotiated compression.
+pub fn encode_request_with(request: &Request, compress: bool) -> Vec<u8> {
+    let mut header = vec![
+        ("id", request.id.to_string()),
+        ("procedure", request.procedure_id.to_string()),
+    ];
+    if let Some(deadline) = request.deadline {
+        header.push(("deadline", deadline.to_string()));
+    }
+    if let Some(token) = &request.token {
+        header.push(("token", token.clone()));
+    }
+    if let Some(context) = request.trace {
+        header.push(("trace", trace::format_id(context.trace_id)));
+        header.push(("span", trace::format_id(context.span_id)));
+    }
+    encode_message(&header, &request.payload, compress)
+}
+
+pub fn decode_request(body: &[u8]) -> io::Result<Request> {
+    let (header, payload) = decode_message(body)?;
+    let procedure_id = parse_field(&header, "procedure")?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing procedure id"))?;
+    Ok(Request {
+        id: parse_field(&header, "id")?.unwrap_or(0),
+        procedure_id,
+        payload,
+        deadline: parse_field(&header, "deadline")?,
+        token: header.get("token").cloned(),
+        trace: decode_trace(&header),
+    })
+}
+
+// A malformed trace header only loses the trace, not the request.
+fn decode_trace(header: &HashMap<String, String>) -> Option<TraceContext> {
+    Some(TraceContext {
+        trace_id: trace::parse_id(header.get("trace")?)?,
+        span_id: trace::parse_id(header.get("span")?)?,
+    })
+}
+
+pub fn encode_response(response: &Response) -> Vec<u8> {
+    encode_response_with(response, false)
+}
+
+pub fn encode_response_with(response: &Response, compress: bool) -> Vec<u8> {
+    let mut header = vec![
+        ("id", response.id.to_string()),
+        ("status", response.status.code().to_string()),
+    ];
+    if let Some(retry_after) = response.retry_after {
+        header.push(("retry_after_ms", retry_after.as_millis().to_string()));
+    }
+    encode_message(&header, &response.payload, compress)
+}
+
+pub fn decode_response(body: &[u8]) -> io::Result<Response> {
+    let (header, payload) = decode_message(body)?;
+    let status = parse_field(&header, "status")?
+        .map(Status::from_code)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing response status"))?;
+    Ok(Response {
+        id: parse_field(&header, "id")?.unwrap_or(0),
+        status,
+        payload,
+        retry_after: parse_field(&header, "retry_after_ms")?.map(Duration::from_millis),
+    })
+}
+
+pub async fn write_request<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    request: &Request,
+) -> io::Result<()> {
+    write_frame(writer, &encode_request(request)).await
+}
+
+pub async fn read_response<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Response> {
+    match read_frame(reader).await? {
+        Some(body) => decode_response(&body),
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed before a response was received",
+        )),
+    }
+}
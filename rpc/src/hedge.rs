@@ -0,0 +1,215 @@
+use crate::{metrics, Client, Error, ProcedureId, Request, Response, Status};
+use once_cell::sync::Lazy;
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+static HEDGE_PERCENTILE: Lazy<f64> = Lazy::new(|| {
+    std::env::var("HEDGE_PERCENTILE")
+        .map(|val| val.parse().expect("HEDGE_PERCENTILE must be a number"))
+        .unwrap_or(95.0)
+});
+
+static HEDGE_DELAY_MS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("HEDGE_DELAY_MS")
+        .map(|val| val.parse().expect("HEDGE_DELAY_MS must be a number"))
+        .unwrap_or(50)
+});
+
+static GLOBAL: Lazy<Hedger> = Lazy::new(|| {
+    Hedger::new(
+        Client::global().clone(),
+        HedgeConfig {
+            percentile: *HEDGE_PERCENTILE,
+            initial_delay: Duration::from_millis(*HEDGE_DELAY_MS),
+            window: 100,
+            min_samples: 10,
+        },
+    )
+});
+
+#[derive(Debug, Clone)]
+pub struct HedgeConfig {
+    // The hedge is sent once the first copy has taken longer than this
+    // percentile of recent latencies for the same procedure.
+    pub percentile: f64,
+    // Used until a procedure has `min_samples` latencies recorded.
+    pub initial_delay: Duration,
+    pub window: usize,
+    pub min_samples: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HedgeStats {
+    pub sent: u64,
+    pub won: u64,
+}
+
+// Sends reads that any replica can answer to one address, and a second copy
+// to another if the first is slow. Only use it for procedures that are safe
+// to run twice. Hedges sent and won are counted here and reported to
+// monitoring as `hedge.sent` and `hedge.won`.
+pub struct Hedger {
+    client: Client,
+    config: HedgeConfig,
+    latencies: Mutex<HashMap<ProcedureId, VecDeque<Duration>>>,
+    sent: AtomicU64,
+    won: AtomicU64,
+}
+
+impl Hedger {
+    pub fn new(client: Client, config: HedgeConfig) -> Self {
+        Hedger {
+            client,
+            config,
+            latencies: Mutex::new(HashMap::new()),
+            sent: AtomicU64::new(0),
+            won: AtomicU64::new(0),
+        }
+    }
+
+    pub fn global() -> &'static Hedger {
+        &GLOBAL
+    }
+
+    pub fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            sent: self.sent.load(Ordering::SeqCst),
+            won: self.won.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn delay(&self, procedure_id: ProcedureId) -> Duration {
+        let latencies = self.latencies.lock().unwrap();
+        let mut samples: Vec<Duration> = match latencies.get(&procedure_id) {
+            Some(samples) if samples.len() >= self.config.min_samples => {
+                samples.iter().copied().collect()
+            }
+            _ => return self.config.initial_delay,
+        };
+        samples.sort();
+        let rank = (self.config.percentile / 100.0 * samples.len() as f64).ceil() as usize;
+        samples[rank.clamp(1, samples.len()) - 1]
+    }
+
+    // The first successful response wins. The other copy is dropped, so its
+    // connection stops waiting for it; the server still finishes it, but
+    // gives up at the request's deadline like any other call.
+    pub async fn call(&self, addrs: &[String], request: Request) -> Result<Response, Error> {
+        let mut chosen = addrs.choose_multiple(&mut rand::thread_rng(), 2);
+        let primary_addr = chosen.next().ok_or_else(|| {
+            Error::Status(
+                Status::Unavailable,
+                "No addresses to send the request to".to_string(),
+            )
+        })?;
+        let hedge_addr = chosen.next();
+        self.race(primary_addr, hedge_addr.map(String::as_str), request)
+            .await
+    }
+
+    // Like `call`, but always sends to `primary` first, and only hedges to
+    // one of `others` when it is slow.
+    pub async fn call_preferring(
+        &self,
+        primary: &str,
+        others: &[String],
+        request: Request,
+    ) -> Result<Response, Error> {
+        let hedge_addr = others.choose(&mut rand::thread_rng()).map(String::as_str);
+        self.race(primary, hedge_addr, request).await
+    }
+
+    async fn race(
+        &self,
+        primary_addr: &str,
+        hedge_addr: Option<&str>,
+        request: Request,
+    ) -> Result<Response, Error> {
+        let hedge_addr = match hedge_addr {
+            Some(addr) => addr,
+            None => return self.timed_call(primary_addr, request).await,
+        };
+
+        let procedure_id = request.procedure_id;
+        let primary = self.timed_call(primary_addr, request.clone());
+        tokio::pin!(primary);
+
+        tokio::select! {
+            result = &mut primary => match result {
+                Err(err) if err.is_retryable() => {
+                    // Don't wait out the delay when the first copy has already failed.
+                    self.count_sent();
+                    return self.hedge(hedge_addr, request).await;
+                }
+                result => return result,
+            },
+            _ = sleep(self.delay(procedure_id)) => {}
+        }
+
+        println!("Hedging procedure {} to {}", procedure_id, hedge_addr);
+        self.count_sent();
+        let hedge = self.hedge(hedge_addr, request);
+        tokio::pin!(hedge);
+
+        tokio::select! {
+            result = &mut primary => match result {
+                Ok(response) => Ok(response),
+                Err(_) => hedge.await,
+            },
+            result = &mut hedge => match result {
+                Ok(response) => Ok(response),
+                Err(_) => primary.await,
+            },
+        }
+    }
+
+    fn count_sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        metrics::report("hedge.sent", 1);
+    }
+
+    async fn hedge(&self, addr: &str, request: Request) -> Result<Response, Error> {
+        let result = self.timed_call(addr, request).await;
+        if result.is_ok() {
+            self.won.fetch_add(1, Ordering::SeqCst);
+            metrics::report("hedge.won", 1);
+        }
+        result
+    }
+
+    async fn timed_call(&self, addr: &str, request: Request) -> Result<Response, Error> {
+        let procedure_id = request.procedure_id;
+        let started = Instant::now();
+        let result = self.client.call(addr, request).await;
+        if result.is_ok() {
+            self.record(procedure_id, started.elapsed());
+        }
+        result
+    }
+
+    fn record(&self, procedure_id: ProcedureId, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let samples = latencies.entry(procedure_id).or_default();
+        if samples.len() == self.config.window {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+}
+
+pub async fn send_request(addrs: &[String], request: Request) -> Result<Response, Error> {
+    Hedger::global().call(addrs, request).await
+}
+
+pub async fn send_request_preferring(
+    primary: &str,
+    others: &[String],
+    request: Request,
+) -> Result<Response, Error> {
+    Hedger::global()
+        .call_preferring(primary, others, request)
+        .await
+}
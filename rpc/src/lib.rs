@@ -1,192 +1,384 @@
+pub mod admission;
+pub mod breaker;
 pub mod client;
 pub mod client_v1;
+pub mod compression;
+pub mod connection;
+pub mod deadline;
+pub mod error;
+pub mod faults;
+pub mod frame;
+pub mod hedge;
+pub mod metrics;
+pub mod middleware;
+pub mod protocol;
+pub mod reflection;
+pub mod retry;
 pub mod server;
-pub mod server_v1;
-// pub mod server_v1_5;
-pub mod server_v2;
-pub mod server_v3;
+pub mod shutdown;
+pub mod trace;
+pub mod transport;
 
 // pub use server::*;
 // pub use client::*;
+pub use client::{Client, ClientConfig};
+pub use deadline::Deadline;
+pub use error::Error;
+pub use rpc_macros::service;
+pub use shutdown::Shutdown;
+pub use trace::TraceContext;
 
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 
 pub type ProcedureId = i32;
+pub type RequestId = u64;
 pub type Payload = String;
 
+// Spans and metrics are labelled with the name of the binary that recorded
+// them, which is the service name for every service in this repo.
+pub(crate) static SERVICE_NAME: Lazy<String> = Lazy::new(|| {
+    std::env::current_exe()
+        .ok()
+        .and_then(|path| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+});
+
+// The id is assigned by the connection that sends the request, so responses
+// that come back out of order can be matched to their callers.
 #[derive(Debug, Clone)]
 pub struct Request {
+    pub id: RequestId,
     pub procedure_id: ProcedureId,
     pub payload: Payload,
+    pub deadline: Option<Deadline>,
+    pub token: Option<String>,
+    pub trace: Option<TraceContext>,
+}
+
+impl Request {
+    pub fn new(procedure_id: ProcedureId, payload: impl Into<Payload>) -> Request {
+        Request {
+            id: 0,
+            procedure_id,
+            payload: payload.into(),
+            deadline: None,
+            token: None,
+            trace: None,
+        }
+    }
+
+    // Keeps whichever deadline is earlier, so a timeout never extends the budget.
+    pub fn with_deadline(mut self, deadline: Deadline) -> Request {
+        self.deadline = deadline::earliest(self.deadline, Some(deadline));
+        self
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Request {
+        self.with_deadline(deadline::after(timeout))
+    }
+
+    // Checked by the AuthToken middleware on servers that require one.
+    pub fn with_token(mut self, token: impl Into<String>) -> Request {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.deadline.map(deadline::is_expired).unwrap_or(false)
+    }
+}
+
+// What a service declares about its procedures. Ids are only unique within a
+// service, so each service has its own, and client_v1 is given it to decide
+// which calls it may retry.
+pub struct Procedures {
+    // Running one of these twice has the same effect as running it once.
+    idempotent: &'static [ProcedureId],
+}
+
+impl Procedures {
+    pub const fn new(idempotent: &'static [ProcedureId]) -> Procedures {
+        Procedures { idempotent }
+    }
+
+    pub fn is_idempotent(&self, procedure_id: ProcedureId) -> bool {
+        self.idempotent.contains(&procedure_id)
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    UnknownProcedure,
+    DeadlineExceeded,
+    Unavailable,
+    InvalidArgument,
+    Unauthenticated,
+    Internal,
+    // The server turned the request away without running it.
+    Overloaded,
+}
+
+impl Status {
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::UnknownProcedure => 1,
+            Status::DeadlineExceeded => 2,
+            Status::Unavailable => 3,
+            Status::InvalidArgument => 4,
+            Status::Unauthenticated => 5,
+            Status::Internal => 6,
+            Status::Overloaded => 7,
+        }
+    }
+
+    // A lowercase name for metrics and configuration, e.g. `deadline_exceeded`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::UnknownProcedure => "unknown_procedure",
+            Status::DeadlineExceeded => "deadline_exceeded",
+            Status::Unavailable => "unavailable",
+            Status::InvalidArgument => "invalid_argument",
+            Status::Unauthenticated => "unauthenticated",
+            Status::Internal => "internal",
+            Status::Overloaded => "overloaded",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Status> {
+        [
+            Status::Ok,
+            Status::UnknownProcedure,
+            Status::DeadlineExceeded,
+            Status::Unavailable,
+            Status::InvalidArgument,
+            Status::Unauthenticated,
+            Status::Internal,
+            Status::Overloaded,
+        ]
+        .iter()
+        .copied()
+        .find(|status| status.name() == name)
+    }
+
+    // Codes this build doesn't know about are treated as internal errors.
+    pub fn from_code(code: i32) -> Status {
+        match code {
+            0 => Status::Ok,
+            1 => Status::UnknownProcedure,
+            2 => Status::DeadlineExceeded,
+            3 => Status::Unavailable,
+            4 => Status::InvalidArgument,
+            5 => Status::Unauthenticated,
+            7 => Status::Overloaded,
+            _ => Status::Internal,
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Status::Ok => "OK",
+            Status::UnknownProcedure => "Unknown procedure",
+            Status::DeadlineExceeded => "Deadline exceeded",
+            Status::Unavailable => "Unavailable",
+            Status::InvalidArgument => "Invalid argument",
+            Status::Unauthenticated => "Unauthenticated",
+            Status::Internal => "Internal error",
+            Status::Overloaded => "Overloaded",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// For errors the payload carries the human-readable error message.
 #[derive(Debug, Clone)]
 pub struct Response {
+    pub id: RequestId,
+    pub status: Status,
     pub payload: Payload,
+    // How long the caller should wait before sending the request again, set
+    // on Overloaded responses.
+    pub retry_after: Option<Duration>,
 }
 
-// v3
-
 impl Response {
-    fn default() -> Response {
-        Response { payload: "OK".to_string() }
+    pub fn ok(payload: impl Into<Payload>) -> Response {
+        Response {
+            id: 0,
+            status: Status::Ok,
+            payload: payload.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn error(status: Status, message: impl Into<String>) -> Response {
+        Response {
+            id: 0,
+            status,
+            payload: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn overloaded(message: impl Into<String>, retry_after: Duration) -> Response {
+        Response {
+            retry_after: Some(retry_after),
+            ..Response::error(Status::Overloaded, message)
+        }
+    }
+
+    pub fn unknown_procedure(procedure_id: ProcedureId) -> Response {
+        Response::error(
+            Status::UnknownProcedure,
+            format!("procedure {} is not registered", procedure_id),
+        )
     }
-}
 
-// trait Middleware {
-//     fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response;
-// }
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+
+    // Turns an error status into a typed client error.
+    pub fn into_result(self) -> Result<Response, Error> {
+        match (self.status, self.retry_after) {
+            (Status::Ok, _) => Ok(self),
+            (Status::Overloaded, Some(retry_after)) => {
+                Err(Error::Overloaded(self.payload, retry_after))
+            }
+            (status, _) => Err(Error::Status(status, self.payload)),
+        }
+    }
+}
 
-// type NextFn = Box<dyn Fn(Request) -> Pin<Box<dyn Future<Output = Response> + Send + 'static>>
-//     + Send + Sync>;
+// v3
 
-// pub trait Handler: Send + Sync {
-//     fn handle<'a>(
-//         &'a self,
-//         request: &Request,
-//         next: &'a NextFn,
-//         // next: &'a dyn Fn(Request) -> Pin<Box<dyn Future<Output = Response> + Send + 'static>>
-//     ) -> Pin<Box<dyn Future<Output = Response> + Send + 'static>>;
-// }
+impl Default for Response {
+    fn default() -> Response {
+        Response::ok("OK")
+    }
+}
 
+// A handler sees each request on its way in and may answer it itself or pass
+// it on with `node.call_next`. Handlers are shared by every in-flight
+// request, so any state they keep needs its own fine-grained locking.
 pub trait Handler: Send + Sync {
-    // fn handle(
-    //     &self,
-    //     request: Request,
-    //     node: &HandlerNode,
-    // ) -> Pin<Box<dyn Future<Output = Response> + Send>>;
     fn handle<'a>(
         &'a self,
         request: Request,
         node: &'a HandlerNode,
     ) -> Pin<Box<dyn Future<Output = Response> + Send + 'a>>;
+
+    // Names for the procedures of the service this handler describes, which
+    // the other handlers in the same chain can look up by id.
+    fn procedure_names(&self) -> Vec<(ProcedureId, String)> {
+        Vec::new()
+    }
 }
 
 pub struct HandlerNode {
-    handler: Arc<Mutex<dyn Handler>>,
+    handler: Arc<dyn Handler>,
     next: Option<Arc<HandlerNode>>,
+    names: Arc<HashMap<ProcedureId, String>>,
 }
 
 impl HandlerNode {
-    pub fn new(handler: Arc<Mutex<dyn Handler>>) -> Self {
+    pub fn new(handler: Arc<dyn Handler>) -> Self {
         HandlerNode {
             handler,
             next: None,
+            names: Arc::default(),
         }
     }
 
+    // Ids are only unique within a service, so names come from the handlers
+    // of this server rather than from everything the process has seen.
+    pub fn procedure_name(&self, procedure_id: ProcedureId) -> Option<&str> {
+        self.names.get(&procedure_id).map(String::as_str)
+    }
+
     pub async fn call_next(&self, request: Request) -> Response {
         match &self.next {
             Some(next_node) => next_node.handle(request).await,
-            None => Response::default(), // or some other default handling
+            None => Response::error(
+                Status::Internal,
+                format!("no handler answered procedure {}", request.procedure_id),
+            ),
         }
     }
 
-    // async fn handle(&self, request: Request) -> Response {
-    //     match &self.next {
-    //         Some(next_node) => {
-    //             self.handler.handle(&request, &|req| Box::pin(async move {
-    //                 next_node.handle(req).await
-    //             })).await
-    //         }
-    //         None => {
-    //             Response::default()
-    //         }
-    //     }
-    // }
-    // async fn handle(&self, request: Request) -> Response {
-    //     self.handler.handle(&request, &HandlerNode::call_next).await
-    // }
-
     async fn handle(&self, request: Request) -> Response {
-        self.handler.lock().await.handle(request, self).await
-    }
-
-    // async fn handle(&self, request: Request) -> Response {
-    //     match &self.next {
-    //         Some(next_node) => {
-    //             let next_closure: Box<dyn Fn(Request) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync> = Box::new(move |req| {
-    //                 let next_node_clone = next_node.clone();
-    //                 Box::pin(async move { next_node_clone.handle(req).await })
-    //             });
-
-    //             self.handler.handle(&request, &next_closure).await
-    //         }
-    //         None => {
-    //             Response::default()
-    //         }
-    //     }
-    // }
+        self.handler.handle(request, self).await
+    }
 }
 
-#[derive(Clone)]
+// Handlers run in the order they were added, so the first one added sees
+// every request first and every response last.
+#[derive(Clone, Default)]
 pub struct Handlers {
+    handlers: Vec<Arc<dyn Handler>>,
     handler_nodes: Option<Arc<HandlerNode>>,
-    // handlers: Vec<Arc<dyn Handler>>,
 }
 
 impl Handlers {
-    // fn handle_request(&self, request: &Request) -> Response {
-    //     let mut response = Response::default();
-    //     for middleware in &self.middlewares {
-    //         response = middleware.handle(request, &|req| response.clone());
-    //     }
-    //     response
-    // }
     pub fn new() -> Self {
-        Handlers {
-            handler_nodes: None,
-        }
+        Handlers::default()
     }
 
-    // fn handle_request(
-    //     &self,
-    //     request: Request
-    // ) -> Pin<Box<dyn Future<Output = Response> + Send + 'static>> {
-    //     let handlers = self.handlers.clone();
-    //     Box::pin(async move {
-    //         let mut response = Response::default();
-    //         // let next: NextFn = Box::new(|request| Box::pin(async move { Response::default() }));
-    //         // let next = |req| Box::pin(async { Response::default() }) as Pin<Box<_>>;
-
-    //         handlers.handle
-    //         for handler in handlers {
-    //             // response = handler.handle(request, &next).await;
-    //             response = handler.handle(&request).await;
-    //         }
-    //         response
-    //     })
-    // }
+    // Logging, tracing, metrics, admission control, fault injection, panic
+    // catching and payload size limits, in that order. Metrics already
+    // records latency, so Timing is only for services that want it locally.
+    // Services add authentication and their endpoint after it.
+    pub fn standard() -> Self {
+        Handlers::new()
+            .with(middleware::Logging)
+            .with(middleware::Tracing)
+            .with(metrics::Metrics::global().clone())
+            .with(admission::AdmissionControl::new())
+            .with(faults::FaultInjection::global().clone())
+            .with(middleware::CatchPanic)
+            .with(middleware::PayloadLimit::new(*middleware::MAX_PAYLOAD_SIZE))
+    }
 
-    pub async fn handle_request(&self, request: Request) -> Response {
-        match &self.handler_nodes {
-            Some(node) => {
-                node.handler.lock().await.handle(request, node).await
-            }
-            None => {
-                Response::default()
-            }
-        }
+    pub fn with(mut self, handler: impl Handler + 'static) -> Self {
+        self.add_handler(Arc::new(handler));
+        self
     }
 
-    // pub fn add_handler(&mut self, handler: Arc<dyn Handler>) {
-    //     self.handlers.push(handler);
-    // }
+    pub fn add_handler(&mut self, handler: Arc<dyn Handler>) {
+        self.handlers.push(handler);
 
-    pub fn add_handler(&mut self, handler: Arc<Mutex<dyn Handler>>) {
-        let mut node = HandlerNode::new(handler);
+        let names: Arc<HashMap<ProcedureId, String>> = Arc::new(
+            self.handlers
+                .iter()
+                .flat_map(|handler| handler.procedure_names())
+                .collect(),
+        );
+        let mut next = None;
+        for handler in self.handlers.iter().rev() {
+            let mut node = HandlerNode::new(handler.clone());
+            node.next = next;
+            node.names = names.clone();
+            next = Some(Arc::new(node));
+        }
+        self.handler_nodes = next;
+    }
 
-        match self.handler_nodes.take() {
-            Some(existing) => {
-                node.next = Some(existing);
-                self.handler_nodes = Some(Arc::new(node));
-            }
-            None => {
-                self.handler_nodes = Some(Arc::new(node));
-            }
+    pub async fn handle_request(&self, request: Request) -> Response {
+        match &self.handler_nodes {
+            Some(node) => node.handle(request).await,
+            None => Response::default(),
         }
     }
 }
@@ -0,0 +1,291 @@
+use crate::connection::IO_RUNTIME;
+use crate::{
+    trace, Client, Handler, HandlerNode, ProcedureId, Request, Response, Status, SERVICE_NAME,
+};
+use normalization::{Deserializable, Serializable};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+
+// Where batches are sent. Monitoring answers REPORT_BATCH_PROCEDURE
+// alongside its own procedures.
+pub static METRICS_ADDR: Lazy<String> =
+    Lazy::new(|| std::env::var("METRICS_ADDR").unwrap_or_else(|_| "127.0.0.1:10800".to_string()));
+
+static METRICS_FLUSH_MS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("METRICS_FLUSH_MS")
+        .map(|val| val.parse().expect("METRICS_FLUSH_MS must be a number"))
+        .unwrap_or(10_000)
+});
+
+pub const REPORT_BATCH_PROCEDURE: ProcedureId = 8;
+
+// `metrics` holds `name=value` entries joined by ';'.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct ReportBatchArgs {
+    pub service: String,
+    pub metrics: String,
+}
+
+// Upper bounds of the latency histogram buckets, in milliseconds. Slower
+// requests land in a final `inf` bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 12] =
+    [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+// What one procedure did since the last flush, apart from `in_flight`,
+// which is how many requests are running right now.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcedureMetrics {
+    pub requests: u64,
+    pub errors: BTreeMap<&'static str, u64>,
+    pub in_flight: u64,
+    // One count per LATENCY_BUCKETS_MS entry, plus the `inf` bucket.
+    pub latency: [u64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+impl ProcedureMetrics {
+    fn observe(&mut self, status: Status, elapsed: Duration) {
+        self.requests += 1;
+        if status != Status::Ok {
+            *self.errors.entry(status.name()).or_default() += 1;
+        }
+        let millis = elapsed.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.latency[bucket] += 1;
+    }
+
+    // The upper bound of the bucket holding the given quantile, so a
+    // percentile is only ever overstated. None if nothing was observed or it
+    // fell in the `inf` bucket.
+    pub fn percentile_ms(&self, quantile: f64) -> Option<u64> {
+        let target = (self.requests as f64 * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.latency.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return LATENCY_BUCKETS_MS.get(bucket).copied();
+            }
+        }
+        None
+    }
+
+    // `requests`, `errors.<status>`, `in_flight`, `latency_p50_ms`,
+    // `latency_p99_ms` and one `latency_le_<ms>` per bucket, each prefixed
+    // with the procedure name.
+    fn entries(&self, procedure: &str, out: &mut Vec<(String, i32)>) {
+        let mut push = |metric: String, value: u64| {
+            out.push((
+                format!("{}.{}", procedure, metric),
+                value.min(i32::MAX as u64) as i32,
+            ));
+        };
+        push("requests".to_string(), self.requests);
+        for (status, count) in &self.errors {
+            push(format!("errors.{}", status), *count);
+        }
+        push("in_flight".to_string(), self.in_flight);
+        if let Some(p50) = self.percentile_ms(0.5) {
+            push("latency_p50_ms".to_string(), p50);
+        }
+        if let Some(p99) = self.percentile_ms(0.99) {
+            push("latency_p99_ms".to_string(), p99);
+        }
+        for (bucket, count) in self.latency.iter().enumerate() {
+            let bound = match LATENCY_BUCKETS_MS.get(bucket) {
+                Some(bound) => bound.to_string(),
+                None => "inf".to_string(),
+            };
+            push(format!("latency_le_{}", bound), *count);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    procedures: HashMap<ProcedureId, ProcedureMetrics>,
+    // Counters reported by hand with `report`, summed until the next flush.
+    counters: BTreeMap<String, i64>,
+    // Payload bytes before and after compression.
+    compression: (u64, u64),
+}
+
+// Records request counts, errors by status, in-flight requests and a latency
+// histogram per procedure. Clones share the same numbers; the one in
+// Handlers::standard() is `Metrics::global()`, which is flushed to
+// monitoring every METRICS_FLUSH_MS.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+static GLOBAL: Lazy<Metrics> = Lazy::new(Metrics::new);
+static START_FLUSHING: Once = Once::new();
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn global() -> &'static Metrics {
+        START_FLUSHING.call_once(|| {
+            IO_RUNTIME.spawn(async {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(*METRICS_FLUSH_MS)).await;
+                    flush().await;
+                }
+            });
+        });
+        &GLOBAL
+    }
+
+    pub fn snapshot(&self) -> HashMap<ProcedureId, ProcedureMetrics> {
+        self.registry.lock().unwrap().procedures.clone()
+    }
+
+    pub fn report(&self, metric: &str, value: i32) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.counters.entry(metric.to_string()).or_default() += value as i64;
+    }
+
+    pub fn record_compression(&self, original: usize, compressed: usize) {
+        let mut registry = self.registry.lock().unwrap();
+        registry.compression.0 += original as u64;
+        registry.compression.1 += compressed as u64;
+    }
+
+    // Everything recorded since the last call as `name=value` pairs, and
+    // starts a new interval. Procedures that were idle the whole interval
+    // are left out.
+    pub fn take_batch(&self) -> Vec<(String, i32)> {
+        let mut registry = self.registry.lock().unwrap();
+        let mut batch = Vec::new();
+        let mut procedures: Vec<_> = registry.procedures.iter_mut().collect();
+        procedures.sort_by_key(|(procedure_id, _)| **procedure_id);
+        for (procedure_id, metrics) in procedures {
+            if metrics.requests == 0 && metrics.in_flight == 0 {
+                continue;
+            }
+            let name = trace::procedure_name(*procedure_id).replace(' ', "_");
+            metrics.entries(&name, &mut batch);
+            *metrics = ProcedureMetrics {
+                in_flight: metrics.in_flight,
+                ..ProcedureMetrics::default()
+            };
+        }
+        // `compression.ratio_pct` is the compressed size as a percentage of
+        // the original, so lower is better.
+        let (original, compressed) = std::mem::take(&mut registry.compression);
+        if original > 0 {
+            let clamp = |bytes: u64| bytes.min(i32::MAX as u64) as i32;
+            batch.push(("compression.original_bytes".to_string(), clamp(original)));
+            batch.push((
+                "compression.compressed_bytes".to_string(),
+                clamp(compressed),
+            ));
+            batch.push((
+                "compression.ratio_pct".to_string(),
+                clamp(compressed * 100 / original),
+            ));
+        }
+        for (metric, value) in std::mem::take(&mut registry.counters) {
+            let value = value.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+            batch.push((metric, value));
+        }
+        batch
+    }
+
+    fn start(&self, procedure_id: ProcedureId) -> InFlight<'_> {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .procedures
+            .entry(procedure_id)
+            .or_default()
+            .in_flight += 1;
+        InFlight {
+            metrics: self,
+            procedure_id,
+            started: Instant::now(),
+            status: None,
+        }
+    }
+}
+
+// Counts a request as in flight until it is dropped. A handler dropped before
+// it answered was cut off at its deadline, so it's recorded as exceeding it.
+struct InFlight<'a> {
+    metrics: &'a Metrics,
+    procedure_id: ProcedureId,
+    started: Instant,
+    status: Option<Status>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let status = self.status.unwrap_or(Status::DeadlineExceeded);
+        let mut registry = self.metrics.registry.lock().unwrap();
+        let metrics = registry.procedures.entry(self.procedure_id).or_default();
+        metrics.in_flight = metrics.in_flight.saturating_sub(1);
+        metrics.observe(status, self.started.elapsed());
+    }
+}
+
+impl Handler for Metrics {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        node: &'a HandlerNode,
+    ) -> Pin<Box<dyn Future<Output = Response> + Send + 'a>> {
+        Box::pin(async move {
+            let mut in_flight = self.start(request.procedure_id);
+            let response = node.call_next(request).await;
+            in_flight.status = Some(response.status);
+            response
+        })
+    }
+}
+
+// Adds to a counter that is sent with the next batch, for metrics that
+// aren't about a procedure, like requests a load balancer turned away.
+pub fn report(metric: &str, value: i32) {
+    Metrics::global().report(metric, value);
+}
+
+pub fn encode_batch(batch: &[(String, i32)]) -> String {
+    let entries: Vec<String> = batch
+        .iter()
+        .map(|(metric, value)| format!("{}={}", metric.replace(['=', ';'], "_"), value))
+        .collect();
+    entries.join(";")
+}
+
+// Entries that don't parse are skipped rather than failing the batch.
+pub fn decode_batch(s: &str) -> Vec<(String, i32)> {
+    s.split(';')
+        .filter_map(|entry| entry.split_once('='))
+        .filter_map(|(metric, value)| Some((metric.trim().to_string(), value.trim().parse().ok()?)))
+        .collect()
+}
+
+// Sends what the global metrics recorded since the last flush to monitoring.
+// A batch that can't be delivered is dropped rather than piling up.
+pub async fn flush() {
+    let batch = GLOBAL.take_batch();
+    if batch.is_empty() {
+        return;
+    }
+    let args = ReportBatchArgs {
+        service: SERVICE_NAME.clone(),
+        metrics: encode_batch(&batch),
+    };
+    let request = Request::new(REPORT_BATCH_PROCEDURE, args.serialize());
+    let result = trace::scope(None, Client::global().call(&METRICS_ADDR, request)).await;
+    if let Err(e) = result.and_then(|response| response.into_result()) {
+        println!("Dropped {} metrics: {}", batch.len(), e);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::trace::{self, ActiveSpan};
+use crate::{Handler, HandlerNode, ProcedureId, Request, Response, Status};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+pub static MAX_PAYLOAD_SIZE: Lazy<usize> = Lazy::new(|| {
+    std::env::var("MAX_PAYLOAD_SIZE")
+        .map(|val| val.parse().expect("MAX_PAYLOAD_SIZE must be a number"))
+        .unwrap_or(4 * 1024 * 1024)
+});
+
+type BoxFuture<'a> = Pin<Box<dyn Future<Output = Response> + Send + 'a>>;
+
+// Ends a chain by handing the request to a service's request handler.
+pub struct Endpoint<F> {
+    handler: F,
+}
+
+impl<F> Endpoint<F>
+where
+    F: Fn(Request) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync,
+{
+    pub fn new(handler: F) -> Self {
+        Endpoint { handler }
+    }
+}
+
+impl<F> Handler for Endpoint<F>
+where
+    F: Fn(Request) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync,
+{
+    fn handle<'a>(&'a self, request: Request, _node: &'a HandlerNode) -> BoxFuture<'a> {
+        (self.handler)(request)
+    }
+}
+
+pub struct Logging;
+
+impl Handler for Logging {
+    fn handle<'a>(&'a self, request: Request, node: &'a HandlerNode) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let procedure_id = request.procedure_id;
+            let id = request.id;
+            println!("Handling request {} for procedure {}", id, procedure_id);
+            let response = node.call_next(request).await;
+            if response.is_ok() {
+                println!("Request {} for procedure {} succeeded", id, procedure_id);
+            } else {
+                println!(
+                    "Request {} for procedure {} failed: {}: {}",
+                    id, procedure_id, response.status, response.payload
+                );
+            }
+            response
+        })
+    }
+}
+
+// Records a span for every request that is part of a trace, and runs the
+// rest of the chain inside it so the handler's own calls become its children.
+// Requests without a trace context are passed through untouched.
+pub struct Tracing;
+
+impl Handler for Tracing {
+    fn handle<'a>(&'a self, request: Request, node: &'a HandlerNode) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let parent = match request.trace {
+                Some(parent) => parent,
+                None => return node.call_next(request).await,
+            };
+            let span = ActiveSpan::child(parent, trace::procedure_name(request.procedure_id));
+            let response = trace::scope(Some(span.context()), node.call_next(request)).await;
+            span.finish(response.status.to_string());
+            response
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatencyStats {
+    pub count: u64,
+    pub errors: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.count as u32
+    }
+}
+
+// Records how long each procedure takes. Clones share the same numbers, so a
+// service can keep one to report from while another sits in the chain.
+#[derive(Clone, Default)]
+pub struct Timing {
+    stats: Arc<Mutex<HashMap<ProcedureId, LatencyStats>>>,
+}
+
+impl Timing {
+    pub fn new() -> Self {
+        Timing::default()
+    }
+
+    pub fn stats(&self) -> HashMap<ProcedureId, LatencyStats> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+impl Handler for Timing {
+    fn handle<'a>(&'a self, request: Request, node: &'a HandlerNode) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let procedure_id = request.procedure_id;
+            let started = Instant::now();
+            let response = node.call_next(request).await;
+            let elapsed = started.elapsed();
+
+            let mut stats = self.stats.lock().unwrap();
+            let entry = stats.entry(procedure_id).or_default();
+            entry.count += 1;
+            if !response.is_ok() {
+                entry.errors += 1;
+            }
+            entry.total += elapsed;
+            entry.max = entry.max.max(elapsed);
+            response
+        })
+    }
+}
+
+// Turns a panicking handler into an Internal error instead of leaving the
+// caller waiting for a response that will never be sent.
+pub struct CatchPanic;
+
+impl Handler for CatchPanic {
+    fn handle<'a>(&'a self, request: Request, node: &'a HandlerNode) -> BoxFuture<'a> {
+        Box::pin(async move {
+            let procedure_id = request.procedure_id;
+            match CatchUnwind(Box::pin(node.call_next(request))).await {
+                Ok(response) => response,
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    eprintln!(
+                        "Handler for procedure {} panicked: {}",
+                        procedure_id, message
+                    );
+                    Response::error(Status::Internal, format!("handler panicked: {}", message))
+                }
+            }
+        })
+    }
+}
+
+struct CatchUnwind<F>(F);
+
+impl<F: Future + Unpin> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.0;
+        match panic::catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+type Validator = Box<dyn Fn(String) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
+// Rejects requests that don't carry a token the validator accepts. Some
+// procedures, like health checks, can be left open with `allow`.
+pub struct AuthToken {
+    validate: Validator,
+    public: HashSet<ProcedureId>,
+}
+
+impl AuthToken {
+    pub fn new<F, Fut>(validate: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        AuthToken {
+            validate: Box::new(move |token| Box::pin(validate(token))),
+            public: HashSet::new(),
+        }
+    }
+
+    pub fn with_tokens(tokens: Vec<String>) -> Self {
+        let tokens: Arc<HashSet<String>> = Arc::new(tokens.into_iter().collect());
+        AuthToken::new(move |token| {
+            let valid = tokens.contains(&token);
+            async move { valid }
+        })
+    }
+
+    pub fn allow(mut self, procedure_id: ProcedureId) -> Self {
+        self.public.insert(procedure_id);
+        self
+    }
+}
+
+impl Handler for AuthToken {
+    fn handle<'a>(&'a self, request: Request, node: &'a HandlerNode) -> BoxFuture<'a> {
+        Box::pin(async move {
+            if self.public.contains(&request.procedure_id) {
+                return node.call_next(request).await;
+            }
+            match request.token.clone() {
+                None => Response::error(Status::Unauthenticated, "missing auth token"),
+                Some(token) => {
+                    if (self.validate)(token).await {
+                        node.call_next(request).await
+                    } else {
+                        Response::error(Status::Unauthenticated, "invalid auth token")
+                    }
+                }
+            }
+        })
+    }
+}
+
+pub struct PayloadLimit {
+    max_size: usize,
+}
+
+impl PayloadLimit {
+    pub fn new(max_size: usize) -> Self {
+        PayloadLimit { max_size }
+    }
+}
+
+impl Handler for PayloadLimit {
+    fn handle<'a>(&'a self, request: Request, node: &'a HandlerNode) -> BoxFuture<'a> {
+        Box::pin(async move {
+            if request.payload.len() > self.max_size {
+                return Response::error(
+                    Status::InvalidArgument,
+                    format!(
+                        "payload of {} bytes exceeds limit of {}",
+                        request.payload.len(),
+                        self.max_size
+                    ),
+                );
+            }
+            node.call_next(request).await
+        })
+    }
+}
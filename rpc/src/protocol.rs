@@ -0,0 +1,182 @@
+use crate::transport::BoxStream;
+use crate::{frame, ProcedureId, Request, Response, Status};
+use std::collections::BTreeSet;
+use std::fmt;
+use tokio::io;
+use tokio::time::{timeout, Duration};
+
+// Clients open every connection with a request for this procedure, carrying
+// the protocol version and capabilities they speak; the server answers with
+// what both sides share. A server from before the handshake answers it like
+// any procedure it doesn't know, which tells the client to speak
+// LEGACY_VERSION. Clients from before the handshake never send it, and the
+// server assumes the same of them. A server from before framing can't read
+// the handshake at all and drops the connection or answers with something
+// that isn't a frame; the client then speaks UNFRAMED_VERSION.
+pub const HANDSHAKE_PROCEDURE: ProcedureId = -1;
+
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const LEGACY_VERSION: u32 = 1;
+pub const UNFRAMED_VERSION: u32 = 0;
+
+// Length-prefixed frames with a `key=value` header line, and request ids so
+// calls can be pipelined.
+pub const FRAMING: &str = "framing";
+pub const DEADLINES: &str = "deadlines";
+pub const TRACING: &str = "tracing";
+pub const AUTH: &str = "auth";
+// Overloaded responses carry a retry-after hint.
+pub const OVERLOAD: &str = "overload";
+// Large payloads may be sent compressed, see compression.rs.
+pub const COMPRESSION: &str = "compression";
+
+pub const CAPABILITIES: &[&str] = &[FRAMING, DEADLINES, TRACING, AUTH, OVERLOAD, COMPRESSION];
+
+// How long a client waits for the answer to its handshake before giving up
+// on the connection.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u32,
+    pub capabilities: BTreeSet<String>,
+}
+
+impl Protocol {
+    pub fn new(version: u32, capabilities: &[&str]) -> Protocol {
+        Protocol {
+            version,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    // What this build speaks.
+    pub fn current() -> Protocol {
+        Protocol::new(PROTOCOL_VERSION, CAPABILITIES)
+    }
+
+    // What a peer that doesn't take part in the handshake is assumed to speak.
+    pub fn legacy() -> Protocol {
+        Protocol::new(LEGACY_VERSION, &[FRAMING])
+    }
+
+    // `procedure_id:payload` lines answered with the bare payload, one call
+    // at a time.
+    pub fn unframed() -> Protocol {
+        Protocol::new(UNFRAMED_VERSION, &[])
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+
+    // The older of the two versions, and the capabilities both sides have.
+    pub fn negotiate(&self, peer: &Protocol) -> Protocol {
+        Protocol {
+            version: self.version.min(peer.version),
+            capabilities: self
+                .capabilities
+                .intersection(&peer.capabilities)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    // `version=2;capabilities=framing,tracing`
+    pub fn encode(&self) -> String {
+        let capabilities: Vec<&str> = self.capabilities.iter().map(String::as_str).collect();
+        format!(
+            "version={};capabilities={}",
+            self.version,
+            capabilities.join(",")
+        )
+    }
+
+    // Unknown fields are ignored, so later versions can add some.
+    pub fn decode(s: &str) -> Option<Protocol> {
+        let mut version = None;
+        let mut capabilities = BTreeSet::new();
+        for (key, value) in s.split(';').filter_map(|field| field.split_once('=')) {
+            match key.trim() {
+                "version" => version = value.trim().parse().ok(),
+                "capabilities" => {
+                    capabilities = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|c| !c.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        Some(Protocol {
+            version: version?,
+            capabilities,
+        })
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capabilities: Vec<&str> = self.capabilities.iter().map(String::as_str).collect();
+        write!(f, "v{} ({})", self.version, capabilities.join(", "))
+    }
+}
+
+// The server's side: what to answer a handshake with, and what the
+// connection speaks from then on.
+pub(crate) fn answer(request: &Request) -> (Response, Protocol) {
+    match Protocol::decode(&request.payload) {
+        Some(peer) => {
+            let negotiated = Protocol::current().negotiate(&peer);
+            (Response::ok(negotiated.encode()), negotiated)
+        }
+        None => (
+            Response::error(
+                Status::InvalidArgument,
+                format!("bad handshake: {}", request.payload),
+            ),
+            Protocol::legacy(),
+        ),
+    }
+}
+
+// The client's side, run on a fresh stream before anything else is sent.
+// When it comes back unframed the stream is no use any more, the server
+// having given up on it, and the caller has to connect again.
+pub(crate) async fn handshake(stream: &mut BoxStream, addr: &str) -> io::Result<Protocol> {
+    let hello = Request::new(HANDSHAKE_PROCEDURE, Protocol::current().encode());
+    let exchange = async {
+        frame::write_request(stream, &hello).await?;
+        frame::read_response(stream).await
+    };
+    let response = timeout(HANDSHAKE_TIMEOUT, exchange).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("No answer to the handshake from {}", addr),
+        )
+    })?;
+    let response = match response {
+        Ok(response) => response,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+            ) =>
+        {
+            return Ok(Protocol::unframed())
+        }
+        Err(e) => return Err(e),
+    };
+
+    match response.status {
+        Status::Ok => Protocol::decode(&response.payload).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Bad handshake answer from {}: {}", addr, response.payload),
+            )
+        }),
+        _ => Ok(Protocol::legacy()),
+    }
+}
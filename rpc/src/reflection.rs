@@ -0,0 +1,334 @@
+use crate::{trace, Client, Error, Handler, HandlerNode, ProcedureId, Request, Response, Status};
+use normalization::{Deserializable, Serializable};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+// Reserved on every server; services number their own procedures from 1.
+pub const REFLECTION_PROCEDURE: ProcedureId = 0;
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct ReflectionArgs {
+    pub placeholder: i32,
+}
+
+// `procedures` holds one ProcedureDescription per entry, joined by ';'.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct ReflectionResult {
+    pub service: String,
+    pub version: String,
+    pub procedures: String,
+}
+
+// The fields of an arguments or result struct. Procedures that take or
+// return plain text, like the usual "OK", have an empty schema shown as `()`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Schema {
+    pub name: String,
+    pub fields: Vec<(String, String)>,
+}
+
+// Builds a Schema from a type deriving Serializable: `schema!(GetArgs)`.
+#[macro_export]
+macro_rules! schema {
+    ($ty:ty) => {
+        $crate::reflection::Schema::new(stringify!($ty), <$ty>::schema())
+    };
+}
+
+impl Schema {
+    pub fn new(name: &str, fields: Vec<(&str, &str)>) -> Schema {
+        Schema {
+            name: name.to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(field, ty)| (field.to_string(), ty.to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn none() -> Schema {
+        Schema::default()
+    }
+
+    // Builds a payload from `field=value` strings, quoting and escaping each
+    // value the way Serializable would for the field's type.
+    pub fn encode(&self, values: &HashMap<String, String>) -> Result<String, Error> {
+        if self.name.is_empty() {
+            return Ok(String::new());
+        }
+        if let Some(unknown) = values
+            .keys()
+            .find(|key| !self.fields.iter().any(|(field, _)| field == *key))
+        {
+            return Err(invalid(format!("{} has no field {}", self.name, unknown)));
+        }
+
+        let mut parts = Vec::new();
+        for (field, ty) in &self.fields {
+            let value = match values.get(field) {
+                Some(value) => value,
+                // Placeholder-style fields don't need to be spelled out.
+                None if is_number(ty) => "0",
+                None => return Err(invalid(format!("missing {}: {}", field, ty))),
+            };
+            parts.push(format!("{}: {}", field, encode_value(ty, value)?));
+        }
+        Ok(format!("{{{}}}", parts.join(",")))
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(field, ty)| format!("{}: {}", field, ty))
+            .collect();
+        write!(f, "{}({})", self.name, fields.join(", "))
+    }
+}
+
+impl std::str::FromStr for Schema {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Schema, Error> {
+        let (name, fields) = s
+            .trim()
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| invalid(format!("malformed schema: {}", s)))?;
+        let fields = split_top_level(fields)
+            .into_iter()
+            .map(|field| match field.split_once(':') {
+                Some((field, ty)) => Ok((field.trim().to_string(), ty.trim().to_string())),
+                None => Err(invalid(format!("malformed field: {}", field))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Schema {
+            name: name.trim().to_string(),
+            fields,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcedureDescription {
+    pub id: ProcedureId,
+    pub name: String,
+    pub args: Schema,
+    pub result: Schema,
+}
+
+// e.g. `1 get GetArgs(key: String) -> GetResult(value: String, found: i32)`
+impl fmt::Display for ProcedureDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} -> {}",
+            self.id, self.name, self.args, self.result
+        )
+    }
+}
+
+impl std::str::FromStr for ProcedureDescription {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ProcedureDescription, Error> {
+        let malformed = || invalid(format!("malformed procedure: {}", s));
+        let (id, rest) = s.trim().split_once(' ').ok_or_else(malformed)?;
+        let (name, rest) = rest.split_once(' ').ok_or_else(malformed)?;
+        let (args, result) = rest.split_once(" -> ").ok_or_else(malformed)?;
+        Ok(ProcedureDescription {
+            id: id.parse().map_err(|_| malformed())?,
+            name: name.to_string(),
+            args: args.parse()?,
+            result: result.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ServiceDescription {
+    pub name: String,
+    pub version: String,
+    pub procedures: Vec<ProcedureDescription>,
+}
+
+impl ServiceDescription {
+    pub fn new(name: &str, version: &str) -> ServiceDescription {
+        ServiceDescription {
+            name: name.to_string(),
+            version: version.to_string(),
+            procedures: Vec::new(),
+        }
+    }
+
+    pub fn procedure(mut self, id: ProcedureId, name: &str, args: Schema, result: Schema) -> Self {
+        self.procedures.push(ProcedureDescription {
+            id,
+            name: name.to_string(),
+            args,
+            result,
+        });
+        self
+    }
+
+    // Looks a procedure up by name, or by id if given a number.
+    pub fn find(&self, procedure: &str) -> Option<&ProcedureDescription> {
+        match procedure.parse::<ProcedureId>() {
+            Ok(id) => self.procedures.iter().find(|p| p.id == id),
+            Err(_) => self.procedures.iter().find(|p| p.name == procedure),
+        }
+    }
+
+    pub fn to_result(&self) -> ReflectionResult {
+        let procedures: Vec<String> = self.procedures.iter().map(|p| p.to_string()).collect();
+        ReflectionResult {
+            service: self.name.clone(),
+            version: self.version.clone(),
+            procedures: procedures.join(";"),
+        }
+    }
+
+    pub fn from_result(result: ReflectionResult) -> Result<ServiceDescription, Error> {
+        let procedures = result
+            .procedures
+            .split(';')
+            .filter(|p| !p.trim().is_empty())
+            .map(|p| p.parse())
+            .collect::<Result<_, _>>()?;
+        Ok(ServiceDescription {
+            name: result.service,
+            version: result.version,
+            procedures,
+        })
+    }
+
+    pub fn respond(&self) -> Response {
+        Response::ok(self.to_result().serialize())
+    }
+}
+
+// Answers REFLECTION_PROCEDURE for services that don't use #[rpc::service],
+// whose generated dispatch answers it already.
+pub struct Reflection {
+    description: ServiceDescription,
+}
+
+impl Reflection {
+    pub fn new(description: ServiceDescription) -> Self {
+        trace::name_procedures(&description);
+        Reflection { description }
+    }
+}
+
+impl Handler for Reflection {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        node: &'a HandlerNode,
+    ) -> Pin<Box<dyn Future<Output = Response> + Send + 'a>> {
+        Box::pin(async move {
+            if request.procedure_id == REFLECTION_PROCEDURE {
+                return self.description.respond();
+            }
+            node.call_next(request).await
+        })
+    }
+
+    fn procedure_names(&self) -> Vec<(ProcedureId, String)> {
+        self.description
+            .procedures
+            .iter()
+            .map(|procedure| (procedure.id, procedure.name.clone()))
+            .collect()
+    }
+}
+
+pub async fn describe(addr: &str) -> Result<ServiceDescription, Error> {
+    let args = ReflectionArgs { placeholder: 0 };
+    let request = Request::new(REFLECTION_PROCEDURE, args.serialize());
+    let response = Client::global().call(addr, request).await?;
+    let result = ReflectionResult::deserialize(&response.payload)
+        .map_err(|e| invalid(format!("Failed to deserialize ReflectionResult: {}", e)))?;
+    ServiceDescription::from_result(result)
+}
+
+fn invalid(message: String) -> Error {
+    Error::Status(Status::InvalidArgument, message)
+}
+
+fn is_number(ty: &str) -> bool {
+    matches!(ty, "i32" | "i64" | "u32" | "u64" | "usize" | "f64")
+}
+
+fn encode_value(ty: &str, value: &str) -> Result<String, Error> {
+    let check = |ok: bool| {
+        if ok {
+            Ok(value.to_string())
+        } else {
+            Err(invalid(format!("{} is not a valid {}", value, ty)))
+        }
+    };
+    match ty {
+        "i32" => check(value.parse::<i32>().is_ok()),
+        "i64" => check(value.parse::<i64>().is_ok()),
+        "u32" => check(value.parse::<u32>().is_ok()),
+        "u64" => check(value.parse::<u64>().is_ok()),
+        "usize" => check(value.parse::<usize>().is_ok()),
+        "f64" => check(value.parse::<f64>().is_ok()),
+        "bool" => check(value.parse::<bool>().is_ok()),
+        "String" => Ok(format!("\"{}\"", escape(value))),
+        _ if ty.starts_with("Option<") && ty.ends_with('>') => match value {
+            "null" => Ok(value.to_string()),
+            _ => encode_value(&ty["Option<".len()..ty.len() - 1], value),
+        },
+        _ => match ty.strip_prefix("Vec<").and_then(|ty| ty.strip_suffix('>')) {
+            Some(inner) => {
+                let items = value
+                    .split(',')
+                    .filter(|item| !item.is_empty())
+                    .map(|item| encode_value(inner, item))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("[{}]", items.join(",")))
+            }
+            None => Err(invalid(format!(
+                "fields of type {} can't be given on the command line",
+                ty
+            ))),
+        },
+    }
+}
+
+// The same escaping Serializable applies to strings.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(':', "\\:")
+        .replace('"', "\\\"")
+        .replace(',', "\\,")
+}
+
+// Splits `a: Vec<i32>, b: HashMap<String, i32>` on the commas between fields.
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !s[start..].trim().is_empty() {
+        parts.push(s[start..].trim());
+    }
+    parts
+}
@@ -0,0 +1,54 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+
+static RETRY_BUDGET_RATIO: Lazy<f64> = Lazy::new(|| {
+    std::env::var("RETRY_BUDGET_RATIO")
+        .map(|val| val.parse().expect("RETRY_BUDGET_RATIO must be a number"))
+        .unwrap_or(0.1)
+});
+
+static GLOBAL: Lazy<RetryBudget> = Lazy::new(|| RetryBudget::new(*RETRY_BUDGET_RATIO, 10.0));
+
+// Caps retries at a fraction of all requests, so that during an outage
+// retries can't multiply the load on a struggling service. Every request
+// deposits `ratio` of a token and every retry spends a whole one; the
+// balance never goes above `reserve`, which also lets a process that has
+// been quiet retry a few times straight away.
+pub struct RetryBudget {
+    ratio: f64,
+    reserve: f64,
+    balance: Mutex<f64>,
+}
+
+impl RetryBudget {
+    pub fn new(ratio: f64, reserve: f64) -> Self {
+        RetryBudget {
+            ratio,
+            reserve,
+            balance: Mutex::new(reserve),
+        }
+    }
+
+    pub fn global() -> &'static RetryBudget {
+        &GLOBAL
+    }
+
+    pub fn deposit(&self) {
+        let mut balance = self.balance.lock().unwrap();
+        *balance = (*balance + self.ratio).min(self.reserve);
+    }
+
+    pub fn try_withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().unwrap();
+        if *balance >= 1.0 {
+            *balance -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn balance(&self) -> f64 {
+        *self.balance.lock().unwrap()
+    }
+}
@@ -1,53 +1,197 @@
 // use std::sync::{Arc, Mutex};
-use crate::{Request, Response};
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
+use crate::admission::AdmissionControl;
+use crate::faults::{self, DropSignal};
+use crate::middleware::Endpoint;
+use crate::shutdown::{self, Shutdown};
+use crate::transport::{self, BoxStream, Listener};
+use crate::{deadline, frame, protocol, Handlers, Request, Response};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{self, AsyncBufRead, AsyncReadExt, AsyncWrite, BufReader};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
 
 pub async fn start_server(
     addr: &str,
     handler: impl Fn(Request) -> Response + Send + Sync + 'static + Clone,
 ) -> io::Result<()> {
-    let listener = TcpListener::bind(addr).await?;
+    let mut listener = Listener::bind(addr).await?;
 
     loop {
-        let (mut socket, _) = listener.accept().await?;
+        let socket = listener.accept().await?;
         let handler = handler.clone();
 
-        tokio::spawn(async move {
-            loop {
-                let mut buffer = vec![0u8; 1024];
-                let n = match socket.read(&mut buffer).await {
-                    Ok(n) if n == 0 => {
-                        // Client closed the connection or no data was read.
-                        break;
-                    }
-                    Ok(n) => n,
+        tokio::spawn(serve_connection(
+            socket,
+            move |request| std::future::ready(handler(request)),
+            Shutdown::new(),
+        ));
+    }
+}
+
+// Every server here answers the protocol handshake, see protocol.rs, and
+// clients from before framing too, see frame::read_prefix.
+// Requests on one connection are handled concurrently and each response is
+// tagged with the id of its request, so a slow call doesn't hold up the calls
+// pipelined behind it. Once shutdown starts no more requests are read, and
+// the connection closes after the running ones have answered. A handler can
+// also drop the connection on purpose, for fault injection, in which case
+// nothing more is written to it.
+pub(crate) async fn serve_connection<H, F>(socket: BoxStream, handler: H, shutdown: Shutdown)
+where
+    H: Fn(Request) -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = Response> + Send + 'static,
+{
+    let (mut reader, mut writer) = io::split(socket);
+    let prefix = tokio::select! {
+        biased;
+        _ = shutdown.triggered() => return,
+        prefix = frame::read_prefix(&mut reader) => prefix,
+    };
+    let (prefix, legacy) = match prefix {
+        Ok(prefix) => prefix,
+        Err(e) => {
+            println!("Failed to read from socket: {}", e);
+            return;
+        }
+    };
+    let mut reader = std::io::Cursor::new(prefix).chain(reader);
+    if legacy {
+        serve_legacy_connection(BufReader::new(reader), writer, handler, shutdown).await;
+        return;
+    }
+
+    let (responses, mut outgoing) = mpsc::unbounded_channel::<Response>();
+    let drop_signal = Arc::new(DropSignal::default());
+    // Set once the client negotiates compression; legacy clients never do.
+    let compress = Arc::new(AtomicBool::new(false));
+
+    let writer_signal = drop_signal.clone();
+    let writer_compress = compress.clone();
+    let writer_task = tokio::spawn(async move {
+        while let Some(response) = outgoing.recv().await {
+            if writer_signal.is_dropped() {
+                break;
+            }
+            println!("Sending: {}", frame::preview(&response.payload));
+            let body =
+                frame::encode_response_with(&response, writer_compress.load(Ordering::SeqCst));
+            if let Err(e) = frame::write_frame(&mut writer, &body).await {
+                println!("Failed to write to socket: {}", e);
+                break;
+            }
+        }
+    });
+
+    // Loop to keep reading frames from the socket
+    loop {
+        let frame = tokio::select! {
+            biased;
+            _ = shutdown.triggered() => break,
+            _ = drop_signal.dropped() => break,
+            frame = frame::read_frame(&mut reader) => frame,
+        };
+        match frame {
+            Ok(None) => {
+                // Socket was closed gracefully
+                break;
+            }
+            Ok(Some(body)) => {
+                let request = match frame::decode_request(&body) {
+                    Ok(request) => request,
                     Err(e) => {
-                        eprintln!("Failed to read from socket: {}", e);
+                        println!("Malformed request: {}", e);
                         break;
                     }
                 };
-                // let n = socket
-                //     .read(&mut buffer)
-                //     .await
-                //     .expect("Failed to read from socket");
-                let data = String::from_utf8_lossy(&buffer[..n]);
-                println!("Received: {}", data);
-
-                let parts: Vec<&str> = data.splitn(2, ':').collect();
-                let request = Request {
-                    procedure_id: parts[0].parse().unwrap(),
-                    payload: parts[1].trim().to_string(),
-                };
+                println!(
+                    "Receiving: {}:{}",
+                    request.procedure_id,
+                    frame::preview(&request.payload)
+                );
+
+                // Answered here rather than by the handlers, so every server
+                // takes part whatever its handlers are.
+                if request.procedure_id == protocol::HANDSHAKE_PROCEDURE {
+                    let (mut response, negotiated) = protocol::answer(&request);
+                    println!("Speaking protocol {} with peer", negotiated);
+                    compress.store(negotiated.supports(protocol::COMPRESSION), Ordering::SeqCst);
+                    response.id = request.id;
+                    let _ = responses.send(response);
+                    continue;
+                }
+
+                let handler = handler.clone();
+                let responses = responses.clone();
+                let in_flight = shutdown.begin_request();
+                let drop_signal = drop_signal.clone();
+                tokio::spawn(async move {
+                    let id = request.id;
+                    let deadline = request.deadline;
+                    let mut response = faults::connection_scope(
+                        drop_signal,
+                        deadline::serve(deadline, handler(request)),
+                    )
+                    .await;
+                    response.id = id;
+                    let _ = responses.send(response);
+                    drop(in_flight);
+                });
+            }
+            Err(e) => {
+                println!("Failed to read from socket: {}", e);
+                break;
+            }
+        }
+    }
+
+    // Let requests that are still running finish writing their responses.
+    drop(responses);
+    let _ = writer_task.await;
+}
 
-                let response = handler(request);
-                println!("Sending: {}", response.payload);
-                socket
-                    .write_all(response.payload.as_bytes())
-                    .await
-                    .expect("Failed to write to socket");
+// Legacy clients wait for each answer before sending another request, so
+// their requests are handled one at a time.
+async fn serve_legacy_connection<R, W, H, F>(
+    mut reader: R,
+    mut writer: W,
+    handler: H,
+    shutdown: Shutdown,
+) where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+    H: Fn(Request) -> F,
+    F: Future<Output = Response>,
+{
+    loop {
+        let request = tokio::select! {
+            biased;
+            _ = shutdown.triggered() => break,
+            request = frame::read_legacy_request(&mut reader) => request,
+        };
+        let request = match request {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                println!("Malformed request: {}", e);
+                break;
             }
-        });
+        };
+        println!(
+            "Receiving: {}:{}",
+            request.procedure_id,
+            frame::preview(&request.payload)
+        );
+
+        let in_flight = shutdown.begin_request();
+        let response = handler(request).await;
+        drop(in_flight);
+        println!("Sending: {}", frame::preview(&response.payload));
+        if let Err(e) = frame::write_legacy_response(&mut writer, &response).await {
+            println!("Failed to write to socket: {}", e);
+            break;
+        }
     }
 }
 
@@ -84,8 +228,8 @@ pub async fn start_server(
 
 // pub async fn start_server_with_state<T: Send + 'static>(
 //     addr: &str,
-//     handler: impl Fn(Request, Arc<Mutex<T>>) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync + 'static + Clone,
-//     shared_state: Arc<Mutex<T>>,
+//     handler: impl Fn(Request, S) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync + 'static + Clone,
+//     shared_state: S,
 // ) -> io::Result<()> {
 //     let listener = TcpListener::bind(addr).await?;
 
@@ -118,59 +262,97 @@ pub async fn start_server(
 //     }
 // }
 
-use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
-pub async fn start_server_with_state<T: Send + 'static>(
+// The shared state is cloned into every request, so it is usually an Arc
+// around whatever locking the service needs: a Mutex, an RwLock that lets
+// read-only procedures run side by side, or state sharded across locks.
+// Requests beyond what the server keeps up with are answered Overloaded.
+pub async fn start_server_with_state<S: Clone + Send + Sync + 'static>(
     addr: &str,
-    handler: impl Fn(Request, Arc<Mutex<T>>) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    handler: impl Fn(Request, S) -> Pin<Box<dyn Future<Output = Response> + Send>>
         + Send
         + Sync
         + 'static
         + Clone,
-    shared_state: Arc<Mutex<T>>,
+    shared_state: S,
 ) -> io::Result<()> {
-    let listener = TcpListener::bind(addr).await?;
+    let handlers = Handlers::new().with(AdmissionControl::new());
+    start_server_with_handlers(addr, handlers, handler, shared_state).await
+}
+
+// Runs every request through `handlers` before it reaches `handler`.
+pub async fn start_server_with_handlers<S: Clone + Send + Sync + 'static>(
+    addr: &str,
+    handlers: Handlers,
+    handler: impl Fn(Request, S) -> Pin<Box<dyn Future<Output = Response> + Send>>
+        + Send
+        + Sync
+        + 'static
+        + Clone,
+    shared_state: S,
+) -> io::Result<()> {
+    start_server_with_shutdown(addr, handlers, handler, shared_state, Shutdown::new()).await
+}
+
+// Like start_server_with_handlers, but returns once `shutdown` is triggered
+// and the server has drained. With UNIX_SOCKET_DIR set it serves the same
+// handlers on a Unix socket as well, see transport::unix_address.
+pub async fn start_server_with_shutdown<S: Clone + Send + Sync + 'static>(
+    addr: &str,
+    handlers: Handlers,
+    handler: impl Fn(Request, S) -> Pin<Box<dyn Future<Output = Response> + Send>>
+        + Send
+        + Sync
+        + 'static
+        + Clone,
+    shared_state: S,
+    shutdown: Shutdown,
+) -> io::Result<()> {
+    let handlers = handlers.with(Endpoint::new(move |request| {
+        handler(request, shared_state.clone())
+    }));
+    let mut listener = Listener::bind(addr).await?;
+    let mut local = match transport::unix_address(addr) {
+        Some(local_addr) => Some(Listener::bind(&local_addr).await?),
+        None => None,
+    };
 
     loop {
-        let (mut socket, _) = listener.accept().await?;
-        let handler = handler.clone();
-        let shared_state = shared_state.clone();
+        let socket = tokio::select! {
+            biased;
+            _ = shutdown.triggered() => break,
+            accepted = listener.accept() => accepted?,
+            accepted = accept_local(&mut local) => accepted?,
+        };
+        let handlers = handlers.clone();
 
-        tokio::spawn(async move {
-            let mut buffer = vec![0u8; 1024];
+        tokio::spawn(serve_connection(
+            socket,
+            move |request| {
+                let handlers = handlers.clone();
+                async move { handlers.handle_request(request).await }
+            },
+            shutdown.clone(),
+        ));
+    }
 
-            // Loop to keep reading from the socket
-            loop {
-                match socket.read(&mut buffer).await {
-                    Ok(n) if n == 0 => {
-                        // Socket was closed gracefully
-                        break;
-                    }
-                    Ok(n) => {
-                        let data = String::from_utf8_lossy(&buffer[..n]);
-                        println!("Receiving: {}", data);
-
-                        let parts: Vec<&str> = data.splitn(2, ':').collect();
-                        let request = Request {
-                            procedure_id: parts[0].parse().unwrap(),
-                            payload: parts[1].trim().to_string(),
-                        };
-
-                        let response = handler(request, shared_state.clone()).await;
-                        if socket.write_all(response.payload.as_bytes()).await.is_err() {
-                            println!("Failed to write to socket");
-                            break;
-                        }
-                    }
-                    Err(_) => {
-                        println!("Failed to read from socket");
-                        break;
-                    }
-                }
-            }
-        });
+    drop(listener);
+    drop(local);
+    println!(
+        "Shutting down {}, {} requests in flight",
+        addr,
+        shutdown.in_flight()
+    );
+    shutdown
+        .drain(Duration::from_secs(*shutdown::GRACE_PERIOD_SECS))
+        .await;
+    Ok(())
+}
+
+async fn accept_local(listener: &mut Option<Listener>) -> io::Result<BoxStream> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
     }
 }
@@ -0,0 +1,143 @@
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration, Instant};
+
+pub static GRACE_PERIOD_SECS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("GRACE_PERIOD_SECS")
+        .map(|val| val.parse().expect("GRACE_PERIOD_SECS must be a number"))
+        .unwrap_or(10)
+});
+
+type Hook = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Inner {
+    triggered: watch::Sender<bool>,
+    hooks: Mutex<Vec<Hook>>,
+    in_flight: AtomicUsize,
+}
+
+// Tells a server to stop. Once triggered, the server stops accepting
+// connections and requests, runs the shutdown hooks (e.g. deregistering from
+// discovery), waits up to the grace period for in-flight requests, and returns.
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (triggered, _) = watch::channel(false);
+        Shutdown {
+            inner: Arc::new(Inner {
+                triggered,
+                hooks: Mutex::new(Vec::new()),
+                in_flight: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    // A handle that is also triggered by SIGTERM or Ctrl-C. Must be called
+    // from inside a tokio runtime.
+    pub fn on_signal() -> Self {
+        let shutdown = Shutdown::new();
+        let handle = shutdown.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            println!("Received shutdown signal");
+            handle.trigger();
+        });
+        shutdown
+    }
+
+    pub fn trigger(&self) {
+        self.inner.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.inner.triggered.borrow()
+    }
+
+    pub async fn triggered(&self) {
+        let mut receiver = self.inner.triggered.subscribe();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // Runs once shutdown starts, before in-flight requests are drained.
+    pub fn on_shutdown(&self, hook: impl Future<Output = ()> + Send + 'static) {
+        self.inner.hooks.lock().unwrap().push(Box::pin(hook));
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn begin_request(&self) -> RequestGuard {
+        self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        RequestGuard {
+            shutdown: self.clone(),
+        }
+    }
+
+    // Called by the server after it has stopped accepting connections.
+    pub(crate) async fn drain(&self, grace_period: Duration) {
+        let deadline = Instant::now() + grace_period;
+
+        let hooks: Vec<Hook> = self.inner.hooks.lock().unwrap().drain(..).collect();
+        for hook in hooks {
+            if tokio::time::timeout_at(deadline, hook).await.is_err() {
+                eprintln!("Shutdown hook did not finish within the grace period");
+            }
+        }
+
+        while self.in_flight() > 0 {
+            if Instant::now() >= deadline {
+                eprintln!(
+                    "Grace period expired with {} requests in flight",
+                    self.in_flight()
+                );
+                return;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+pub(crate) struct RequestGuard {
+    shutdown: Shutdown,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.shutdown.inner.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,265 @@
+use crate::connection::IO_RUNTIME;
+use crate::reflection::ServiceDescription;
+use crate::{Client, ProcedureId, Request, SERVICE_NAME};
+use normalization::{Deserializable, Serializable};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub type TraceId = u64;
+pub type SpanId = u64;
+
+// Where finished spans are sent. Monitoring hosts the collector, and answers
+// RECORD_SPANS_PROCEDURE alongside its own procedures.
+pub static TRACE_COLLECTOR_ADDR: Lazy<String> = Lazy::new(|| {
+    std::env::var("TRACE_COLLECTOR_ADDR").unwrap_or_else(|_| "127.0.0.1:10800".to_string())
+});
+
+static TRACE_FLUSH_MS: Lazy<u64> = Lazy::new(|| {
+    std::env::var("TRACE_FLUSH_MS")
+        .map(|val| val.parse().expect("TRACE_FLUSH_MS must be a number"))
+        .unwrap_or(1000)
+});
+
+// Spans waiting to be sent are capped so an unreachable collector can't
+// grow the buffer without bound; the oldest are dropped first.
+static MAX_BUFFERED_SPANS: Lazy<usize> = Lazy::new(|| {
+    std::env::var("MAX_BUFFERED_SPANS")
+        .map(|val| val.parse().expect("MAX_BUFFERED_SPANS must be a number"))
+        .unwrap_or(10_000)
+});
+
+pub const RECORD_SPANS_PROCEDURE: ProcedureId = 5;
+
+// `spans` holds one encoded Span per entry, joined by ';'.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct RecordSpansArgs {
+    pub spans: String,
+}
+
+// Identifies the span a piece of work belongs to. A request carries the
+// caller's context, and the span the server opens for it becomes the parent
+// of every call the handler makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+}
+
+tokio::task_local! {
+    static CURRENT: Option<TraceContext>;
+}
+
+// The span the current task is working in, if it is part of a trace.
+// Outgoing calls made from inside a handler carry it automatically.
+pub fn current() -> Option<TraceContext> {
+    CURRENT.try_with(|context| *context).ok().flatten()
+}
+
+pub async fn scope<F: Future>(context: Option<TraceContext>, future: F) -> F::Output {
+    CURRENT.scope(context, future).await
+}
+
+// Ids are sent as hex, and 0 is never used so it can't be mistaken for a
+// missing id.
+pub fn new_id() -> u64 {
+    rand::thread_rng().gen_range(1..=u64::MAX)
+}
+
+pub fn format_id(id: u64) -> String {
+    format!("{:016x}", id)
+}
+
+pub fn parse_id(id: &str) -> Option<u64> {
+    u64::from_str_radix(id, 16).ok().filter(|id| *id != 0)
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+    // None for the root span of a trace.
+    pub parent_id: Option<SpanId>,
+    pub service: String,
+    pub name: String,
+    // Microseconds since the unix epoch, so spans from different processes
+    // line up on one timeline.
+    pub start: u64,
+    pub duration: u64,
+    pub status: String,
+}
+
+impl Span {
+    pub fn end(&self) -> u64 {
+        self.start + self.duration
+    }
+
+    // `trace|span|parent|service|name|start|duration|status`, with the
+    // separators stripped from the text fields.
+    pub fn encode(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}",
+            format_id(self.trace_id),
+            format_id(self.span_id),
+            self.parent_id.map(format_id).unwrap_or_default(),
+            clean(&self.service),
+            clean(&self.name),
+            self.start,
+            self.duration,
+            clean(&self.status)
+        )
+    }
+
+    pub fn decode(s: &str) -> Option<Span> {
+        let fields: Vec<&str> = s.trim().split('|').collect();
+        if fields.len() != 8 {
+            return None;
+        }
+        Some(Span {
+            trace_id: parse_id(fields[0])?,
+            span_id: parse_id(fields[1])?,
+            parent_id: parse_id(fields[2]),
+            service: fields[3].to_string(),
+            name: fields[4].to_string(),
+            start: fields[5].parse().ok()?,
+            duration: fields[6].parse().ok()?,
+            status: fields[7].to_string(),
+        })
+    }
+}
+
+fn clean(s: &str) -> String {
+    s.replace(['|', ';'], " ")
+}
+
+pub fn encode_spans(spans: &[Span]) -> String {
+    let spans: Vec<String> = spans.iter().map(Span::encode).collect();
+    spans.join(";")
+}
+
+// Entries that don't parse are skipped rather than failing the batch.
+pub fn decode_spans(s: &str) -> Vec<Span> {
+    s.split(';').filter_map(Span::decode).collect()
+}
+
+// A span that has started but not finished. Run the work under `context()`
+// and call `finish` once it is done; a span that is dropped unfinished is
+// never recorded.
+pub struct ActiveSpan {
+    context: TraceContext,
+    parent_id: Option<SpanId>,
+    name: String,
+    start: u64,
+    started: Instant,
+}
+
+impl ActiveSpan {
+    // Starts a new trace.
+    pub fn root(name: impl Into<String>) -> ActiveSpan {
+        ActiveSpan::start(new_id(), None, name.into())
+    }
+
+    pub fn child(parent: TraceContext, name: impl Into<String>) -> ActiveSpan {
+        ActiveSpan::start(parent.trace_id, Some(parent.span_id), name.into())
+    }
+
+    fn start(trace_id: TraceId, parent_id: Option<SpanId>, name: String) -> ActiveSpan {
+        ActiveSpan {
+            context: TraceContext {
+                trace_id,
+                span_id: new_id(),
+            },
+            parent_id,
+            name,
+            start: now_micros(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn context(&self) -> TraceContext {
+        self.context
+    }
+
+    pub fn finish(self, status: impl Into<String>) {
+        record(Span {
+            trace_id: self.context.trace_id,
+            span_id: self.context.span_id,
+            parent_id: self.parent_id,
+            service: SERVICE_NAME.clone(),
+            name: self.name,
+            start: self.start,
+            duration: self.started.elapsed().as_micros() as u64,
+            status: status.into(),
+        });
+    }
+}
+
+static PROCEDURE_NAMES: Lazy<Mutex<HashMap<ProcedureId, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Lets server spans show `get` rather than `procedure 1`. Reflection::new
+// registers the names of the service it describes.
+pub fn name_procedures(description: &ServiceDescription) {
+    let mut names = PROCEDURE_NAMES.lock().unwrap();
+    for procedure in &description.procedures {
+        names.insert(procedure.id, procedure.name.clone());
+    }
+}
+
+pub fn procedure_name(procedure_id: ProcedureId) -> String {
+    match PROCEDURE_NAMES.lock().unwrap().get(&procedure_id) {
+        Some(name) => name.clone(),
+        None => format!("procedure {}", procedure_id),
+    }
+}
+
+static BUFFER: Lazy<Mutex<VecDeque<Span>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static START_FLUSHING: Once = Once::new();
+
+// Buffers a finished span; a background task sends the buffer to the
+// collector every TRACE_FLUSH_MS.
+pub fn record(span: Span) {
+    {
+        let mut buffer = BUFFER.lock().unwrap();
+        if buffer.len() >= *MAX_BUFFERED_SPANS {
+            buffer.pop_front();
+        }
+        buffer.push_back(span);
+    }
+    START_FLUSHING.call_once(|| {
+        IO_RUNTIME.spawn(async {
+            loop {
+                tokio::time::sleep(Duration::from_millis(*TRACE_FLUSH_MS)).await;
+                flush().await;
+            }
+        });
+    });
+}
+
+// Sends every buffered span to the collector. Spans that can't be delivered
+// are dropped: tracing is best effort and must never hold up a request.
+pub async fn flush() {
+    let spans: Vec<Span> = BUFFER.lock().unwrap().drain(..).collect();
+    if spans.is_empty() {
+        return;
+    }
+    let args = RecordSpansArgs {
+        spans: encode_spans(&spans),
+    };
+    // Outside of any trace scope, so shipping spans isn't itself traced.
+    let request = Request::new(RECORD_SPANS_PROCEDURE, args.serialize());
+    let result = scope(None, Client::global().call(&TRACE_COLLECTOR_ADDR, request)).await;
+    if let Err(e) = result.and_then(|response| response.into_result()) {
+        println!("Dropped {} spans: {}", spans.len(), e);
+    }
+}
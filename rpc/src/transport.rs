@@ -0,0 +1,223 @@
+use crate::SERVICE_NAME;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::{self, AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+// Addresses starting with this are served in memory by a listener in the
+// same process, e.g. `mem:storage`. Ones starting with UNIX_PREFIX are a
+// Unix domain socket path, e.g. `unix:/run/planetary/storage-10600.sock`.
+// Anything else is a TCP `host:port`.
+pub const MEMORY_PREFIX: &str = "mem:";
+pub const UNIX_PREFIX: &str = "unix:";
+
+// When set, servers started with start_server_with_shutdown also listen on
+// `<dir>/<service>-<port>.sock` next to their TCP address, and register both
+// with discovery so co-located callers can skip TCP.
+pub static UNIX_SOCKET_DIR: Lazy<Option<String>> = Lazy::new(|| {
+    std::env::var("UNIX_SOCKET_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+});
+
+// Who may connect to a socket is decided by its file mode: by default the
+// owner and the group it was created with.
+static UNIX_SOCKET_MODE: Lazy<u32> = Lazy::new(|| {
+    std::env::var("UNIX_SOCKET_MODE")
+        .map(|val| u32::from_str_radix(&val, 8).expect("UNIX_SOCKET_MODE must be an octal mode"))
+        .unwrap_or(0o660)
+});
+
+// How much each direction of an in-memory connection buffers before the
+// writer has to wait for the reader.
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+pub type BoxStream = Box<dyn Stream>;
+
+// The server ends of in-memory connections, by address, waiting to be
+// accepted by the listener bound there.
+static MEMORY_LISTENERS: Lazy<Mutex<HashMap<String, mpsc::UnboundedSender<DuplexStream>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn is_memory(addr: &str) -> bool {
+    addr.starts_with(MEMORY_PREFIX)
+}
+
+pub fn is_unix(addr: &str) -> bool {
+    addr.starts_with(UNIX_PREFIX)
+}
+
+// The socket a server on the TCP address `addr` also listens on, if
+// UNIX_SOCKET_DIR is set.
+pub fn unix_address(addr: &str) -> Option<String> {
+    let dir = UNIX_SOCKET_DIR.as_ref()?;
+    if is_memory(addr) || is_unix(addr) {
+        return None;
+    }
+    let port = addr.rsplit(':').next().unwrap_or(addr);
+    let path = Path::new(dir).join(format!("{}-{}.sock", *SERVICE_NAME, port));
+    Some(format!("{}{}", UNIX_PREFIX, path.display()))
+}
+
+fn port(addr: &str) -> &str {
+    let addr = addr.strip_suffix(".sock").unwrap_or(addr);
+    addr.rsplit([':', '-']).next().unwrap_or(addr)
+}
+
+fn is_loopback(addr: &str) -> bool {
+    addr.starts_with("127.0.0.1:") || addr.starts_with("localhost:")
+}
+
+// Discovery lists a server with a socket under both of its addresses. This
+// keeps one of each pair: the socket when it is on this host, the TCP address
+// otherwise. Sockets on other hosts are dropped.
+pub fn one_per_server(addresses: Vec<String>) -> Vec<String> {
+    let on_this_host = |addr: &str| match addr.strip_prefix(UNIX_PREFIX) {
+        Some(path) => Path::new(path).exists(),
+        None => false,
+    };
+    let local_ports: Vec<String> = addresses
+        .iter()
+        .filter(|addr| on_this_host(addr))
+        .map(|addr| port(addr).to_string())
+        .collect();
+    addresses
+        .into_iter()
+        .filter(|addr| {
+            if is_unix(addr) {
+                on_this_host(addr)
+            } else {
+                !(is_loopback(addr) && local_ports.iter().any(|p| p == port(addr)))
+            }
+        })
+        .collect()
+}
+
+// Connecting to an in-memory address or a socket path nobody is listening on
+// fails the way a refused TCP connection does, so retries and circuit
+// breakers treat them all the same.
+pub async fn connect(addr: &str) -> io::Result<BoxStream> {
+    if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+        return match UnixStream::connect(path).await {
+            Ok(stream) => Ok(Box::new(stream)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("Nothing is listening on {}", addr),
+            )),
+            Err(e) => Err(e),
+        };
+    }
+    if !is_memory(addr) {
+        return Ok(Box::new(tokio::net::TcpStream::connect(addr).await?));
+    }
+    let (client, server) = tokio::io::duplex(MEMORY_BUFFER_SIZE);
+    let listeners = MEMORY_LISTENERS.lock().unwrap();
+    match listeners.get(addr) {
+        Some(listener) if listener.send(server).is_ok() => Ok(Box::new(client)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Nothing is listening on {}", addr),
+        )),
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+    Memory {
+        addr: String,
+        incoming: mpsc::UnboundedReceiver<DuplexStream>,
+    },
+}
+
+impl Listener {
+    pub async fn bind(addr: &str) -> io::Result<Listener> {
+        if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+            return bind_unix(addr, PathBuf::from(path)).await;
+        }
+        if !is_memory(addr) {
+            return Ok(Listener::Tcp(TcpListener::bind(addr).await?));
+        }
+        let mut listeners = MEMORY_LISTENERS.lock().unwrap();
+        if listeners.contains_key(addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already in use", addr),
+            ));
+        }
+        let (sender, incoming) = mpsc::unbounded_channel();
+        listeners.insert(addr.to_string(), sender);
+        Ok(Listener::Memory {
+            addr: addr.to_string(),
+            incoming,
+        })
+    }
+
+    pub async fn accept(&mut self) -> io::Result<BoxStream> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (socket, _) = listener.accept().await?;
+                Ok(Box::new(socket))
+            }
+            Listener::Unix { listener, .. } => {
+                let (socket, _) = listener.accept().await?;
+                Ok(Box::new(socket))
+            }
+            Listener::Memory { addr, incoming } => match incoming.recv().await {
+                Some(stream) => Ok(Box::new(stream)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("{} stopped listening", addr),
+                )),
+            },
+        }
+    }
+}
+
+// A socket file left behind by a server that crashed would make the bind
+// fail, so one nobody answers on is removed first. One that still answers
+// belongs to a running server.
+async fn bind_unix(addr: &str, path: PathBuf) -> io::Result<Listener> {
+    if path.exists() {
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already in use", addr),
+            ));
+        }
+        std::fs::remove_file(&path)?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(*UNIX_SOCKET_MODE))?;
+    Ok(Listener::Unix { listener, path })
+}
+
+// Frees the address, so in-memory and Unix socket servers can be restarted
+// like TCP ones.
+impl Drop for Listener {
+    fn drop(&mut self) {
+        match self {
+            Listener::Memory { addr, .. } => {
+                MEMORY_LISTENERS.lock().unwrap().remove(addr.as_str());
+            }
+            Listener::Unix { path, .. } => {
+                let _ = std::fs::remove_file(path);
+            }
+            Listener::Tcp(_) => {}
+        }
+    }
+}
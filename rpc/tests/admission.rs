@@ -0,0 +1,120 @@
+use rpc::admission::AdmissionControl;
+use rpc::reflection::{self, Reflection, ServiceDescription};
+use rpc::{client_v1, frame, server, Client, Error, Handlers, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+// Sleeps for as many milliseconds as the payload says.
+fn sleepy(request: Request, _state: ()) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+    Box::pin(async move {
+        let millis = request.payload.parse().unwrap_or(0);
+        sleep(Duration::from_millis(millis)).await;
+        Response::ok(request.payload)
+    })
+}
+
+async fn start_sleepy(addr: &str, admission: AdmissionControl) {
+    let addr = addr.to_string();
+    let handlers = Handlers::new()
+        .with(admission)
+        .with(Reflection::new(ServiceDescription::new("sleepy", "1")));
+    tokio::spawn(
+        async move { server::start_server_with_handlers(&addr, handlers, sleepy, ()).await },
+    );
+    tokio::task::yield_now().await;
+}
+
+async fn call(addr: &str, millis: u64) -> Result<Response, Error> {
+    Client::global()
+        .call(addr, Request::new(1, millis.to_string()))
+        .await
+}
+
+#[test]
+fn test_retry_after_round_trips() {
+    let mut response = Response::overloaded("busy", Duration::from_millis(250));
+    response.id = 3;
+    let decoded = frame::decode_response(&frame::encode_response(&response)).unwrap();
+    assert_eq!(decoded.status, Status::Overloaded);
+    assert_eq!(decoded.retry_after, Some(Duration::from_millis(250)));
+
+    let err = decoded.into_result().unwrap_err();
+    assert_eq!(err.retry_after(), Some(Duration::from_millis(250)));
+    assert!(err.is_retryable() && err.was_rejected());
+    assert_eq!(Status::from_name("overloaded"), Some(Status::Overloaded));
+}
+
+#[tokio::test]
+async fn test_requests_over_the_limit_are_overloaded() {
+    let admission = AdmissionControl::with_limits(1.0, 1.0, 1.0);
+    start_sleepy("mem:admission-limit", admission.clone()).await;
+
+    let slow = tokio::spawn(call("mem:admission-limit", 200));
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(admission.in_flight(), 1);
+
+    let err = call("mem:admission-limit", 0).await.unwrap_err();
+    assert_eq!(err.status(), Status::Overloaded);
+    assert!(err.retry_after().is_some());
+    // Reflection is admitted regardless.
+    let description = reflection::describe("mem:admission-limit").await.unwrap();
+    assert_eq!(description.name, "sleepy");
+
+    assert!(slow.await.unwrap().is_ok());
+    assert!(call("mem:admission-limit", 0).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_limit_adapts_to_latency() {
+    let admission = AdmissionControl::with_limits(20.0, 2.0, 40.0);
+    start_sleepy("mem:admission-adapt", admission.clone()).await;
+
+    // Fast requests keeping the server busy raise the limit...
+    for _ in 0..10 {
+        let calls: Vec<_> = (0..15)
+            .map(|_| tokio::spawn(call("mem:admission-adapt", 1)))
+            .collect();
+        for call in calls {
+            call.await.unwrap().unwrap();
+        }
+    }
+    let raised = admission.limit();
+    assert!(raised > 20, "{}", raised);
+
+    // ...and once they slow down it is cut.
+    for _ in 0..5 {
+        let calls: Vec<_> = (0..2)
+            .map(|_| tokio::spawn(call("mem:admission-adapt", 60)))
+            .collect();
+        for call in calls {
+            let _ = call.await.unwrap();
+        }
+    }
+    assert!(
+        admission.limit() < raised,
+        "{} >= {}",
+        admission.limit(),
+        raised
+    );
+}
+
+#[tokio::test]
+async fn test_client_v1_waits_out_the_retry_after_hint() {
+    let admission = AdmissionControl::with_limits(1.0, 1.0, 1.0);
+    start_sleepy("mem:admission-retry", admission).await;
+
+    let slow = tokio::spawn(call("mem:admission-retry", 150));
+    sleep(Duration::from_millis(20)).await;
+
+    // Not idempotent, but it was never run, so it is retried once the hint
+    // has passed rather than straight away.
+    let started = Instant::now();
+    let response = client_v1::send_request("mem:admission-retry", Request::new(1, "0"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "0");
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert!(slow.await.unwrap().is_ok());
+}
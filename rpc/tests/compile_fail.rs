@@ -0,0 +1,5 @@
+// Services that must not compile, with the errors they must give.
+#[test]
+fn test_procedure_id_clashes_fail_to_compile() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,156 @@
+use rpc::compression::{compress, decompress};
+use rpc::metrics::Metrics;
+use rpc::transport::Listener;
+use rpc::{frame, server, Client, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+
+fn echo(request: Request, _state: ()) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+    Box::pin(async move { Response::ok(request.payload) })
+}
+
+// Deterministic bytes that don't compress.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state: u32 = 12345;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+fn scan_result(rows: usize) -> String {
+    let rows: Vec<String> = (0..rows)
+        .map(|i| {
+            format!(
+                "{{key: \"user/{}\", value: \"active\", version: {}}}",
+                i,
+                i % 7
+            )
+        })
+        .collect();
+    format!("{{rows: [{}]}}", rows.join(", "))
+}
+
+#[test]
+fn test_round_trips() {
+    let inputs: Vec<Vec<u8>> = vec![
+        Vec::new(),
+        b"abc".to_vec(),
+        vec![b'x'; 100_000],
+        noise(10_000),
+        scan_result(500).into_bytes(),
+        [noise(300), noise(300)].concat(),
+    ];
+    for input in inputs {
+        let compressed = compress(&input);
+        assert_eq!(
+            decompress(&compressed).unwrap(),
+            input,
+            "{} bytes",
+            input.len()
+        );
+    }
+
+    let text = scan_result(500);
+    assert!(compress(text.as_bytes()).len() * 4 < text.len());
+    assert!(compress(&vec![b'x'; 100_000]).len() < 3000);
+}
+
+#[test]
+fn test_corrupt_input_is_rejected() {
+    let compressed = compress(scan_result(50).as_bytes());
+    assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+    assert!(decompress(&compressed[..2]).is_err());
+
+    let mut wrong_length = compressed.clone();
+    wrong_length[3] ^= 1;
+    assert!(decompress(&wrong_length).is_err());
+
+    // A copy reaching back before the start of the output.
+    assert!(decompress(&[0, 0, 0, 4, 0x80, 0, 1]).is_err());
+}
+
+#[test]
+fn test_frames_compress_large_payloads_when_asked() {
+    let large = Response::ok(scan_result(500));
+    let body = frame::encode_response_with(&large, true);
+    assert!(String::from_utf8_lossy(&body).contains("encoding=lz"));
+    assert!(body.len() * 4 < large.payload.len());
+    assert_eq!(
+        frame::decode_response(&body).unwrap().payload,
+        large.payload
+    );
+
+    let plain = frame::encode_response_with(&large, false);
+    assert!(!String::from_utf8_lossy(&plain).contains("encoding="));
+    let small = frame::encode_response_with(&Response::ok("{ok: 1}"), true);
+    assert!(!String::from_utf8_lossy(&small).contains("encoding="));
+
+    let mut unknown = b"id=1;status=0;encoding=zip\n".to_vec();
+    unknown.extend_from_slice(b"payload");
+    assert!(frame::decode_response(&unknown).is_err());
+}
+
+#[tokio::test]
+async fn test_negotiated_connections_compress_and_record_the_ratio() {
+    let addr = "mem:compression-echo".to_string();
+    let server_addr = addr.clone();
+    tokio::spawn(async move { server::start_server_with_state(&server_addr, echo, ()).await });
+    tokio::task::yield_now().await;
+
+    let payload = scan_result(1000);
+    let response = Client::global()
+        .call(&addr, Request::new(1, payload.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, payload);
+
+    let batch = Metrics::global().take_batch();
+    let metric = |name: &str| {
+        batch
+            .iter()
+            .find(|(metric, _)| metric == name)
+            .map(|(_, v)| *v)
+    };
+    let ratio = metric("compression.ratio_pct").unwrap();
+    assert!(ratio > 0 && ratio < 50, "{}", ratio);
+    assert!(metric("compression.original_bytes").unwrap() >= 2 * payload.len() as i32);
+}
+
+#[tokio::test]
+async fn test_legacy_servers_get_plain_payloads() {
+    let mut listener = Listener::bind("mem:compression-legacy").await.unwrap();
+    let raw_requests = tokio::spawn(async move {
+        let mut socket = listener.accept().await.unwrap();
+        let mut bodies = Vec::new();
+        while let Ok(Some(body)) = frame::read_frame(&mut socket).await {
+            // Answers the handshake like a server from before it.
+            let request = frame::decode_request(&body).unwrap();
+            let mut response = match request.procedure_id {
+                1 => Response::ok(request.payload),
+                _ => Response::unknown_procedure(request.procedure_id),
+            };
+            response.id = request.id;
+            frame::write_frame(&mut socket, &frame::encode_response(&response))
+                .await
+                .unwrap();
+            bodies.push(body);
+            if bodies.len() == 2 {
+                break;
+            }
+        }
+        bodies
+    });
+
+    let payload = scan_result(1000);
+    let response = Client::global()
+        .call("mem:compression-legacy", Request::new(1, payload.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, payload);
+    for body in raw_requests.await.unwrap() {
+        assert!(!String::from_utf8_lossy(&body).contains("encoding="));
+    }
+}
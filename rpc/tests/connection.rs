@@ -0,0 +1,89 @@
+use rpc::connection::Connection;
+use rpc::{server, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+async fn slow_echo(request: Request, _state: Arc<Mutex<()>>) -> Response {
+    let delay: u64 = request.payload.parse().unwrap_or(0);
+    sleep(Duration::from_millis(delay)).await;
+    Response::ok(request.payload)
+}
+
+#[tokio::test]
+async fn test_pipelined_calls_complete_out_of_order() {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            |request, state| {
+                Box::pin(slow_echo(request, state))
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(Mutex::new(())),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let conn = Arc::new(Connection::connect(&addr).await.unwrap());
+    let started = Instant::now();
+
+    let slow_conn = conn.clone();
+    let slow = tokio::spawn(async move {
+        let response = slow_conn.call(Request::new(1, "500")).await.unwrap();
+        (response.payload, Instant::now())
+    });
+    sleep(Duration::from_millis(20)).await;
+    let fast = conn.call(Request::new(1, "0")).await.unwrap();
+    let fast_done = Instant::now();
+
+    let (slow_payload, slow_done) = slow.await.unwrap();
+    assert_eq!(fast.payload, "0");
+    assert_eq!(slow_payload, "500");
+    assert!(fast_done < slow_done);
+    assert!(fast_done.duration_since(started) < Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn test_many_concurrent_calls_share_one_connection() {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            |request, state| {
+                Box::pin(slow_echo(request, state))
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(Mutex::new(())),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let conn = Arc::new(Connection::connect(&addr).await.unwrap());
+    let calls: Vec<_> = (0..50)
+        .map(|i| {
+            let conn = conn.clone();
+            tokio::spawn(async move {
+                let delay = (50 - i) % 7;
+                let response = conn.call(Request::new(1, delay.to_string())).await.unwrap();
+                assert_eq!(response.payload, delay.to_string());
+            })
+        })
+        .collect();
+    for call in calls {
+        call.await.unwrap();
+    }
+    assert_eq!(conn.in_flight(), 0);
+}
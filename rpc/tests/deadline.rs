@@ -0,0 +1,101 @@
+use rpc::{client, client_v1, deadline, server, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+async fn start_server<F>(
+    handler: fn(Request, Arc<Mutex<String>>) -> F,
+    downstream: String,
+) -> String
+where
+    F: Future<Output = Response> + Send + 'static,
+{
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            move |request, state| {
+                Box::pin(handler(request, state)) as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(Mutex::new(downstream)),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    addr
+}
+
+async fn slow(request: Request, _state: Arc<Mutex<String>>) -> Response {
+    let delay: u64 = request.payload.parse().unwrap_or(0);
+    sleep(Duration::from_millis(delay)).await;
+    Response::ok(request.payload)
+}
+
+// Reports the budget it was given, then forwards the call downstream.
+async fn forward(request: Request, state: Arc<Mutex<String>>) -> Response {
+    let downstream = state.lock().await.clone();
+    let inherited = deadline::current();
+    match client::send_request(&downstream, Request::new(1, request.payload)).await {
+        Ok(response) => Response::ok(format!("{:?}:{}", inherited, response.payload)),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[tokio::test]
+async fn test_expired_request_is_rejected() {
+    let addr = start_server(slow, String::new()).await;
+    let request = Request::new(1, "0").with_deadline(deadline::now() - 1000);
+
+    let mut stream = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    rpc::frame::write_request(&mut stream, &request)
+        .await
+        .unwrap();
+    let response = rpc::frame::read_response(&mut stream).await.unwrap();
+    assert_eq!(response.status, Status::DeadlineExceeded);
+}
+
+#[tokio::test]
+async fn test_handler_is_cancelled_at_deadline() {
+    let addr = start_server(slow, String::new()).await;
+
+    let started = Instant::now();
+    let err = client_v1::send_request_with_timeout(
+        &addr,
+        Request::new(1, "2000"),
+        Duration::from_millis(200),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.status(), Status::DeadlineExceeded);
+    assert!(started.elapsed() < Duration::from_millis(1000));
+}
+
+#[tokio::test]
+async fn test_remaining_budget_is_passed_downstream() {
+    let backend = start_server(slow, String::new()).await;
+    let middle = start_server(forward, backend).await;
+
+    let request = Request::new(1, "0").with_timeout(Duration::from_secs(5));
+    let sent_deadline = request.deadline;
+    let response = client::send_request(&middle, request).await.unwrap();
+    assert_eq!(response.payload, format!("{:?}:0", sent_deadline));
+
+    // The downstream call runs out of budget even though the backend itself has no timeout.
+    let started = Instant::now();
+    let err = client::send_request(
+        &middle,
+        Request::new(1, "2000").with_timeout(Duration::from_millis(300)),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.status(), Status::DeadlineExceeded);
+    assert!(started.elapsed() < Duration::from_millis(1000));
+}
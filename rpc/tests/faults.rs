@@ -0,0 +1,177 @@
+use rpc::faults::{FaultInjection, FaultRule, CONFIG_GET_PROCEDURE, CONFIG_LIST_PROCEDURE};
+use rpc::reflection::{self, Reflection, ServiceDescription};
+use rpc::{server, Client, Handlers, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+fn echo(request: Request, _state: ()) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+    Box::pin(async move { Response::ok(request.payload) })
+}
+
+fn start_server(addr: &str, handlers: Handlers) {
+    let addr = addr.to_string();
+    tokio::spawn(
+        async move { server::start_server_with_handlers(&addr, handlers, echo, ()).await },
+    );
+}
+
+async fn start_faulty(addr: &str, rules: &[(&str, &str)]) {
+    let faults = FaultInjection::new();
+    faults.set_rules(
+        rules
+            .iter()
+            .map(|(procedure, value)| FaultRule::parse(procedure, value).unwrap())
+            .collect(),
+    );
+    let description = ServiceDescription::new("faulty", "1").procedure(
+        7,
+        "named",
+        reflection::Schema::none(),
+        reflection::Schema::none(),
+    );
+    start_server(
+        addr,
+        Handlers::new()
+            .with(faults)
+            .with(Reflection::new(description)),
+    );
+    tokio::task::yield_now().await;
+}
+
+async fn call(addr: &str, procedure_id: i32) -> Result<Response, rpc::Error> {
+    Client::global()
+        .call(addr, Request::new(procedure_id, "{payload}"))
+        .await
+}
+
+#[test]
+fn test_parse_rules() {
+    let rule = FaultRule::parse("get", "latency_ms=200, error=unavailable, percent=25").unwrap();
+    assert_eq!(rule.latency, Some(Duration::from_millis(200)));
+    assert_eq!(rule.error, Some(Status::Unavailable));
+    assert_eq!(rule.percent, 25.0);
+    assert!(!rule.drop && !rule.corrupt);
+
+    let rule = FaultRule::parse("*", "drop,corrupt").unwrap();
+    assert!(rule.drop && rule.corrupt);
+    assert_eq!(rule.percent, 100.0);
+    assert!(rule.matches(1, None) && rule.matches(99, Some("get")));
+
+    assert!(FaultRule::parse("1", "error=ok").is_err());
+    assert!(FaultRule::parse("1", "error=sometimes").is_err());
+    assert!(FaultRule::parse("1", "percent=150").is_err());
+    assert!(FaultRule::parse("1", "latency_ms=soon").is_err());
+    assert!(FaultRule::parse("1", "explode").is_err());
+}
+
+#[tokio::test]
+async fn test_rules_target_procedures_by_id_or_name() {
+    start_faulty(
+        "mem:faults-errors",
+        &[
+            ("3", "error=internal"),
+            ("named", "error=unauthenticated"),
+            ("4", "error=internal,percent=0"),
+        ],
+    )
+    .await;
+
+    let err = call("mem:faults-errors", 3).await.unwrap_err();
+    assert_eq!(err.status(), Status::Internal);
+    let err = call("mem:faults-errors", 7).await.unwrap_err();
+    assert_eq!(err.status(), Status::Unauthenticated);
+    assert!(call("mem:faults-errors", 4).await.is_ok());
+    assert!(call("mem:faults-errors", 5).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_names_come_from_each_servers_own_handlers() {
+    start_faulty("mem:faults-named", &[("named", "error=unavailable")]).await;
+    // The same id under another name, on another server in this process.
+    let faults = FaultInjection::new();
+    faults.set_rules(vec![FaultRule::parse("named", "error=unavailable").unwrap()]);
+    let description = ServiceDescription::new("other", "1").procedure(
+        7,
+        "other",
+        reflection::Schema::none(),
+        reflection::Schema::none(),
+    );
+    start_server(
+        "mem:faults-other",
+        Handlers::new()
+            .with(faults)
+            .with(Reflection::new(description)),
+    );
+    tokio::task::yield_now().await;
+
+    let err = call("mem:faults-named", 7).await.unwrap_err();
+    assert_eq!(err.status(), Status::Unavailable);
+    assert!(call("mem:faults-other", 7).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_latency_and_corruption() {
+    start_faulty("mem:faults-slow", &[("*", "latency_ms=100,corrupt")]).await;
+
+    let started = Instant::now();
+    let response = call("mem:faults-slow", 1).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(response.payload, "{pay#corrupted#");
+
+    // Reflection is never faulted.
+    let description = reflection::describe("mem:faults-slow").await.unwrap();
+    assert_eq!(description.name, "faulty");
+}
+
+#[tokio::test]
+async fn test_drop_closes_the_connection() {
+    start_faulty("mem:faults-drop", &[("1", "drop")]).await;
+
+    let err = call("mem:faults-drop", 1).await.unwrap_err();
+    assert!(matches!(err, rpc::Error::Io(_)), "{}", err);
+    // The client reconnects for the next call.
+    assert!(call("mem:faults-drop", 2).await.is_ok());
+}
+
+// Answers the configuration procedures the way the configuration service
+// would, with one `error=unavailable` rule for `get` under any prefix.
+fn config(request: Request, _state: ()) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+    Box::pin(async move {
+        let field = |payload: &str| {
+            let start = payload.find(": \"").unwrap() + 3;
+            payload[start..payload.len() - 2].to_string()
+        };
+        match request.procedure_id {
+            CONFIG_LIST_PROCEDURE => {
+                Response::ok(format!("{{keys: \"{}get\"}}", field(&request.payload)))
+            }
+            CONFIG_GET_PROCEDURE => Response::ok("{value: \"error=unavailable\"}"),
+            _ => Response::unknown_procedure(request.procedure_id),
+        }
+    })
+}
+
+#[tokio::test]
+async fn test_standard_handlers_poll_rules_from_configuration() {
+    std::env::set_var("FAULTS_CONFIG_ADDR", "mem:faults-configuration");
+    std::env::set_var("FAULTS_POLL_MS", "50");
+    let addr = "mem:faults-configuration".to_string();
+    tokio::spawn(async move { server::start_server_with_state(&addr, config, ()).await });
+    let description = ServiceDescription::new("polled", "1").procedure(
+        1,
+        "get",
+        reflection::Schema::none(),
+        reflection::Schema::none(),
+    );
+    start_server(
+        "mem:faults-polled",
+        Handlers::standard().with(Reflection::new(description)),
+    );
+    sleep(Duration::from_millis(200)).await;
+
+    let err = call("mem:faults-polled", 1).await.unwrap_err();
+    assert_eq!(err.status(), Status::Unavailable);
+    assert!(call("mem:faults-polled", 2).await.is_ok());
+}
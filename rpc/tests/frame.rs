@@ -0,0 +1,186 @@
+use rpc::{client, frame, server, Request, Response, Status};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{sleep, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_frame_round_trip_across_partial_writes() {
+    let (mut writer, mut reader) = tokio::io::duplex(64);
+    let body = "x".repeat(10_000);
+
+    let expected = body.clone();
+    let read = tokio::spawn(async move { frame::read_frame(&mut reader).await });
+    frame::write_frame(&mut writer, body.as_bytes())
+        .await
+        .unwrap();
+    drop(writer);
+
+    let received = read.await.unwrap().unwrap().unwrap();
+    assert_eq!(received, expected.into_bytes());
+}
+
+#[tokio::test]
+async fn test_clean_close_and_truncated_frame() {
+    let (writer, mut reader) = tokio::io::duplex(64);
+    drop(writer);
+    assert!(frame::read_frame(&mut reader).await.unwrap().is_none());
+
+    let (mut writer, mut reader) = tokio::io::duplex(64);
+    writer.write_all(&[0, 0, 0, 10, b'a', b'b']).await.unwrap();
+    drop(writer);
+    assert!(frame::read_frame(&mut reader).await.is_err());
+}
+
+#[tokio::test]
+async fn test_oversized_frame_is_rejected() {
+    let (mut writer, mut reader) = tokio::io::duplex(64);
+    writer.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+    let err = frame::read_frame(&mut reader).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn test_large_payload_through_server() {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server(&server_addr, |request: Request| {
+            Response::ok(request.payload)
+        })
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let payload = "{entries: \"".to_string() + &"k=v;".repeat(5_000) + "\"}";
+    let response = client::send_request(&addr, Request::new(1, payload.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, payload);
+}
+
+#[tokio::test]
+async fn test_error_status_reaches_client() {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server(&server_addr, |request: Request| {
+            match request.procedure_id {
+                1 => Response::ok(request.payload),
+                _ => Response::unknown_procedure(request.procedure_id),
+            }
+        })
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    // A stored value that happens to look like an error is still a success.
+    let response = client::send_request(&addr, Request::new(1, "ERROR: x".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status, Status::Ok);
+    assert_eq!(response.payload, "ERROR: x");
+
+    let err = client::send_request(&addr, Request::new(9, String::new()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Status::UnknownProcedure);
+    assert!(!err.is_retryable());
+}
+
+fn start_legacy_test_server() -> String {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server(&server_addr, |request: Request| {
+            let status = match request.procedure_id {
+                1 => Status::UnknownProcedure,
+                2 => Status::DeadlineExceeded,
+                3 => Status::Unavailable,
+                4 => Status::Overloaded,
+                5 => Status::InvalidArgument,
+                6 => Status::Unauthenticated,
+                7 => Status::Internal,
+                _ => return Response::ok(format!("{}={}", request.procedure_id, request.payload)),
+            };
+            Response::error(status, "no")
+        })
+        .await
+    });
+    addr
+}
+
+// Sends each line as a client from before framing would and reads the reply.
+async fn legacy_exchange(addr: &str, lines: &[&str]) -> Vec<String> {
+    let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut replies = Vec::new();
+    for line in lines {
+        socket.write_all(line.as_bytes()).await.unwrap();
+        let mut buffer = vec![0u8; 1024];
+        let n = socket.read(&mut buffer).await.unwrap();
+        replies.push(String::from_utf8_lossy(&buffer[..n]).to_string());
+    }
+    replies
+}
+
+#[tokio::test]
+async fn test_legacy_client_is_answered_in_its_format() {
+    let addr = start_legacy_test_server();
+    sleep(Duration::from_millis(100)).await;
+
+    let replies = legacy_exchange(&addr, &["10:payload\n", "12: {a: 1}\n"]).await;
+    assert_eq!(replies, vec!["10=payload", "12={a: 1}"]);
+
+    // Framed clients on other connections are unaffected.
+    let response = client::send_request(&addr, Request::new(13, "framed"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "13=framed");
+}
+
+#[tokio::test]
+async fn test_legacy_client_gets_the_old_error_strings() {
+    let addr = start_legacy_test_server();
+    sleep(Duration::from_millis(100)).await;
+
+    let cases = [
+        ("1:x\n", "Unknown procedure"),
+        ("2:x\n", "Operation timed out"),
+        ("3:x\n", "Service not available"),
+        ("4:x\n", "Service not available"),
+        ("5:x\n", "ERROR: no"),
+        ("6:x\n", "ERROR: no"),
+        ("7:x\n", "ERROR: no"),
+    ];
+    for (line, expected) in cases.iter() {
+        assert_eq!(
+            legacy_exchange(&addr, &[line]).await,
+            vec![*expected],
+            "{}",
+            line
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_legacy_ids_with_many_digits() {
+    let addr = start_legacy_test_server();
+    sleep(Duration::from_millis(100)).await;
+
+    let replies = legacy_exchange(&addr, &["1000:x\n", "2147483647:y\n"]).await;
+    assert_eq!(replies, vec!["1000=x", "2147483647=y"]);
+}
+
+#[test]
+fn test_header_values_cannot_rewrite_other_fields() {
+    let mut request = Request::new(1, "payload");
+    request.token = Some("t;procedure=9;deadline=1\nid=5%41=".to_string());
+    let decoded = frame::decode_request(&frame::encode_request(&request)).unwrap();
+    assert_eq!(decoded.token, request.token);
+    assert_eq!(decoded.procedure_id, 1);
+    assert_eq!(decoded.deadline, None);
+    assert_eq!(decoded.payload, "payload");
+}
@@ -0,0 +1,164 @@
+use rpc::hedge::{HedgeConfig, HedgeStats, Hedger};
+use rpc::metrics::Metrics;
+use rpc::{server, Client, ClientConfig, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use tokio::time::{sleep, Duration, Instant};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn test_hedger(initial_delay: Duration) -> (Hedger, Client) {
+    let client = Client::new(ClientConfig {
+        max_connections_per_address: 2,
+        max_in_flight_per_connection: 4,
+        idle_timeout: Duration::from_secs(60),
+        failure_threshold: 3,
+    });
+    let hedger = Hedger::new(
+        client.clone(),
+        HedgeConfig {
+            percentile: 90.0,
+            initial_delay,
+            window: 10,
+            min_samples: 5,
+        },
+    );
+    (hedger, client)
+}
+
+// Answers with its own name after `delay`.
+async fn start_replica(name: &'static str, delay: Duration) -> String {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            move |_request, _state: ()| {
+                Box::pin(async move {
+                    sleep(delay).await;
+                    Response::ok(name)
+                }) as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            (),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    addr
+}
+
+#[tokio::test]
+async fn test_delay_follows_the_latency_percentile() {
+    let fast = start_replica("fast", Duration::ZERO).await;
+    let (hedger, _) = test_hedger(Duration::from_millis(250));
+    let addrs = vec![fast];
+    assert_eq!(hedger.delay(1), Duration::from_millis(250));
+
+    for _ in 0..5 {
+        hedger.call(&addrs, Request::new(1, "")).await.unwrap();
+    }
+    assert!(hedger.delay(1) < Duration::from_millis(100));
+    // Other procedures keep the initial delay until they have their own samples.
+    assert_eq!(hedger.delay(2), Duration::from_millis(250));
+}
+
+#[tokio::test]
+async fn test_slow_replica_is_hedged_and_the_loser_cancelled() {
+    let slow = start_replica("slow", Duration::from_secs(2)).await;
+    let fast = start_replica("fast", Duration::ZERO).await;
+    let (hedger, client) = test_hedger(Duration::from_millis(20));
+    let addrs = vec![slow.clone(), fast.clone()];
+
+    // The first copy goes to a random replica, so keep going until one is hedged.
+    for _ in 0..20 {
+        let started = Instant::now();
+        let response = hedger.call(&addrs, Request::new(1, "")).await.unwrap();
+        assert_eq!(response.payload, "fast");
+        assert!(started.elapsed() < Duration::from_millis(500));
+        if hedger.stats().sent > 0 {
+            break;
+        }
+    }
+
+    assert_eq!(hedger.stats(), HedgeStats { sent: 1, won: 1 });
+    assert_eq!(client.in_flight(&slow), 0);
+
+    // Other tests hedge too, so only check this one's are among them.
+    let batch = Metrics::global().take_batch();
+    let reported = |metric: &str| {
+        batch
+            .iter()
+            .find(|(name, _)| name == metric)
+            .map(|(_, value)| *value)
+    };
+    assert!(reported("hedge.sent") >= Some(1));
+    assert!(reported("hedge.won") >= Some(1));
+}
+
+#[tokio::test]
+async fn test_fast_replica_is_not_hedged() {
+    let fast = start_replica("fast", Duration::ZERO).await;
+    let (hedger, _) = test_hedger(Duration::from_millis(500));
+
+    let response = hedger
+        .call(&[fast.clone(), fast], Request::new(1, ""))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "fast");
+    assert_eq!(hedger.stats(), HedgeStats::default());
+}
+
+#[tokio::test]
+async fn test_preferred_replica_goes_first() {
+    let slow = start_replica("slow", Duration::from_millis(200)).await;
+    let fast = start_replica("fast", Duration::ZERO).await;
+    let others = vec![fast];
+    let (hedger, _) = test_hedger(Duration::from_millis(500));
+
+    // Slower than the others, but it answers before the hedge delay.
+    for _ in 0..3 {
+        let response = hedger
+            .call_preferring(&slow, &others, Request::new(1, ""))
+            .await
+            .unwrap();
+        assert_eq!(response.payload, "slow");
+    }
+    assert_eq!(hedger.stats(), HedgeStats::default());
+
+    let (hedger, _) = test_hedger(Duration::from_millis(20));
+    let response = hedger
+        .call_preferring(&slow, &others, Request::new(1, ""))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "fast");
+    assert_eq!(hedger.stats(), HedgeStats { sent: 1, won: 1 });
+}
+
+#[tokio::test]
+async fn test_failed_replica_is_hedged_without_waiting() {
+    let dead = free_address();
+    let fast = start_replica("fast", Duration::ZERO).await;
+    let (hedger, _) = test_hedger(Duration::from_secs(5));
+
+    for _ in 0..5 {
+        let started = Instant::now();
+        let response = hedger
+            .call(&[dead.clone(), fast.clone()], Request::new(1, ""))
+            .await
+            .unwrap();
+        assert_eq!(response.payload, "fast");
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+    let stats = hedger.stats();
+    assert_eq!(stats.sent, stats.won);
+}
+
+#[tokio::test]
+async fn test_no_addresses_is_unavailable() {
+    let (hedger, _) = test_hedger(Duration::from_millis(20));
+    let err = hedger.call(&[], Request::new(1, "")).await.unwrap_err();
+    assert_eq!(err.status(), rpc::Status::Unavailable);
+}
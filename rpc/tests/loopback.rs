@@ -0,0 +1,147 @@
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::{self, Reflection, ServiceDescription};
+use rpc::transport::Listener;
+use rpc::{client, server, Client, Handlers, Request, Response, Shutdown, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct EchoArgs {
+    pub text: String,
+}
+
+#[rpc::service]
+pub trait Echo {
+    async fn echo(&self, args: EchoArgs) -> EchoArgs;
+}
+
+struct EchoService;
+
+impl Echo for EchoService {
+    async fn echo(&self, args: EchoArgs) -> EchoArgs {
+        args
+    }
+}
+
+// Nothing is bound, so these start instantly and can run side by side
+// without picking ports.
+fn start_echo(addr: &str) {
+    let addr = addr.to_string();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &addr,
+            |request, service: Arc<EchoService>| {
+                Box::pin(async move { service.dispatch(request).await })
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(EchoService),
+        )
+        .await
+    });
+}
+
+// Forwards each request's payload to the address held in its state.
+fn start_forwarder(addr: &str, downstream: &str, shutdown: Shutdown) {
+    let addr = addr.to_string();
+    let downstream = Arc::new(Mutex::new(downstream.to_string()));
+    tokio::spawn(async move {
+        server::start_server_with_shutdown(
+            &addr,
+            Handlers::standard().with(Reflection::new(ServiceDescription::new("forwarder", "1"))),
+            |request: Request, downstream: Arc<Mutex<String>>| {
+                Box::pin(async move {
+                    let downstream = downstream.lock().await.clone();
+                    let request = Request::new(ECHO_PROCEDURE, request.payload);
+                    match Client::global().call(&downstream, request).await {
+                        Ok(response) => response,
+                        Err(e) => e.into_response(),
+                    }
+                }) as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            downstream,
+            shutdown,
+        )
+        .await
+    });
+}
+
+#[tokio::test]
+async fn test_services_call_each_other_in_memory() {
+    start_echo("mem:loopback-echo");
+    start_forwarder(
+        "mem:loopback-forwarder",
+        "mem:loopback-echo",
+        Shutdown::new(),
+    );
+    tokio::task::yield_now().await;
+
+    let echo = EchoClient::new("mem:loopback-echo");
+    let result = echo
+        .echo(EchoArgs {
+            text: "hi".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(result.text, "hi");
+
+    let args = EchoArgs {
+        text: "through".to_string(),
+    };
+    let response =
+        client::send_request("mem:loopback-forwarder", Request::new(1, args.serialize()))
+            .await
+            .unwrap();
+    assert_eq!(
+        EchoArgs::deserialize(&response.payload).unwrap().text,
+        "through"
+    );
+
+    let description = reflection::describe("mem:loopback-forwarder")
+        .await
+        .unwrap();
+    assert_eq!(description.name, "forwarder");
+}
+
+#[tokio::test]
+async fn test_frames_larger_than_the_buffer() {
+    start_echo("mem:loopback-large");
+    tokio::task::yield_now().await;
+
+    let text = "x".repeat(1024 * 1024);
+    let echo = EchoClient::new("mem:loopback-large");
+    let result = echo.echo(EchoArgs { text: text.clone() }).await.unwrap();
+    assert_eq!(result.text, text);
+}
+
+#[tokio::test]
+async fn test_unbound_address_is_refused() {
+    let err = Client::global()
+        .call("mem:loopback-nobody", Request::new(1, ""))
+        .await
+        .unwrap_err();
+    assert!(err.is_connect_failure(), "{}", err);
+    assert_eq!(err.status(), Status::Unavailable);
+}
+
+#[tokio::test]
+async fn test_address_is_freed_on_shutdown() {
+    let first = Listener::bind("mem:loopback-reuse").await.unwrap();
+    assert!(Listener::bind("mem:loopback-reuse").await.is_err());
+    drop(first);
+
+    let shutdown = Shutdown::new();
+    start_forwarder(
+        "mem:loopback-reuse",
+        "mem:loopback-nobody",
+        shutdown.clone(),
+    );
+    tokio::task::yield_now().await;
+    assert!(Listener::bind("mem:loopback-reuse").await.is_err());
+
+    shutdown.trigger();
+    sleep(Duration::from_millis(50)).await;
+    assert!(Listener::bind("mem:loopback-reuse").await.is_ok());
+}
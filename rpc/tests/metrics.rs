@@ -0,0 +1,139 @@
+use rpc::metrics::{self, Metrics};
+use rpc::{client, deadline, server, Handlers, Request, Response, Status};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use tokio::time::{sleep, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+// Procedure 1 succeeds, 2 fails, and 3 sleeps for the payload's milliseconds.
+async fn handle(request: Request, _state: ()) -> Response {
+    match request.procedure_id {
+        1 => Response::ok("OK"),
+        2 => Response::error(Status::InvalidArgument, "bad"),
+        _ => {
+            let delay: u64 = request.payload.parse().unwrap_or(0);
+            sleep(Duration::from_millis(delay)).await;
+            Response::ok("OK")
+        }
+    }
+}
+
+async fn start_server(metrics: Metrics) -> String {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_handlers(
+            &server_addr,
+            Handlers::new().with(metrics),
+            |request, state| {
+                Box::pin(handle(request, state)) as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            (),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    addr
+}
+
+#[tokio::test]
+async fn test_requests_errors_and_latency_are_recorded() {
+    let metrics = Metrics::new();
+    let addr = start_server(metrics.clone()).await;
+
+    for _ in 0..3 {
+        client::send_request(&addr, Request::new(1, ""))
+            .await
+            .unwrap();
+    }
+    let _ = client::send_request(&addr, Request::new(2, "")).await;
+    client::send_request(&addr, Request::new(3, "30"))
+        .await
+        .unwrap();
+
+    let snapshot = metrics.snapshot();
+    let ok = &snapshot[&1];
+    assert_eq!(ok.requests, 3);
+    assert!(ok.errors.is_empty());
+    assert_eq!(ok.latency[0], 3);
+    assert_eq!(ok.percentile_ms(0.99), Some(1));
+
+    let failed = &snapshot[&2];
+    assert_eq!(failed.requests, 1);
+    assert_eq!(failed.errors.get("invalid_argument"), Some(&1));
+
+    // 30ms lands in the (25, 50] bucket.
+    let slow = &snapshot[&3];
+    assert_eq!(slow.latency.iter().sum::<u64>(), 1);
+    assert_eq!(slow.percentile_ms(0.5), Some(50));
+}
+
+#[tokio::test]
+async fn test_in_flight_and_cut_off_requests() {
+    let metrics = Metrics::new();
+    let addr = start_server(metrics.clone()).await;
+
+    let slow_addr = addr.clone();
+    let slow =
+        tokio::spawn(async move { client::send_request(&slow_addr, Request::new(3, "200")).await });
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(metrics.snapshot()[&3].in_flight, 1);
+    slow.await.unwrap().unwrap();
+    assert_eq!(metrics.snapshot()[&3].in_flight, 0);
+
+    // A handler dropped at its deadline still counts, as a deadline error.
+    let request = Request::new(3, "500").with_deadline(deadline::now() + 50);
+    let err = client::send_request(&addr, request).await.unwrap_err();
+    assert_eq!(err.status(), Status::DeadlineExceeded);
+    sleep(Duration::from_millis(100)).await;
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot[&3].in_flight, 0);
+    assert_eq!(snapshot[&3].errors.get("deadline_exceeded"), Some(&1));
+}
+
+#[tokio::test]
+async fn test_batches_start_a_new_interval() {
+    let metrics = Metrics::new();
+    let addr = start_server(metrics.clone()).await;
+    client::send_request(&addr, Request::new(1, ""))
+        .await
+        .unwrap();
+    let _ = client::send_request(&addr, Request::new(2, "")).await;
+    metrics.report("rate_limited", 2);
+    metrics.report("rate_limited", 3);
+
+    let batch: HashMap<String, i32> = metrics.take_batch().into_iter().collect();
+    assert_eq!(batch["procedure_1.requests"], 1);
+    assert_eq!(batch["procedure_1.in_flight"], 0);
+    assert_eq!(batch["procedure_1.latency_p50_ms"], 1);
+    assert_eq!(batch["procedure_1.latency_le_1"], 1);
+    assert_eq!(batch["procedure_1.latency_le_inf"], 0);
+    assert_eq!(batch["procedure_2.errors.invalid_argument"], 1);
+    assert!(!batch.contains_key("procedure_1.errors.invalid_argument"));
+    assert_eq!(batch["rate_limited"], 5);
+
+    // Idle procedures and spent counters are left out of the next batch.
+    assert!(metrics.take_batch().is_empty());
+    assert_eq!(metrics.snapshot()[&1].requests, 0);
+}
+
+#[test]
+fn test_batch_round_trip() {
+    let batch = vec![
+        ("get.requests".to_string(), 12),
+        ("odd;name=x".to_string(), -1),
+    ];
+    let encoded = format!("{};garbage", metrics::encode_batch(&batch));
+    assert_eq!(
+        metrics::decode_batch(&encoded),
+        vec![
+            ("get.requests".to_string(), 12),
+            ("odd_name_x".to_string(), -1)
+        ]
+    );
+}
@@ -0,0 +1,151 @@
+use rpc::middleware::{AuthToken, CatchPanic, PayloadLimit, Timing};
+use rpc::{client, server, Handler, HandlerNode, Handlers, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+async fn handler(request: Request, _state: Arc<Mutex<()>>) -> Response {
+    match request.procedure_id {
+        1 => Response::ok(request.payload),
+        2 => {
+            sleep(Duration::from_millis(300)).await;
+            Response::ok(request.payload)
+        }
+        3 => panic!("boom"),
+        _ => Response::unknown_procedure(request.procedure_id),
+    }
+}
+
+async fn start(handlers: Handlers) -> String {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_handlers(
+            &server_addr,
+            handlers,
+            |request, state| {
+                Box::pin(handler(request, state)) as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(Mutex::new(())),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    addr
+}
+
+// Appends its tag to every payload on the way out.
+struct Tag(&'static str);
+
+impl Handler for Tag {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        node: &'a HandlerNode,
+    ) -> Pin<Box<dyn Future<Output = Response> + Send + 'a>> {
+        Box::pin(async move {
+            let mut response = node.call_next(request).await;
+            response.payload.push_str(self.0);
+            response
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_handlers_run_in_order_added() {
+    let addr = start(Handlers::new().with(Tag("-outer")).with(Tag("-inner"))).await;
+    let response = client::send_request(&addr, Request::new(1, "x"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "x-inner-outer");
+}
+
+#[tokio::test]
+async fn test_requests_are_not_serialized_by_the_chain() {
+    let addr = start(Handlers::standard()).await;
+    let started = Instant::now();
+    let calls: Vec<_> = (0..5)
+        .map(|i| {
+            let addr = addr.clone();
+            tokio::spawn(async move {
+                client::send_request(&addr, Request::new(2, i.to_string())).await
+            })
+        })
+        .collect();
+    for call in calls {
+        call.await.unwrap().unwrap();
+    }
+    assert!(started.elapsed() < Duration::from_millis(1000));
+}
+
+#[tokio::test]
+async fn test_panic_becomes_internal_error() {
+    let addr = start(Handlers::new().with(CatchPanic)).await;
+    let err = client::send_request(&addr, Request::new(3, ""))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Status::Internal);
+    assert!(err.to_string().contains("boom"));
+
+    // The server keeps serving after the panic.
+    let response = client::send_request(&addr, Request::new(1, "ok"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "ok");
+}
+
+#[tokio::test]
+async fn test_auth_token_is_checked() {
+    let auth = AuthToken::with_tokens(vec!["secret".to_string()]).allow(2);
+    let addr = start(Handlers::new().with(auth)).await;
+
+    let err = client::send_request(&addr, Request::new(1, "x"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Status::Unauthenticated);
+    let err = client::send_request(&addr, Request::new(1, "x").with_token("wrong"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Status::Unauthenticated);
+
+    let response = client::send_request(&addr, Request::new(1, "x").with_token("secret"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "x");
+    let response = client::send_request(&addr, Request::new(2, "open"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "open");
+}
+
+#[tokio::test]
+async fn test_payload_limit_and_timing() {
+    let timing = Timing::new();
+    let addr = start(
+        Handlers::new()
+            .with(timing.clone())
+            .with(PayloadLimit::new(8)),
+    )
+    .await;
+
+    let err = client::send_request(&addr, Request::new(1, "x".repeat(9)))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Status::InvalidArgument);
+    client::send_request(&addr, Request::new(1, "x".repeat(8)))
+        .await
+        .unwrap();
+
+    let stats = timing.stats();
+    let echo = stats.get(&1).unwrap();
+    assert_eq!(echo.count, 2);
+    assert_eq!(echo.errors, 1);
+    assert!(echo.max >= echo.mean());
+}
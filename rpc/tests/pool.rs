@@ -0,0 +1,96 @@
+use rpc::{server, Client, ClientConfig, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn test_client(idle_timeout: Duration) -> Client {
+    Client::new(ClientConfig {
+        max_connections_per_address: 2,
+        max_in_flight_per_connection: 4,
+        idle_timeout,
+        failure_threshold: 2,
+    })
+}
+
+async fn slow_echo(request: Request, _state: Arc<Mutex<()>>) -> Response {
+    let delay: u64 = request.payload.parse().unwrap_or(0);
+    sleep(Duration::from_millis(delay)).await;
+    Response::ok(request.payload)
+}
+
+async fn start_echo_server() -> String {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            |request, state| {
+                Box::pin(slow_echo(request, state))
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(Mutex::new(())),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    addr
+}
+
+#[tokio::test]
+async fn test_sequential_calls_reuse_one_connection() {
+    let addr = start_echo_server().await;
+    let client = test_client(Duration::from_secs(60));
+
+    for _ in 0..10 {
+        let response = client.call(&addr, Request::new(1, "0")).await.unwrap();
+        assert_eq!(response.payload, "0");
+    }
+    assert_eq!(client.connection_count(&addr), 1);
+}
+
+#[tokio::test]
+async fn test_busy_address_grows_to_connection_limit() {
+    let addr = start_echo_server().await;
+    let client = test_client(Duration::from_secs(60));
+
+    let calls: Vec<_> = (0..20)
+        .map(|_| {
+            let client = client.clone();
+            let addr = addr.clone();
+            tokio::spawn(async move { client.call(&addr, Request::new(1, "200")).await })
+        })
+        .collect();
+    for call in calls {
+        assert_eq!(call.await.unwrap().unwrap().payload, "200");
+    }
+    assert_eq!(client.connection_count(&addr), 2);
+    assert_eq!(client.in_flight(&addr), 0);
+}
+
+#[tokio::test]
+async fn test_idle_connections_are_reaped() {
+    let addr = start_echo_server().await;
+    let client = test_client(Duration::from_millis(200));
+
+    client.call(&addr, Request::new(1, "0")).await.unwrap();
+    assert_eq!(client.connection_count(&addr), 1);
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(client.connection_count(&addr), 0);
+}
+
+#[tokio::test]
+async fn test_unreachable_address_fails_without_pooling() {
+    let addr = free_address();
+    let client = test_client(Duration::from_secs(60));
+
+    let err = client.call(&addr, Request::new(1, "0")).await.unwrap_err();
+    assert!(err.is_retryable());
+    assert_eq!(client.connection_count(&addr), 0);
+}
@@ -0,0 +1,182 @@
+use rpc::protocol::{self, Protocol, HANDSHAKE_PROCEDURE, LEGACY_VERSION, PROTOCOL_VERSION};
+use rpc::transport::{self, Listener};
+use rpc::{frame, server, Client, Error, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn echo(request: Request, _state: ()) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+    Box::pin(async move { Response::ok(request.payload) })
+}
+
+async fn start_echo(addr: &str) {
+    let addr = addr.to_string();
+    tokio::spawn(async move { server::start_server_with_state(&addr, echo, ()).await });
+    tokio::task::yield_now().await;
+}
+
+// A server from before the handshake: it answers every request it can
+// decode, the handshake included, like an unknown procedure or an echo.
+async fn start_legacy(addr: &str) {
+    let mut listener = Listener::bind(addr).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok(mut socket) = listener.accept().await {
+            tokio::spawn(async move {
+                while let Ok(Some(body)) = frame::read_frame(&mut socket).await {
+                    let request = frame::decode_request(&body).unwrap();
+                    let mut response = match request.procedure_id {
+                        1 => Response::ok(request.payload),
+                        _ => Response::unknown_procedure(request.procedure_id),
+                    };
+                    response.id = request.id;
+                    frame::write_frame(&mut socket, &frame::encode_response(&response))
+                        .await
+                        .unwrap();
+                }
+            });
+        }
+    });
+}
+
+// A server from before framing, as it was: one read of up to 1024 bytes
+// taken as `procedure_id:payload`, answered with the bare payload. Anything
+// else panics the connection's task, which drops the connection.
+async fn start_unframed() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                loop {
+                    let mut buffer = vec![0u8; 1024];
+                    let n = match socket.read(&mut buffer).await {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(_) => break,
+                    };
+                    let data = String::from_utf8_lossy(&buffer[..n]);
+
+                    let parts: Vec<&str> = data.splitn(2, ':').collect();
+                    let procedure_id: i32 = parts[0].parse().unwrap();
+                    let payload = parts[1].trim().to_string();
+
+                    let response = match procedure_id {
+                        1 => payload,
+                        _ => "Unknown procedure".to_string(),
+                    };
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                }
+            });
+        }
+    });
+    addr
+}
+
+async fn exchange(addr: &str, request: Request) -> Response {
+    let mut stream = transport::connect(addr).await.unwrap();
+    frame::write_request(&mut stream, &request).await.unwrap();
+    frame::read_response(&mut stream).await.unwrap()
+}
+
+#[test]
+fn test_negotiate_keeps_what_both_sides_have() {
+    let ours = Protocol::current();
+    let theirs = Protocol::new(
+        PROTOCOL_VERSION + 1,
+        &[protocol::FRAMING, protocol::TRACING, "teleport"],
+    );
+    let agreed = ours.negotiate(&theirs);
+    assert_eq!(agreed.version, PROTOCOL_VERSION);
+    assert_eq!(
+        agreed,
+        Protocol::new(PROTOCOL_VERSION, &[protocol::FRAMING, protocol::TRACING])
+    );
+    assert!(agreed.supports(protocol::TRACING) && !agreed.supports(protocol::AUTH));
+
+    assert_eq!(Protocol::decode(&ours.encode()), Some(ours));
+    assert_eq!(
+        Protocol::decode("capabilities=;version=1;later=field"),
+        Some(Protocol::new(LEGACY_VERSION, &[]))
+    );
+    assert_eq!(Protocol::decode("capabilities=framing"), None);
+}
+
+#[tokio::test]
+async fn test_current_client_and_server_agree_on_everything() {
+    start_echo("mem:protocol-current").await;
+    let agreed = Client::global()
+        .protocol("mem:protocol-current")
+        .await
+        .unwrap();
+    assert_eq!(agreed, Protocol::current());
+}
+
+#[tokio::test]
+async fn test_server_answers_a_newer_client_with_what_it_speaks() {
+    start_echo("mem:protocol-newer").await;
+    let hello = Protocol::new(
+        PROTOCOL_VERSION + 1,
+        &[protocol::FRAMING, protocol::DEADLINES, "teleport"],
+    );
+    let response = exchange(
+        "mem:protocol-newer",
+        Request::new(HANDSHAKE_PROCEDURE, hello.encode()),
+    )
+    .await;
+    assert!(response.is_ok(), "{}", response.payload);
+    assert_eq!(
+        Protocol::decode(&response.payload),
+        Some(Protocol::new(
+            PROTOCOL_VERSION,
+            &[protocol::FRAMING, protocol::DEADLINES]
+        ))
+    );
+}
+
+#[tokio::test]
+async fn test_client_falls_back_for_a_legacy_server() {
+    start_legacy("mem:protocol-legacy-server").await;
+    let agreed = Client::global()
+        .protocol("mem:protocol-legacy-server")
+        .await
+        .unwrap();
+    assert_eq!(agreed, Protocol::legacy());
+
+    let response = Client::global()
+        .call("mem:protocol-legacy-server", Request::new(1, "{old}"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "{old}");
+}
+
+#[tokio::test]
+async fn test_server_serves_a_legacy_client() {
+    start_echo("mem:protocol-legacy-client").await;
+    // Straight to the first request, no handshake.
+    let mut request = Request::new(1, "{old}");
+    request.id = 7;
+    let response = exchange("mem:protocol-legacy-client", request).await;
+    assert_eq!(response.id, 7);
+    assert_eq!(response.payload, "{old}");
+}
+
+#[tokio::test]
+async fn test_client_falls_back_for_a_server_from_before_framing() {
+    let addr = start_unframed().await;
+    let agreed = Client::global().protocol(&addr).await.unwrap();
+    assert_eq!(agreed, Protocol::unframed());
+
+    for payload in ["{first}", "{second}"] {
+        let response = Client::global()
+            .call(&addr, Request::new(1, payload))
+            .await
+            .unwrap();
+        assert_eq!(response.payload, payload);
+    }
+    match Client::global().call(&addr, Request::new(9, "{}")).await {
+        Err(Error::Status(Status::UnknownProcedure, _)) => {}
+        other => panic!("expected an unknown procedure, got {:?}", other),
+    }
+}
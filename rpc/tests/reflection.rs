@@ -0,0 +1,93 @@
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::{self, Reflection, Schema, ServiceDescription};
+use rpc::{schema, server, Handlers, Request, Response, Shutdown};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use tokio::time::{sleep, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct PutArgs {
+    pub key: String,
+    pub version: i32,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct PeersResult {
+    pub peer_count: i32,
+    pub addresses: String,
+}
+
+fn description() -> ServiceDescription {
+    ServiceDescription::new("kv", "1.2.3")
+        .procedure(1, "put", schema!(PutArgs), Schema::none())
+        .procedure(7, "get_peers", Schema::none(), schema!(PeersResult))
+}
+
+#[test]
+fn test_description_round_trips_through_its_result() {
+    let result = description().to_result().serialize();
+    let result = reflection::ReflectionResult::deserialize(&result).unwrap();
+    let decoded = ServiceDescription::from_result(result).unwrap();
+
+    assert_eq!(decoded, description());
+    assert_eq!(
+        decoded.find("put").unwrap().to_string(),
+        "1 put PutArgs(key: String, version: i32, tags: Vec<String>) -> ()"
+    );
+    assert_eq!(decoded.find("7").unwrap().name, "get_peers");
+    assert!(decoded.find("8").is_none());
+}
+
+#[test]
+fn test_schema_encodes_command_line_values() {
+    let mut values = HashMap::new();
+    values.insert("key".to_string(), "a:b,\"c\" d".to_string());
+    values.insert("version".to_string(), "3".to_string());
+    values.insert("tags".to_string(), "x,y".to_string());
+
+    let payload = schema!(PutArgs).encode(&values).unwrap();
+    let args = PutArgs::deserialize(&payload).unwrap();
+    assert_eq!(args.key, "a:b,\"c\" d");
+    assert_eq!(args.version, 3);
+    assert_eq!(args.tags, vec!["x".to_string(), "y".to_string()]);
+
+    values.insert("version".to_string(), "three".to_string());
+    assert!(schema!(PutArgs).encode(&values).is_err());
+    values.insert("nope".to_string(), "1".to_string());
+    assert!(schema!(PutArgs).encode(&values).is_err());
+}
+
+#[tokio::test]
+async fn test_server_answers_reflection() {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_shutdown(
+            &server_addr,
+            Handlers::standard().with(Reflection::new(description())),
+            |request, _state: ()| {
+                Box::pin(async move { Response::ok(format!("handled {}", request.procedure_id)) })
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            (),
+            Shutdown::new(),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(reflection::describe(&addr).await.unwrap(), description());
+
+    let response = rpc::Client::global()
+        .call(&addr, Request::new(1, ""))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "handled 1");
+}
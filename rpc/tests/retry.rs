@@ -0,0 +1,137 @@
+use rpc::breaker::{BreakerState, CircuitBreakers};
+use rpc::retry::RetryBudget;
+use rpc::{client_v1, server, Error, ProcedureId, Procedures, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::time::{sleep, Duration, Instant};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn unavailable() -> Result<Response, Error> {
+    Err(Error::Status(Status::Unavailable, "down".to_string()))
+}
+
+// Answers every request with Unavailable and counts how many it has seen.
+async fn start_unavailable_server() -> (String, Arc<AtomicUsize>) {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let server_calls = calls.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            |_request, calls: Arc<AtomicUsize>| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async { Response::error(Status::Unavailable, "overloaded") })
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            server_calls,
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    (addr, calls)
+}
+
+#[tokio::test]
+async fn test_breaker_opens_probes_and_closes() {
+    let breakers = CircuitBreakers::new(2, Duration::from_millis(50));
+    let addr = "10.0.0.1:1";
+
+    breakers.record(addr, &unavailable());
+    assert_eq!(breakers.state(addr), BreakerState::Closed);
+    breakers.record(addr, &unavailable());
+    assert_eq!(breakers.state(addr), BreakerState::Open);
+    assert!(breakers.acquire(addr).is_err());
+
+    sleep(Duration::from_millis(60)).await;
+    assert!(breakers.acquire(addr).is_ok());
+    assert_eq!(breakers.state(addr), BreakerState::HalfOpen);
+    // Only the probe goes through while half-open.
+    assert!(breakers.acquire(addr).is_err());
+
+    // A failed probe opens the circuit again straight away.
+    breakers.record(addr, &unavailable());
+    assert_eq!(breakers.state(addr), BreakerState::Open);
+
+    sleep(Duration::from_millis(60)).await;
+    assert!(breakers.acquire(addr).is_ok());
+    breakers.record(addr, &Ok(Response::ok("")));
+    assert_eq!(breakers.state(addr), BreakerState::Closed);
+    assert!(breakers.acquire(addr).is_ok());
+}
+
+#[tokio::test]
+async fn test_breaker_ignores_errors_from_a_healthy_server() {
+    let breakers = CircuitBreakers::new(1, Duration::from_secs(60));
+    let addr = "10.0.0.1:2";
+
+    let invalid = Err(Error::Status(Status::InvalidArgument, "bad".to_string()));
+    breakers.record(addr, &invalid);
+    assert_eq!(breakers.state(addr), BreakerState::Closed);
+}
+
+#[test]
+fn test_retry_budget_is_a_fraction_of_requests() {
+    let budget = RetryBudget::new(0.25, 1.0);
+
+    assert!(budget.try_withdraw());
+    assert!(!budget.try_withdraw());
+
+    for _ in 0..3 {
+        budget.deposit();
+    }
+    assert!(!budget.try_withdraw());
+    budget.deposit();
+    assert!(budget.try_withdraw());
+
+    // Quiet periods don't bank more than the reserve.
+    for _ in 0..100 {
+        budget.deposit();
+    }
+    assert!(budget.try_withdraw());
+    assert!(!budget.try_withdraw());
+}
+
+#[tokio::test]
+async fn test_only_idempotent_requests_are_retried() {
+    let (addr, calls) = start_unavailable_server().await;
+
+    const READ: ProcedureId = 1;
+    const WRITE: ProcedureId = 2;
+    let procedures = Procedures::new(&[READ]);
+    assert!(!procedures.is_idempotent(WRITE));
+
+    let request = Request::new(WRITE, "write");
+    let result = client_v1::send_request_with_procedures(&addr, request, &procedures).await;
+    assert_eq!(result.unwrap_err().status(), Status::Unavailable);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let request = Request::new(READ, "read");
+    let result = client_v1::send_request_with_procedures(&addr, request, &procedures).await;
+    assert_eq!(result.unwrap_err().status(), Status::Unavailable);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_open_circuit_fails_fast() {
+    // Nothing listens here, so every attempt is refused and can be retried.
+    let addr = free_address();
+
+    let _ = client_v1::send_request(&addr, Request::new(1, "")).await;
+    let _ = client_v1::send_request(&addr, Request::new(1, "")).await;
+    assert_eq!(CircuitBreakers::global().state(&addr), BreakerState::Open);
+
+    let started = Instant::now();
+    let err = client_v1::send_request(&addr, Request::new(1, ""))
+        .await
+        .unwrap_err();
+    assert!(started.elapsed() < Duration::from_millis(50));
+    assert_eq!(err.status(), Status::Unavailable);
+    assert!(err.to_string().contains("circuit open"));
+}
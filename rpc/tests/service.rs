@@ -0,0 +1,157 @@
+use normalization::{Deserializable, Serializable};
+use rpc::{client, reflection, server, Client, Error, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct AddArgs {
+    pub amount: i32,
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct TotalResult {
+    pub total: i32,
+}
+
+#[rpc::service]
+pub trait Counter {
+    async fn add(&self, args: AddArgs) -> TotalResult;
+    async fn reset(&self, args: AddArgs);
+    #[procedure(10)]
+    async fn checked_add(&self, args: AddArgs) -> Result<TotalResult, Error>;
+}
+
+#[derive(Default)]
+struct CounterService {
+    total: AtomicI32,
+}
+
+impl Counter for CounterService {
+    async fn add(&self, args: AddArgs) -> TotalResult {
+        let total = self.total.fetch_add(args.amount, Ordering::SeqCst) + args.amount;
+        TotalResult { total }
+    }
+
+    async fn reset(&self, args: AddArgs) {
+        self.total.store(args.amount, Ordering::SeqCst);
+    }
+
+    async fn checked_add(&self, args: AddArgs) -> Result<TotalResult, Error> {
+        if args.amount < 0 {
+            return Err(Error::Status(
+                Status::InvalidArgument,
+                "negative amount".to_string(),
+            ));
+        }
+        Ok(self.add(args).await)
+    }
+}
+
+async fn start_counter() -> CounterClient {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            |request, service: Arc<CounterService>| {
+                Box::pin(async move { service.dispatch(request).await })
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(CounterService::default()),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    CounterClient::new(addr)
+}
+
+#[test]
+fn test_procedure_ids_follow_declaration_order() {
+    assert_eq!(ADD_PROCEDURE, 1);
+    assert_eq!(RESET_PROCEDURE, 2);
+    assert_eq!(CHECKED_ADD_PROCEDURE, 10);
+}
+
+#[tokio::test]
+async fn test_typed_client_calls_through_the_dispatcher() {
+    let client = start_counter().await;
+
+    client.reset(AddArgs { amount: 5 }).await.unwrap();
+    let result = client.add(AddArgs { amount: 3 }).await.unwrap();
+    assert_eq!(result.total, 8);
+    let result = client.checked_add(AddArgs { amount: 2 }).await.unwrap();
+    assert_eq!(result.total, 10);
+}
+
+#[tokio::test]
+async fn test_generic_calls_serialize_through_the_traits() {
+    let client = start_counter().await;
+
+    let result: TotalResult = client::call(client.address(), ADD_PROCEDURE, &AddArgs { amount: 4 })
+        .await
+        .unwrap();
+    assert_eq!(result.total, 4);
+
+    let err = Client::global()
+        .call_typed::<_, Vec<i32>>(client.address(), ADD_PROCEDURE, &AddArgs { amount: 1 })
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Status::Internal);
+    assert_eq!(client.add(AddArgs { amount: 0 }).await.unwrap().total, 5);
+}
+
+#[tokio::test]
+async fn test_procedure_errors_become_statuses() {
+    let client = start_counter().await;
+
+    let err = client
+        .checked_add(AddArgs { amount: -1 })
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Status::InvalidArgument);
+    assert_eq!(err.to_string(), "Invalid argument: negative amount");
+}
+
+#[tokio::test]
+async fn test_dispatch_rejects_bad_requests() {
+    let service = CounterService::default();
+
+    let response = service.dispatch(Request::new(99, "{}")).await;
+    assert_eq!(response.status, Status::UnknownProcedure);
+
+    let response = service
+        .dispatch(Request::new(ADD_PROCEDURE, "garbage"))
+        .await;
+    assert_eq!(response.status, Status::InvalidArgument);
+    assert_eq!(service.total.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_dispatch_answers_reflection() {
+    let client = start_counter().await;
+    let description = reflection::describe(client.address()).await.unwrap();
+
+    assert_eq!(description.name, "counter");
+    assert_eq!(description.version, env!("CARGO_PKG_VERSION"));
+    let procedures: Vec<String> = description
+        .procedures
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    assert_eq!(
+        procedures,
+        vec![
+            "1 add AddArgs(amount: i32) -> TotalResult(total: i32)",
+            "2 reset AddArgs(amount: i32) -> ()",
+            "10 checked_add AddArgs(amount: i32) -> TotalResult(total: i32)",
+        ]
+    );
+}
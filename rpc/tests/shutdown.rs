@@ -0,0 +1,77 @@
+use rpc::{client, server, Handlers, Request, Response, Shutdown};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+async fn slow_echo(request: Request, _state: Arc<Mutex<()>>) -> Response {
+    let delay: u64 = request.payload.parse().unwrap_or(0);
+    sleep(Duration::from_millis(delay)).await;
+    Response::ok(request.payload)
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_in_flight_requests() {
+    let addr = free_address();
+    let shutdown = Shutdown::new();
+    let hook_ran = Arc::new(AtomicBool::new(false));
+    let hook_flag = hook_ran.clone();
+    shutdown.on_shutdown(async move {
+        hook_flag.store(true, Ordering::SeqCst);
+    });
+
+    let server_addr = addr.clone();
+    let server_shutdown = shutdown.clone();
+    let server = tokio::spawn(async move {
+        server::start_server_with_shutdown(
+            &server_addr,
+            Handlers::new(),
+            |request, state| {
+                Box::pin(slow_echo(request, state))
+                    as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(Mutex::new(())),
+            server_shutdown,
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let call_addr = addr.clone();
+    let in_flight =
+        tokio::spawn(async move { client::send_request(&call_addr, Request::new(1, "500")).await });
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(shutdown.in_flight(), 1);
+
+    shutdown.trigger();
+    sleep(Duration::from_millis(50)).await;
+    assert!(hook_ran.load(Ordering::SeqCst));
+    assert!(tokio::net::TcpStream::connect(&addr).await.is_err());
+
+    let response = in_flight.await.unwrap().unwrap();
+    assert_eq!(response.payload, "500");
+    timeout(Duration::from_secs(2), server)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(shutdown.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn test_triggered_resolves_for_late_waiters() {
+    let shutdown = Shutdown::new();
+    assert!(!shutdown.is_triggered());
+    shutdown.trigger();
+    timeout(Duration::from_millis(100), shutdown.triggered())
+        .await
+        .unwrap();
+    assert!(shutdown.is_triggered());
+}
@@ -0,0 +1,67 @@
+use rpc::{client, server, Request, Response};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use tokio::time::{sleep, Duration, Instant};
+
+const GET: i32 = 1;
+const SET: i32 = 2;
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+// Reads copy the value out and then do slow work without holding the lock.
+async fn handler(request: Request, state: Arc<RwLock<HashMap<String, String>>>) -> Response {
+    match request.procedure_id {
+        GET => {
+            let value = state.read().unwrap().get(&request.payload).cloned();
+            sleep(Duration::from_millis(300)).await;
+            Response::ok(value.unwrap_or_default())
+        }
+        SET => {
+            let (key, value) = request.payload.split_once('=').unwrap();
+            state
+                .write()
+                .unwrap()
+                .insert(key.to_string(), value.to_string());
+            Response::ok("OK")
+        }
+        _ => Response::unknown_procedure(request.procedure_id),
+    }
+}
+
+#[tokio::test]
+async fn test_server_accepts_any_shared_state() {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_state(
+            &server_addr,
+            |request, state| {
+                Box::pin(handler(request, state)) as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            Arc::new(RwLock::new(HashMap::new())),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    client::send_request(&addr, Request::new(SET, "a=1"))
+        .await
+        .unwrap();
+
+    let started = Instant::now();
+    let reads: Vec<_> = (0..10)
+        .map(|_| {
+            let addr = addr.clone();
+            tokio::spawn(async move { client::send_request(&addr, Request::new(GET, "a")).await })
+        })
+        .collect();
+    for read in reads {
+        assert_eq!(read.await.unwrap().unwrap().payload, "1");
+    }
+    assert!(started.elapsed() < Duration::from_millis(1000));
+}
@@ -0,0 +1,211 @@
+use once_cell::sync::Lazy;
+use rpc::trace::{self, ActiveSpan, RecordSpansArgs, Span, TraceContext};
+use rpc::{client, frame, server, Handlers, Request, Response, Status};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Mutex, Once};
+use tokio::time::{sleep, Duration};
+
+fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+static COLLECTED: Lazy<Mutex<Vec<Span>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static START_COLLECTOR: Once = Once::new();
+
+// Every test that records spans points the collector at this process before
+// anything is flushed, since the address is read once.
+fn start_collector() {
+    START_COLLECTOR.call_once(|| {
+        let addr = free_address();
+        std::env::set_var("TRACE_COLLECTOR_ADDR", &addr);
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(async move {
+                    server::start_server_with_state(
+                        &addr,
+                        |request: Request, _state: ()| {
+                            Box::pin(async move {
+                                let args = RecordSpansArgs::deserialize(&request.payload).unwrap();
+                                COLLECTED
+                                    .lock()
+                                    .unwrap()
+                                    .extend(trace::decode_spans(&args.spans));
+                                Response::default()
+                            })
+                                as Pin<Box<dyn Future<Output = Response> + Send>>
+                        },
+                        (),
+                    )
+                    .await
+                })
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    });
+}
+
+fn collected(trace_id: u64) -> Vec<Span> {
+    COLLECTED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|span| span.trace_id == trace_id)
+        .cloned()
+        .collect()
+}
+
+// Reports the trace context the request arrived with and the one its handler
+// runs in, then forwards the call to the address in the payload, if any.
+async fn forward(request: Request, _state: ()) -> Response {
+    let received = format!("{:?}/{:?}", request.trace, trace::current());
+    if request.payload.is_empty() {
+        return Response::ok(received);
+    }
+    match client::send_request(&request.payload, Request::new(2, "")).await {
+        Ok(response) => Response::ok(format!("{} {}", received, response.payload)),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn start_server() -> String {
+    let addr = free_address();
+    let server_addr = addr.clone();
+    tokio::spawn(async move {
+        server::start_server_with_handlers(
+            &server_addr,
+            Handlers::standard(),
+            |request, state| {
+                Box::pin(forward(request, state)) as Pin<Box<dyn Future<Output = Response> + Send>>
+            },
+            (),
+        )
+        .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    addr
+}
+
+#[test]
+fn test_trace_headers_round_trip() {
+    let context = TraceContext {
+        trace_id: 0xabc,
+        span_id: u64::MAX,
+    };
+    let mut request = Request::new(3, "payload");
+    request.trace = Some(context);
+    let decoded = frame::decode_request(&frame::encode_request(&request)).unwrap();
+    assert_eq!(decoded.trace, Some(context));
+
+    let decoded = frame::decode_request(&frame::encode_request(&Request::new(3, ""))).unwrap();
+    assert_eq!(decoded.trace, None);
+
+    // A bad trace header drops the trace but keeps the request.
+    let decoded = frame::decode_request(b"id=1;procedure=3;trace=zz;span=1\npayload").unwrap();
+    assert_eq!(decoded.trace, None);
+    assert_eq!(decoded.payload, "payload");
+}
+
+#[test]
+fn test_spans_round_trip_and_skip_garbage() {
+    let spans = vec![
+        Span {
+            trace_id: 1,
+            span_id: 2,
+            parent_id: None,
+            service: "frontend".to_string(),
+            name: "GET /dashboard".to_string(),
+            start: 1_000,
+            duration: 250,
+            status: "200".to_string(),
+        },
+        Span {
+            trace_id: 1,
+            span_id: 3,
+            parent_id: Some(2),
+            service: "storage".to_string(),
+            name: "get|odd;name".to_string(),
+            start: 1_010,
+            duration: 40,
+            status: "Internal error".to_string(),
+        },
+    ];
+    let encoded = format!("{};not a span", trace::encode_spans(&spans));
+    let decoded = trace::decode_spans(&encoded);
+
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[0], spans[0]);
+    assert_eq!(decoded[1].name, "get odd name");
+    assert_eq!(decoded[1].parent_id, Some(2));
+}
+
+#[tokio::test]
+async fn test_untraced_requests_stay_untraced() {
+    let addr = start_server().await;
+    let response = client::send_request(&addr, Request::new(1, ""))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "None/None");
+}
+
+#[tokio::test]
+async fn test_context_propagates_across_two_hops() {
+    start_collector();
+    let leaf = start_server().await;
+    let middle = start_server().await;
+
+    let root = ActiveSpan::root("test");
+    let context = root.context();
+    let response = trace::scope(
+        Some(context),
+        client::send_request(&middle, Request::new(1, leaf.clone())),
+    )
+    .await
+    .unwrap();
+    root.finish(Status::Ok.to_string());
+    trace::flush().await;
+
+    // The background flush may have picked some spans up first.
+    let mut spans = collected(context.trace_id);
+    for _ in 0..20 {
+        if spans.len() >= 3 {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+        spans = collected(context.trace_id);
+    }
+    assert_eq!(spans.len(), 3, "{:?}", spans);
+    let middle_span = spans
+        .iter()
+        .find(|span| span.parent_id == Some(context.span_id))
+        .unwrap();
+    let leaf_span = spans
+        .iter()
+        .find(|span| span.parent_id == Some(middle_span.span_id))
+        .unwrap();
+    assert_eq!(middle_span.name, "procedure 1");
+    assert_eq!(leaf_span.name, "procedure 2");
+    assert_eq!(leaf_span.status, "OK");
+    assert!(leaf_span.start >= middle_span.start && leaf_span.end() <= middle_span.end());
+
+    // Each hop ran inside its own span and passed that span on as the parent.
+    let middle_context = TraceContext {
+        trace_id: context.trace_id,
+        span_id: middle_span.span_id,
+    };
+    let leaf_context = TraceContext {
+        trace_id: context.trace_id,
+        span_id: leaf_span.span_id,
+    };
+    assert_eq!(
+        response.payload,
+        format!(
+            "{:?}/{:?} {:?}/{:?}",
+            Some(context),
+            Some(middle_context),
+            Some(middle_context),
+            Some(leaf_context)
+        )
+    );
+}
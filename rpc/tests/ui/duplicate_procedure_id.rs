@@ -0,0 +1,15 @@
+use normalization::{Deserializable, Serializable};
+
+#[derive(Serializable, Deserializable)]
+pub struct Args {
+    pub amount: i32,
+}
+
+#[rpc::service]
+pub trait Counter {
+    async fn add(&self, args: Args) -> Args;
+    #[procedure(1)]
+    async fn reset(&self, args: Args);
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use normalization::{Deserializable, Serializable};
+
+#[derive(Serializable, Deserializable)]
+pub struct Args {
+    pub amount: i32,
+}
+
+#[rpc::service]
+pub trait Counter {
+    #[procedure(0)]
+    async fn add(&self, args: Args) -> Args;
+}
+
+fn main() {}
@@ -0,0 +1,145 @@
+use rpc::reflection::{self, Reflection, ServiceDescription};
+use rpc::transport::{self, Listener};
+use rpc::{server, Client, Handlers, Request, Response, Shutdown};
+use std::future::Future;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Once;
+use tokio::time::{sleep, Duration};
+
+fn echo(request: Request, _state: ()) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+    Box::pin(async move { Response::ok(request.payload) })
+}
+
+// A fresh directory per test, so runs don't trip over each other's sockets.
+fn socket_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rpc-unix-{}-{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+// UNIX_SOCKET_DIR is read once, by whichever server starts first, so every
+// test sets it before starting one.
+fn local_socket_dir() -> PathBuf {
+    static SET_DIR: Once = Once::new();
+    let dir = std::env::temp_dir().join(format!("rpc-unix-{}-local", std::process::id()));
+    SET_DIR.call_once(|| std::env::set_var("UNIX_SOCKET_DIR", &dir));
+    dir
+}
+
+fn start_echo(addr: &str, shutdown: Shutdown) {
+    local_socket_dir();
+    let addr = addr.to_string();
+    tokio::spawn(async move {
+        server::start_server_with_shutdown(
+            &addr,
+            Handlers::new().with(Reflection::new(ServiceDescription::new("unix-echo", "1"))),
+            echo,
+            (),
+            shutdown,
+        )
+        .await
+    });
+}
+
+#[tokio::test]
+async fn test_call_over_a_unix_socket() {
+    let path = socket_dir("call").join("echo.sock");
+    let addr = format!("unix:{}", path.display());
+    start_echo(&addr, Shutdown::new());
+    sleep(Duration::from_millis(50)).await;
+
+    let response = Client::global()
+        .call(&addr, Request::new(1, "{hi}"))
+        .await
+        .unwrap();
+    assert_eq!(response.payload, "{hi}");
+    let description = reflection::describe(&addr).await.unwrap();
+    assert_eq!(description.name, "unix-echo");
+
+    // Only the owner and its group may connect.
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o660);
+}
+
+#[tokio::test]
+async fn test_stale_socket_is_replaced_and_removed_on_shutdown() {
+    let path = socket_dir("stale").join("echo.sock");
+    let addr = format!("unix:{}", path.display());
+
+    // A socket file left behind by a server that is gone.
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let shutdown = Shutdown::new();
+    start_echo(&addr, shutdown.clone());
+    sleep(Duration::from_millis(50)).await;
+    assert!(Client::global()
+        .call(&addr, Request::new(1, "{}"))
+        .await
+        .is_ok());
+
+    // One a server still answers on is left alone.
+    let err = Listener::bind(&addr).await.err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+
+    shutdown.trigger();
+    sleep(Duration::from_millis(50)).await;
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn test_missing_socket_is_refused() {
+    let addr = format!(
+        "unix:{}",
+        socket_dir("missing").join("nobody.sock").display()
+    );
+    let err = Client::global()
+        .call(&addr, Request::new(1, ""))
+        .await
+        .unwrap_err();
+    assert!(err.is_connect_failure(), "{}", err);
+}
+
+#[tokio::test]
+async fn test_socket_dir_serves_tcp_servers_locally_too() {
+    let dir = local_socket_dir();
+    let local = transport::unix_address("127.0.0.1:10998").unwrap();
+    assert!(
+        local.starts_with(&format!("unix:{}", dir.display())),
+        "{}",
+        local
+    );
+    assert!(local.ends_with("-10998.sock"), "{}", local);
+    assert_eq!(transport::unix_address("mem:unix-local"), None);
+
+    start_echo("127.0.0.1:10998", Shutdown::new());
+    sleep(Duration::from_millis(50)).await;
+
+    for addr in ["127.0.0.1:10998", local.as_str()].iter() {
+        let response = Client::global()
+            .call(addr, Request::new(1, "{both}"))
+            .await
+            .unwrap();
+        assert_eq!(response.payload, "{both}");
+    }
+
+    // As discovery lists it: both addresses of this server, and others'.
+    let listed = vec![
+        "127.0.0.1:10998".to_string(),
+        local.clone(),
+        "unix:/elsewhere/unix-echo-10997.sock".to_string(),
+        "10.0.0.2:10998".to_string(),
+        "127.0.0.1:10997".to_string(),
+    ];
+    assert_eq!(
+        transport::one_per_server(listed),
+        vec![
+            local,
+            "10.0.0.2:10998".to_string(),
+            "127.0.0.1:10997".to_string()
+        ]
+    );
+}
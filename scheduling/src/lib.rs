@@ -1,5 +1,6 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::ServiceDescription;
+use rpc::{schema, ProcedureId};
 
 pub const SYSTEM_NAME: &str = "scheduling";
 pub const SYSTEM_ADDRESS: &str = "127.0.0.1:10900";
@@ -84,10 +85,7 @@ pub async fn schedule_service(
         bin_name,
         replicas,
     };
-    let request = Request {
-        procedure_id: SCHEDULE_SERVICE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(SCHEDULE_SERVICE_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to schedule service");
@@ -96,10 +94,7 @@ pub async fn schedule_service(
 
 pub async fn list_instances(addr: &str) -> ListInstancesResult {
     let args = ListInstancesArgs { placeholder: 0 };
-    let request = Request {
-        procedure_id: LIST_INSTANCES_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(LIST_INSTANCES_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to list instances");
@@ -108,10 +103,7 @@ pub async fn list_instances(addr: &str) -> ListInstancesResult {
 
 pub async fn scale_service(addr: &str, name: String, replicas: i32) -> ScaleServiceResult {
     let args = ScaleServiceArgs { name, replicas };
-    let request = Request {
-        procedure_id: SCALE_SERVICE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(SCALE_SERVICE_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to scale service");
@@ -120,10 +112,7 @@ pub async fn scale_service(addr: &str, name: String, replicas: i32) -> ScaleServ
 
 pub async fn stop_instance(addr: &str, instance_id: String) -> StopInstanceResult {
     let args = StopInstanceArgs { instance_id };
-    let request = Request {
-        procedure_id: STOP_INSTANCE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(STOP_INSTANCE_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to stop instance");
@@ -132,12 +121,43 @@ pub async fn stop_instance(addr: &str, instance_id: String) -> StopInstanceResul
 
 pub async fn get_service(addr: &str, name: String) -> GetServiceResult {
     let args = GetServiceArgs { name };
-    let request = Request {
-        procedure_id: GET_SERVICE_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(GET_SERVICE_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request)
         .await
         .expect("Failed to get service");
     GetServiceResult::deserialize(&response.payload).expect("Failed to deserialize")
 }
+
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION"))
+        .procedure(
+            SCHEDULE_SERVICE_PROCEDURE,
+            "schedule_service",
+            schema!(ScheduleServiceArgs),
+            schema!(ScheduleServiceResult),
+        )
+        .procedure(
+            LIST_INSTANCES_PROCEDURE,
+            "list_instances",
+            schema!(ListInstancesArgs),
+            schema!(ListInstancesResult),
+        )
+        .procedure(
+            SCALE_SERVICE_PROCEDURE,
+            "scale_service",
+            schema!(ScaleServiceArgs),
+            schema!(ScaleServiceResult),
+        )
+        .procedure(
+            STOP_INSTANCE_PROCEDURE,
+            "stop_instance",
+            schema!(StopInstanceArgs),
+            schema!(StopInstanceResult),
+        )
+        .procedure(
+            GET_SERVICE_PROCEDURE,
+            "get_service",
+            schema!(GetServiceArgs),
+            schema!(GetServiceResult),
+        )
+}
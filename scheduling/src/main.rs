@@ -1,4 +1,5 @@
-use rpc::{server, Request, Response};
+use rpc::reflection::Reflection;
+use rpc::{server, Handlers, Request, Response, Shutdown};
 use scheduling::{
     GetServiceArgs, GetServiceResult, ListInstancesArgs, ListInstancesResult, ScaleServiceArgs,
     ScaleServiceResult, ScheduleServiceArgs, ScheduleServiceResult, StopInstanceArgs,
@@ -9,7 +10,7 @@ use scheduling::{
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
-use std::process::Command;
+use std::process::{Child, Command};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
@@ -26,6 +27,7 @@ struct Instance {
     service_name: String,
     port: u16,
     pid: u32,
+    child: Option<Child>,
     status: String,
 }
 
@@ -81,6 +83,7 @@ impl SchedulerState {
                     service_name: spec.name.clone(),
                     port,
                     pid,
+                    child: Some(child),
                     status: "starting".to_string(),
                 })
             }
@@ -246,9 +249,7 @@ mod handlers {
         state.reconcile(&args.name);
 
         let result = ScheduleServiceResult { success: 1 };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn list_instances(payload: &str, state: &mut SchedulerState) -> Response {
@@ -270,9 +271,7 @@ mod handlers {
         let result = ListInstancesResult {
             instances: entries.join(";"),
         };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn scale_service(payload: &str, state: &mut SchedulerState) -> Response {
@@ -285,9 +284,7 @@ mod handlers {
         state.reconcile(&args.name);
 
         let result = ScaleServiceResult { success: 1 };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn stop_instance(payload: &str, state: &mut SchedulerState) -> Response {
@@ -297,8 +294,21 @@ mod handlers {
         let mut success = 0;
         for instance in state.instances.iter_mut() {
             if instance.id == args.instance_id {
-                // Kill the process
-                libc_kill(instance.pid as i32);
+                // Ask the process to drain, and kill it if it is still
+                // around once its grace period is over. Holding the Child
+                // until then keeps the pid from being reused.
+                let pid = instance.pid;
+                libc_kill(pid as i32, SIGTERM);
+                if let Some(mut child) = instance.child.take() {
+                    tokio::spawn(async move {
+                        sleep(stop_timeout()).await;
+                        if let Ok(None) = child.try_wait() {
+                            println!("Instance pid {} did not exit, killing it", pid);
+                            let _ = child.kill();
+                            let _ = child.wait();
+                        }
+                    });
+                }
                 instance.status = "stopped".to_string();
                 success = 1;
                 println!("Stopped instance {} (pid {})", instance.id, instance.pid);
@@ -307,9 +317,7 @@ mod handlers {
         }
 
         let result = StopInstanceResult { success };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn get_service(payload: &str, state: &mut SchedulerState) -> Response {
@@ -339,20 +347,33 @@ mod handlers {
             instance_count: running.len() as i32,
             instances: instance_strs.join(";"),
         };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
-    // Send SIGKILL via libc
-    fn libc_kill(pid: i32) {
+    const SIGTERM: i32 = 15;
+
+    // On top of the instances' grace period, so a draining instance gets to
+    // finish before it is killed.
+    const STOP_MARGIN_SECS: u64 = 5;
+
+    fn stop_timeout() -> Duration {
+        Duration::from_secs(*rpc::shutdown::GRACE_PERIOD_SECS + STOP_MARGIN_SECS)
+    }
+
+    // Send a signal via libc
+    fn libc_kill(pid: i32, sig: i32) -> i32 {
         unsafe {
             #[cfg(unix)]
             {
                 extern "C" {
                     fn kill(pid: i32, sig: i32) -> i32;
                 }
-                kill(pid, 9); // SIGKILL
+                kill(pid, sig)
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = (pid, sig);
+                -1
             }
         }
     }
@@ -377,9 +398,7 @@ async fn request_handler(
             handlers::stop_instance(&request.payload, &mut state).await
         }
         GET_SERVICE_PROCEDURE => handlers::get_service(&request.payload, &mut state).await,
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
+        _ => Response::unknown_procedure(request.procedure_id),
     }
 }
 
@@ -425,7 +444,8 @@ async fn main() {
     let addr = std::env::var("PORT")
         .map(|p| format!("127.0.0.1:{}", p))
         .unwrap_or_else(|_| SYSTEM_ADDRESS.to_string());
-    discovery::register(SYSTEM_NAME.to_string(), addr.clone());
+    let shutdown = Shutdown::on_signal();
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.clone(), &shutdown);
 
     // Bootstrap the fleet
     {
@@ -442,13 +462,15 @@ async fn main() {
         health_check_loop(health_state).await;
     });
 
-    server::start_server_with_state(
+    server::start_server_with_shutdown(
         &addr,
+        Handlers::standard().with(Reflection::new(scheduling::describe())),
         |request, state| {
             Box::pin(request_handler(request, state))
                 as Pin<Box<dyn Future<Output = Response> + Send>>
         },
         state,
+        shutdown,
     )
     .await
     .expect("Server crashed");
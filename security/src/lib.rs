@@ -1,5 +1,6 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::ServiceDescription;
+use rpc::{schema, ProcedureId};
 
 pub const SYSTEM_NAME: &str = "security";
 pub const SYSTEM_ADDRESS: &str = "127.0.0.1:11100";
@@ -58,40 +59,56 @@ use rpc::{client, Request};
 
 pub async fn create_token(addr: &str, name: String, permissions: String) -> CreateTokenResult {
     let args = CreateTokenArgs { name, permissions };
-    let request = Request {
-        procedure_id: CREATE_TOKEN_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(CREATE_TOKEN_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request).await.expect("Failed to create token");
     CreateTokenResult::deserialize(&response.payload).expect("Failed to deserialize")
 }
 
 pub async fn validate_token(addr: &str, token: String) -> ValidateTokenResult {
     let args = ValidateTokenArgs { token };
-    let request = Request {
-        procedure_id: VALIDATE_TOKEN_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(VALIDATE_TOKEN_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request).await.expect("Failed to validate token");
     ValidateTokenResult::deserialize(&response.payload).expect("Failed to deserialize")
 }
 
 pub async fn revoke_token(addr: &str, token: String) -> RevokeTokenResult {
     let args = RevokeTokenArgs { token };
-    let request = Request {
-        procedure_id: REVOKE_TOKEN_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(REVOKE_TOKEN_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request).await.expect("Failed to revoke token");
     RevokeTokenResult::deserialize(&response.payload).expect("Failed to deserialize")
 }
 
 pub async fn list_tokens(addr: &str) -> ListTokensResult {
     let args = ListTokensArgs { placeholder: 0 };
-    let request = Request {
-        procedure_id: LIST_TOKENS_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(LIST_TOKENS_PROCEDURE, args.serialize());
     let response = client::send_request(addr, request).await.expect("Failed to list tokens");
     ListTokensResult::deserialize(&response.payload).expect("Failed to deserialize")
 }
+
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION"))
+        .procedure(
+            CREATE_TOKEN_PROCEDURE,
+            "create_token",
+            schema!(CreateTokenArgs),
+            schema!(CreateTokenResult),
+        )
+        .procedure(
+            VALIDATE_TOKEN_PROCEDURE,
+            "validate_token",
+            schema!(ValidateTokenArgs),
+            schema!(ValidateTokenResult),
+        )
+        .procedure(
+            REVOKE_TOKEN_PROCEDURE,
+            "revoke_token",
+            schema!(RevokeTokenArgs),
+            schema!(RevokeTokenResult),
+        )
+        .procedure(
+            LIST_TOKENS_PROCEDURE,
+            "list_tokens",
+            schema!(ListTokensArgs),
+            schema!(ListTokensResult),
+        )
+}
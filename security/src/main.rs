@@ -1,4 +1,5 @@
-use rpc::{server, Request, Response};
+use rpc::reflection::Reflection;
+use rpc::{server, Handlers, Request, Response, Shutdown};
 use security::{
     CreateTokenArgs, CreateTokenResult, ListTokensArgs, ListTokensResult, RevokeTokenArgs,
     RevokeTokenResult, ValidateTokenArgs, ValidateTokenResult, CREATE_TOKEN_PROCEDURE,
@@ -71,9 +72,7 @@ mod handlers {
         };
         state.tokens.insert(token.clone(), entry);
         let result = CreateTokenResult { token };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn validate_token(payload: &str, state: &mut SecurityState) -> Response {
@@ -86,9 +85,7 @@ mod handlers {
                     name: entry.name.clone(),
                     permissions: entry.permissions.clone(),
                 };
-                Response {
-                    payload: result.serialize(),
-                }
+                Response::ok(result.serialize())
             }
             None => {
                 let result = ValidateTokenResult {
@@ -96,9 +93,7 @@ mod handlers {
                     name: String::new(),
                     permissions: String::new(),
                 };
-                Response {
-                    payload: result.serialize(),
-                }
+                Response::ok(result.serialize())
             }
         }
     }
@@ -110,9 +105,7 @@ mod handlers {
         let result = RevokeTokenResult {
             success: if removed { 1 } else { 0 },
         };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 
     pub async fn list_tokens(payload: &str, state: &mut SecurityState) -> Response {
@@ -131,9 +124,7 @@ mod handlers {
         let result = ListTokensResult {
             tokens: tokens.join(";"),
         };
-        Response {
-            payload: result.serialize(),
-        }
+        Response::ok(result.serialize())
     }
 }
 
@@ -146,9 +137,7 @@ async fn request_handler(request: Request, shared_state: Arc<Mutex<SecurityState
         }
         REVOKE_TOKEN_PROCEDURE => handlers::revoke_token(&request.payload, &mut state).await,
         LIST_TOKENS_PROCEDURE => handlers::list_tokens(&request.payload, &mut state).await,
-        _ => Response {
-            payload: "Unknown procedure".to_string(),
-        },
+        _ => Response::unknown_procedure(request.procedure_id),
     }
 }
 
@@ -178,17 +167,20 @@ async fn main() {
     let addr = std::env::var("PORT")
         .map(|p| format!("127.0.0.1:{}", p))
         .unwrap_or_else(|_| SYSTEM_ADDRESS.to_string());
-    discovery::register(SYSTEM_NAME.to_string(), addr.clone());
+    let shutdown = Shutdown::on_signal();
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.clone(), &shutdown);
 
     println!("Security service starting on {}", addr);
 
-    server::start_server_with_state(
+    server::start_server_with_shutdown(
         &addr,
+        Handlers::standard().with(Reflection::new(security::describe())),
         |request, state| {
             Box::pin(request_handler(request, state))
                 as Pin<Box<dyn Future<Output = Response> + Send>>
         },
         state,
+        shutdown,
     )
     .await
     .expect("Server crashed");
@@ -167,10 +167,6 @@ impl StorageEngine {
         self.operations_since_snapshot += 1;
     }
 
-    pub fn get(&self, key: &str) -> Option<&String> {
-        self.data.get(key).map(|v| &v.value)
-    }
-
     pub fn get_versioned(&self, key: &str) -> Option<&VersionedValue> {
         self.data.get(key)
     }
@@ -1,8 +1,15 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::ProcedureId;
+mod engine;
+pub mod service;
+
+use normalization::{Deserializable, Serializable};
+use once_cell::sync::Lazy;
+use rpc::reflection::{Schema, ServiceDescription};
+use rpc::{schema, ProcedureId, Procedures};
 
 pub const SYSTEM_NAME: &str = "storage";
-pub const SYSTEM_ADDRESS: &str = "127.0.0.1:10600";
+pub static SYSTEM_ADDRESS: Lazy<String> = Lazy::new(|| {
+    std::env::var("STORAGE_ADDR").unwrap_or_else(|_| "127.0.0.1:10600".to_string())
+});
 
 pub const GET_PROCEDURE: ProcedureId = 1;
 pub const PUT_PROCEDURE: ProcedureId = 2;
@@ -12,6 +19,16 @@ pub const REPLICATE_PUT_PROCEDURE: ProcedureId = 5;
 pub const REPLICATE_DELETE_PROCEDURE: ProcedureId = 6;
 pub const GET_PEERS_PROCEDURE: ProcedureId = 7;
 
+// Replication carries versions, so a repeated one is a no-op. A put or delete
+// retried late could undo a newer write, so those are sent once.
+pub const PROCEDURES: Procedures = Procedures::new(&[
+    GET_PROCEDURE,
+    SCAN_PROCEDURE,
+    REPLICATE_PUT_PROCEDURE,
+    REPLICATE_DELETE_PROCEDURE,
+    GET_PEERS_PROCEDURE,
+]);
+
 #[derive(Debug, Serializable, Deserializable)]
 pub struct GetArgs {
     pub key: String,
@@ -70,39 +87,35 @@ pub struct GetPeersResult {
     pub quorum_r: i32,
 }
 
-// Client helpers for replication
+// Client helpers
 use rpc::{client, Request};
 
-pub async fn replicate_put(addr: &str, key: String, value: String, version: i32) -> String {
+pub async fn put(addr: &str, key: String, value: String) -> Result<(), rpc::Error> {
+    let args = PutArgs { key, value };
+    let request = Request::new(PUT_PROCEDURE, args.serialize());
+    client::send_request(addr, request).await.map(|_| ())
+}
+
+pub async fn replicate_put(
+    addr: &str,
+    key: String,
+    value: String,
+    version: i32,
+) -> Result<(), rpc::Error> {
     let args = ReplicatePutArgs { key, value, version };
-    let request = Request {
-        procedure_id: REPLICATE_PUT_PROCEDURE,
-        payload: args.serialize(),
-    };
-    match client::send_request(addr, request).await {
-        Ok(response) => response.payload,
-        Err(e) => format!("ERROR: {}", e),
-    }
+    let request = Request::new(REPLICATE_PUT_PROCEDURE, args.serialize());
+    client::send_request(addr, request).await.map(|_| ())
 }
 
-pub async fn replicate_delete(addr: &str, key: String, version: i32) -> String {
+pub async fn replicate_delete(addr: &str, key: String, version: i32) -> Result<(), rpc::Error> {
     let args = ReplicateDeleteArgs { key, version };
-    let request = Request {
-        procedure_id: REPLICATE_DELETE_PROCEDURE,
-        payload: args.serialize(),
-    };
-    match client::send_request(addr, request).await {
-        Ok(response) => response.payload,
-        Err(e) => format!("ERROR: {}", e),
-    }
+    let request = Request::new(REPLICATE_DELETE_PROCEDURE, args.serialize());
+    client::send_request(addr, request).await.map(|_| ())
 }
 
 pub async fn get_peers(addr: &str) -> GetPeersResult {
     let args = GetPeersArgs { placeholder: 0 };
-    let request = Request {
-        procedure_id: GET_PEERS_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(GET_PEERS_PROCEDURE, args.serialize());
     match client::send_request(addr, request).await {
         Ok(response) => {
             GetPeersResult::deserialize(&response.payload).unwrap_or(GetPeersResult {
@@ -122,10 +135,7 @@ pub async fn get_peers(addr: &str) -> GetPeersResult {
 // Remote get for quorum reads
 pub async fn remote_get(addr: &str, key: String) -> GetResult {
     let args = GetArgs { key };
-    let request = Request {
-        procedure_id: GET_PROCEDURE,
-        payload: args.serialize(),
-    };
+    let request = Request::new(GET_PROCEDURE, args.serialize());
     match client::send_request(addr, request).await {
         Ok(response) => GetResult::deserialize(&response.payload).unwrap_or(GetResult {
             value: String::new(),
@@ -137,3 +147,24 @@ pub async fn remote_get(addr: &str, key: String) -> GetResult {
         },
     }
 }
+
+pub fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION"))
+        .procedure(GET_PROCEDURE, "get", schema!(GetArgs), schema!(GetResult))
+        .procedure(PUT_PROCEDURE, "put", schema!(PutArgs), Schema::none())
+        .procedure(DELETE_PROCEDURE, "delete", schema!(DeleteArgs), Schema::none())
+        .procedure(SCAN_PROCEDURE, "scan", schema!(ScanArgs), schema!(ScanResult))
+        .procedure(
+            REPLICATE_PUT_PROCEDURE,
+            "replicate_put",
+            schema!(ReplicatePutArgs),
+            Schema::none(),
+        )
+        .procedure(
+            REPLICATE_DELETE_PROCEDURE,
+            "replicate_delete",
+            schema!(ReplicateDeleteArgs),
+            Schema::none(),
+        )
+        .procedure(GET_PEERS_PROCEDURE, "get_peers", schema!(GetPeersArgs), schema!(GetPeersResult))
+}
@@ -0,0 +1,280 @@
+use crate::engine::StorageEngine;
+use rpc::reflection::Reflection;
+use rpc::{server, transport, Handlers, Request, Response, Shutdown};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::{
+    DeleteArgs, GetArgs, GetPeersArgs, GetPeersResult, GetResult, PutArgs, ReplicateDeleteArgs,
+    ReplicatePutArgs, ScanArgs, ScanResult, DELETE_PROCEDURE, GET_PEERS_PROCEDURE, GET_PROCEDURE,
+    PUT_PROCEDURE, REPLICATE_DELETE_PROCEDURE, REPLICATE_PUT_PROCEDURE, SCAN_PROCEDURE,
+    SYSTEM_NAME,
+};
+use std::sync::RwLock;
+use tokio::time::{sleep, Duration};
+
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60);
+
+// The engine sits behind a std RwLock: reads run concurrently, and because
+// its guards can't be held across an await, peer calls always happen after
+// the lock is released.
+struct StorageState {
+    engine: RwLock<StorageEngine>,
+    own_addr: String,
+    quorum_w: i32,
+    quorum_r: i32,
+}
+
+async fn get_peers(own_addr: &str) -> Vec<String> {
+    let own_socket = transport::unix_address(own_addr);
+    let result = match discovery::list_local(SYSTEM_NAME.to_string()).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Failed to list peers: {}", e);
+            return Vec::new();
+        }
+    };
+    let listed = result
+        .addresses
+        .split(';')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    transport::one_per_server(listed)
+        .into_iter()
+        .filter(|s| s != own_addr && Some(s) != own_socket.as_ref())
+        .collect()
+}
+
+mod handlers {
+    use super::*;
+
+    pub async fn get(payload: &str, state: &StorageState) -> Response {
+        let args = GetArgs::deserialize(payload).expect("Failed to deserialize payload");
+
+        let local = state
+            .engine
+            .read()
+            .unwrap()
+            .get_versioned(&args.key)
+            .map(|v| v.value.clone());
+        let local_found = if local.is_some() { 1 } else { 0 };
+        let local_value = local.unwrap_or_default();
+
+        // For quorum reads (R > 1), read from peers
+        if state.quorum_r > 1 {
+            let peers = get_peers(&state.own_addr).await;
+            let needed = (state.quorum_r - 1) as usize; // local counts as 1
+
+            let mut best_value = local_value.clone();
+            let mut best_found = local_found;
+
+            let mut acks = 0;
+            for peer in &peers {
+                if acks >= needed {
+                    break;
+                }
+                let result = crate::remote_get(peer, args.key.clone()).await;
+                acks += 1;
+
+                if result.found == 1 && best_found == 0 {
+                    best_value = result.value.clone();
+                    best_found = 1;
+                }
+            }
+
+            let result = GetResult {
+                value: best_value,
+                found: best_found,
+            };
+            return Response::ok(result.serialize());
+        }
+
+        // Simple local read
+        let result = GetResult {
+            value: local_value,
+            found: local_found,
+        };
+        Response::ok(result.serialize())
+    }
+
+    pub async fn put(payload: &str, state: &StorageState) -> Response {
+        let args = PutArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let version = state
+            .engine
+            .write()
+            .unwrap()
+            .put(args.key.clone(), args.value.clone());
+
+        // Replicate to peers
+        if state.quorum_w > 1 {
+            let peers = get_peers(&state.own_addr).await;
+            let needed = (state.quorum_w - 1) as usize; // local counts as 1
+
+            let mut acks = 0;
+            for peer in &peers {
+                if acks >= needed {
+                    break;
+                }
+                let result = crate::replicate_put(
+                    peer,
+                    args.key.clone(),
+                    args.value.clone(),
+                    version as i32,
+                )
+                .await;
+                if result.is_ok() {
+                    acks += 1;
+                }
+            }
+            println!(
+                "PUT {} replicated to {}/{} peers (W={})",
+                args.key,
+                acks,
+                peers.len(),
+                state.quorum_w
+            );
+        }
+
+        Response::ok("OK")
+    }
+
+    pub async fn delete(payload: &str, state: &StorageState) -> Response {
+        let args = DeleteArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let version = state.engine.write().unwrap().delete(&args.key);
+
+        // Replicate delete to peers
+        if state.quorum_w > 1 {
+            let peers = get_peers(&state.own_addr).await;
+            let needed = (state.quorum_w - 1) as usize;
+
+            let mut acks = 0;
+            for peer in &peers {
+                if acks >= needed {
+                    break;
+                }
+                let result =
+                    crate::replicate_delete(peer, args.key.clone(), version as i32).await;
+                if result.is_ok() {
+                    acks += 1;
+                }
+            }
+        }
+
+        Response::ok("OK")
+    }
+
+    pub async fn scan(payload: &str, state: &StorageState) -> Response {
+        let args = ScanArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let entries = state.engine.read().unwrap().scan(&args.prefix, args.limit);
+        let formatted: Vec<String> = entries.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        let result = ScanResult {
+            entries: formatted.join(";"),
+        };
+        Response::ok(result.serialize())
+    }
+
+    pub async fn replicate_put(payload: &str, state: &StorageState) -> Response {
+        let args =
+            ReplicatePutArgs::deserialize(payload).expect("Failed to deserialize payload");
+        state
+            .engine
+            .write()
+            .unwrap()
+            .put_versioned(args.key, args.value, args.version as u64);
+        Response::ok("OK")
+    }
+
+    pub async fn replicate_delete(payload: &str, state: &StorageState) -> Response {
+        let args =
+            ReplicateDeleteArgs::deserialize(payload).expect("Failed to deserialize payload");
+        state
+            .engine
+            .write()
+            .unwrap()
+            .delete_versioned(&args.key, args.version as u64);
+        Response::ok("OK")
+    }
+
+    pub async fn get_peers_handler(payload: &str, state: &StorageState) -> Response {
+        let _args =
+            GetPeersArgs::deserialize(payload).expect("Failed to deserialize payload");
+        let peers = get_peers(&state.own_addr).await;
+        let result = GetPeersResult {
+            peer_count: peers.len() as i32,
+            quorum_w: state.quorum_w,
+            quorum_r: state.quorum_r,
+        };
+        Response::ok(result.serialize())
+    }
+}
+
+async fn request_handler(request: Request, state: Arc<StorageState>) -> Response {
+    match request.procedure_id {
+        GET_PROCEDURE => handlers::get(&request.payload, &state).await,
+        PUT_PROCEDURE => handlers::put(&request.payload, &state).await,
+        DELETE_PROCEDURE => handlers::delete(&request.payload, &state).await,
+        SCAN_PROCEDURE => handlers::scan(&request.payload, &state).await,
+        REPLICATE_PUT_PROCEDURE => handlers::replicate_put(&request.payload, &state).await,
+        REPLICATE_DELETE_PROCEDURE => {
+            handlers::replicate_delete(&request.payload, &state).await
+        }
+        GET_PEERS_PROCEDURE => handlers::get_peers_handler(&request.payload, &state).await,
+        _ => Response::unknown_procedure(request.procedure_id),
+    }
+}
+
+// Serves storage on `addr` until `shutdown` is triggered, registered with
+// discovery for as long as it does.
+pub async fn serve(addr: &str, shutdown: Shutdown) {
+    // Per-instance data directory based on port
+    let port = addr.split(':').last().unwrap_or("10600");
+    let data_dir = format!("storage_data_{}", port);
+
+    let quorum_w: i32 = std::env::var("QUORUM_W")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2);
+
+    let quorum_r: i32 = std::env::var("QUORUM_R")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2);
+
+    let state = Arc::new(StorageState {
+        engine: RwLock::new(StorageEngine::new(&data_dir)),
+        own_addr: addr.to_string(),
+        quorum_w,
+        quorum_r,
+    });
+
+    // Background compaction task
+    let compaction_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        loop {
+            sleep(COMPACTION_INTERVAL).await;
+            println!("Running compaction check");
+            compaction_state.engine.write().unwrap().compact();
+        }
+    });
+
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.to_string(), &shutdown);
+
+    println!(
+        "Storage service starting on {} (data_dir={}, W={}, R={})",
+        addr, data_dir, quorum_w, quorum_r
+    );
+
+    server::start_server_with_shutdown(
+        addr,
+        Handlers::standard().with(Reflection::new(crate::describe())),
+        |request, state| {
+            Box::pin(request_handler(request, state))
+                as Pin<Box<dyn Future<Output = Response> + Send>>
+        },
+        state,
+        shutdown,
+    )
+    .await
+    .expect("Server crashed");
+}
@@ -0,0 +1,49 @@
+// The real discovery, storage and caching services in one process, on mem:
+// addresses, reached through their client helpers.
+use rpc::Shutdown;
+use tokio::time::{sleep, Duration, Instant};
+
+async fn listed(name: &str, address: &str) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if let Ok(result) = discovery::list(name.to_string()).await {
+            if result.addresses.split(';').any(|a| a == address) {
+                return true;
+            }
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn test_services_run_on_memory_addresses() {
+    // Read once, when each address is first used.
+    std::env::set_var("DISCOVERY_ADDR", "mem:discovery");
+    std::env::set_var("STORAGE_ADDR", "mem:storage");
+    std::env::set_var("CACHING_ADDR", "mem:caching");
+    std::env::set_var("QUORUM_W", "1");
+    std::env::set_var("QUORUM_R", "1");
+
+    tokio::spawn(discovery::service::serve(&discovery::SYSTEM_ADDRESS, Shutdown::new()));
+    sleep(Duration::from_millis(50)).await;
+    tokio::spawn(storage::service::serve(&storage::SYSTEM_ADDRESS, Shutdown::new()));
+    tokio::spawn(caching::service::serve(&caching::SYSTEM_ADDRESS, Shutdown::new()));
+
+    discovery::register("memory-test".to_string(), "mem:memory-test".to_string());
+    assert!(listed("memory-test", "mem:memory-test").await);
+    assert!(listed(storage::SYSTEM_NAME, "mem:storage").await);
+    assert!(listed(caching::SYSTEM_NAME, "mem:caching").await);
+
+    storage::put(&storage::SYSTEM_ADDRESS, "greeting".to_string(), "hello".to_string())
+        .await
+        .unwrap();
+    let stored = storage::remote_get(&storage::SYSTEM_ADDRESS, "greeting".to_string()).await;
+    assert_eq!((stored.found, stored.value.as_str()), (1, "hello"));
+
+    caching::set(&caching::SYSTEM_ADDRESS, "greeting".to_string(), "hi".to_string(), 60)
+        .await
+        .unwrap();
+    let cached = caching::get(&caching::SYSTEM_ADDRESS, "greeting".to_string()).await;
+    assert_eq!((cached.hit, cached.value.as_str()), (1, "hi"));
+}
@@ -1,5 +1,6 @@
-use normalization::{Deserializable, NormalizationError, Serializable};
-use rpc::{server, Response, ProcedureId};
+use normalization::{Deserializable, Serializable};
+use rpc::reflection::{Reflection, ServiceDescription};
+use rpc::{schema, server, transport, Handlers, Response, ProcedureId, Shutdown};
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -28,6 +29,15 @@ struct StatsResult {
     lag_bytes: i32,
 }
 
+fn describe() -> ServiceDescription {
+    ServiceDescription::new(SYSTEM_NAME, env!("CARGO_PKG_VERSION")).procedure(
+        STATS_PROCEDURE,
+        "stats",
+        schema!(StatsArgs),
+        schema!(StatsResult),
+    )
+}
+
 enum WalEntry {
     Put { key: String, value: String, version: u64 },
     Delete { key: String, version: u64 },
@@ -171,7 +181,8 @@ async fn main() {
         lag_bytes: 0,
     }));
 
-    discovery::register(SYSTEM_NAME.to_string(), addr.clone());
+    let shutdown = Shutdown::on_signal();
+    discovery::register_until(SYSTEM_NAME.to_string(), addr.clone(), &shutdown);
     println!("Tailer service starting on {}", addr);
 
     // Tailing loop
@@ -184,36 +195,42 @@ async fn main() {
         loop {
             // Refresh peers periodically
             if last_peer_refresh.elapsed() >= PEER_REFRESH_INTERVAL {
-                // Get local storage instances
+                // The peers found last time are kept if discovery can't be reached
                 let local = discovery::list_local("storage".to_string()).await;
-                let local_addrs: Vec<String> = local.addresses
-                    .split(';')
-                    .filter(|s| !s.is_empty())
-                    .map(|s| s.to_string())
-                    .collect();
-
-                // Get all storage instances
                 let all = discovery::list("storage".to_string()).await;
-                let all_addrs: Vec<String> = all.addresses
-                    .split(';')
-                    .filter(|s| !s.is_empty())
-                    .map(|s| s.to_string())
-                    .collect();
+                match (local, all) {
+                    (Ok(local), Ok(all)) => {
+                        // Get local storage instances
+                        let local_addrs: Vec<String> = local.addresses
+                            .split(';')
+                            .filter(|s| !s.is_empty() && !transport::is_unix(s))
+                            .map(|s| s.to_string())
+                            .collect();
 
-                // Remote = all minus local
-                remote_peers = all_addrs
-                    .into_iter()
-                    .filter(|a| !local_addrs.contains(a))
-                    .collect();
+                        // Get all storage instances
+                        let all_addrs: Vec<String> = all.addresses
+                            .split(';')
+                            .filter(|s| !s.is_empty() && !transport::is_unix(s))
+                            .map(|s| s.to_string())
+                            .collect();
 
-                // Create tailers for any new local instances
-                for addr in &local_addrs {
-                    let port = addr.split(':').last().unwrap_or("10600");
-                    let data_dir = format!("storage_data_{}", port);
-                    if !tailers.iter().any(|t| t.data_dir == data_dir) {
-                        println!("[tailer] Watching {}", data_dir);
-                        tailers.push(WalTailer::new(data_dir));
+                        // Remote = all minus local
+                        remote_peers = all_addrs
+                            .into_iter()
+                            .filter(|a| !local_addrs.contains(a))
+                            .collect();
+
+                        // Create tailers for any new local instances
+                        for addr in &local_addrs {
+                            let port = addr.split(':').last().unwrap_or("10600");
+                            let data_dir = format!("storage_data_{}", port);
+                            if !tailers.iter().any(|t| t.data_dir == data_dir) {
+                                println!("[tailer] Watching {}", data_dir);
+                                tailers.push(WalTailer::new(data_dir));
+                            }
+                        }
                     }
+                    (Err(e), _) | (_, Err(e)) => eprintln!("[tailer] Failed to list storage peers: {}", e),
                 }
 
                 last_peer_refresh = Instant::now();
@@ -239,7 +256,7 @@ async fn main() {
                                 storage::replicate_delete(peer, key.clone(), *version as i32).await
                             }
                         };
-                        if result.starts_with("ERROR") {
+                        if result.is_err() {
                             total_errors += 1;
                         } else {
                             total_replicated += 1;
@@ -266,8 +283,9 @@ async fn main() {
     });
 
     // RPC server for stats
-    server::start_server_with_state(
+    server::start_server_with_shutdown(
         &addr,
+        Handlers::standard().with(Reflection::new(describe())),
         |request, state| {
             Box::pin(async move {
                 let s = state.lock().await;
@@ -279,17 +297,14 @@ async fn main() {
                             errors: s.errors,
                             lag_bytes: s.lag_bytes,
                         };
-                        Response {
-                            payload: result.serialize(),
-                        }
+                        Response::ok(result.serialize())
                     }
-                    _ => Response {
-                        payload: "Unknown procedure".to_string(),
-                    },
+                    _ => Response::unknown_procedure(request.procedure_id),
                 }
             }) as Pin<Box<dyn Future<Output = Response> + Send>>
         },
         state,
+        shutdown,
     )
     .await
     .expect("Server crashed");
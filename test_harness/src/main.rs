@@ -24,10 +24,7 @@ async fn wait_for_service(addr: &str, timeout_secs: u64) -> bool {
 }
 
 async fn send(addr: &str, procedure_id: i32, payload: String) -> String {
-    let request = Request {
-        procedure_id,
-        payload,
-    };
+    let request = Request::new(procedure_id, payload);
     match client::send_request(addr, request).await {
         Ok(response) => response.payload,
         Err(e) => format!("ERROR: {}", e),